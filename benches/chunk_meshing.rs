@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use bevy::{math::IVec3, tasks::block_on};
+use criterion::{Criterion, criterion_group, criterion_main, Throughput};
+use qgame::{Config, generate_chunk_mesh, Headless, run_headless};
+
+/// Along x like the headless app spawns them, the surface row has terrain with some caves underneath at default settings
+const BENCH_CHUNKS: i32 = 8;
+
+fn bench_chunks() -> impl Iterator<Item=IVec3> {
+    (0..BENCH_CHUNKS).flat_map(|x| [IVec3::new(x, 0, 0), IVec3::new(x, -1, 0)])
+}
+
+fn cpu_meshing(c: &mut Criterion) {
+    let config = Config::default();
+    let mesh_all = || bench_chunks().map(|position| generate_chunk_mesh(position, &config).triangles()).sum::<usize>();
+    let triangles = mesh_all();
+
+    let mut group = c.benchmark_group("cpu_chunks");
+    group.throughput(Throughput::Elements(bench_chunks().count() as u64));
+    group.bench_function("generate_chunk_mesh", |b| b.iter(mesh_all));
+    group.finish();
+
+    let mut group = c.benchmark_group("cpu_triangles");
+    group.throughput(Throughput::Elements(triangles as u64));
+    group.bench_function("generate_chunk_mesh", |b| b.iter(mesh_all));
+    group.finish();
+}
+
+/// Runners without a GPU only get the CPU numbers
+fn has_gpu() -> bool {
+    let instance = wgpu::Instance::default();
+    block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).is_some()
+}
+
+/// Each sample starts a whole headless app, only the frame that meshes is timed
+fn gpu_meshing(c: &mut Criterion) {
+    if !has_gpu() {
+        eprintln!("No GPU adapter, skipping GPU chunk meshing");
+        return;
+    }
+    let chunks = BENCH_CHUNKS as usize;
+    let triangles = run_headless(Headless::new(chunks)).expect("Headless meshing failed").triangles;
+
+    let mesh_all = |iters: u64| (0..iters)
+        .map(|_| run_headless(Headless::new(chunks)).expect("Headless meshing failed").meshing)
+        .sum::<Duration>();
+
+    let mut group = c.benchmark_group("gpu_chunks");
+    group.sample_size(10).throughput(Throughput::Elements(chunks as u64));
+    group.bench_function("headless", |b| b.iter_custom(mesh_all));
+    group.finish();
+
+    let mut group = c.benchmark_group("gpu_triangles");
+    group.sample_size(10).throughput(Throughput::Elements(triangles as u64));
+    group.bench_function("headless", |b| b.iter_custom(mesh_all));
+    group.finish();
+}
+
+criterion_group!(benches, cpu_meshing, gpu_meshing);
+criterion_main!(benches);
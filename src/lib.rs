@@ -0,0 +1,3 @@
+pub use qgame::*;
+
+mod qgame;
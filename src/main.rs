@@ -1,40 +1,124 @@
 extern crate core;
 
 use std::{
-    f32::consts::TAU,
+    f32::consts::{FRAC_PI_2, FRAC_PI_4},
     fmt::Write,
+    time::Duration,
 };
 
 use bevy::{
+    audio::Volume,
     diagnostic::DiagnosticsStore,
+    diagnostic::EntityCountDiagnosticsPlugin,
     diagnostic::FrameTimeDiagnosticsPlugin,
+    input::InputSystem,
+    math::Vec2Swizzles,
     prelude::*,
-    prelude::shape::Cube,
-    render::{
-        mesh::{Indices, VertexAttributeValues},
-        render_resource::*,
-    },
+    prelude::shape::{Capsule, Cube},
 };
 use bevy_rapier3d::prelude::*;
 
 use qgame::*;
 
-mod qgame;
-
 #[derive(Component)]
 struct TopRightText;
 
 #[derive(Component)]
 struct PlayerHudText;
 
+/// Stats for chasing down performance and physics problems, toggled with its own key
+#[derive(Component)]
+struct DebugOverlayText;
+
+#[derive(Component)]
+struct InteractPromptText;
+
+/// Sections are the mag, the reserve, then the reload indicator
+#[derive(Component)]
+struct AmmoCounterText;
+
+/// One of the four lines, pointing away from the center
+#[derive(Component)]
+struct CrosshairLine(Vec2);
+
+#[derive(Component)]
+struct CrosshairDot;
+
+const DAMAGE_NUMBER_LIFETIME: f32 = 0.8;
+/// Meters per second damage numbers float up
+const DAMAGE_NUMBER_RISE_SPEED: f32 = 1.5;
+const HIT_MARKER_DURATION: f32 = 0.15;
+/// Stacked under the surface chunk, enough to reach the bottom of the default cave depths
+const UNDERGROUND_CHUNKS: i32 = 3;
+
+/// Floats up from where a hit landed while fading out, then despawns
+#[derive(Component)]
+struct DamageNumber {
+    position: Vec3,
+    age: f32,
+}
+
+/// Hits on one target being added up into a single damage number, see [`DamageNumberMode::Aggregated`]
+struct DamageTally {
+    target: Entity,
+    number: Entity,
+    amount: u32,
+    /// Seconds since the last hit added to it
+    since_hit: f32,
+}
+
+/// Flashes over the crosshair on hits, its lines are children
+#[derive(Component)]
+struct HitMarker {
+    age: f32,
+    color: Color,
+}
+
+#[derive(Component)]
+struct HitMarkerLine;
+
 #[derive(Clone, Hash, Debug, PartialEq, Eq, SystemSet)]
 pub enum PlayerSet {
     Logic,
+    /// Our own movement simulated ahead of the server when playing as a client
+    Predict,
     Render,
 }
 
 fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.peek().is_some_and(|arg| arg == "dump-config") {
+        match dump_config(args.skip(1)) {
+            Ok(toml) => print!("{}", toml),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+    if args.peek().is_some_and(|arg| arg == "--headless") {
+        let result = match Headless::from_args(args.skip(1)) {
+            Ok(headless) => run_headless(headless).map(|_| ()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let net = match Net::from_args(args) {
+        Ok(net) => net,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
     App::new()
+        .insert_resource(net)
         .insert_resource(ClearColor(Color::BLACK))
         .insert_resource(AmbientLight {
             color: Color::WHITE,
@@ -45,19 +129,64 @@ fn main() {
         })
         .add_plugins((
             DefaultPlugins.set(AssetPlugin::default()),
-            RapierPhysicsPlugin::<NoUserData>::default(),
+            RapierPhysicsPlugin::<NoUserData>::default().in_fixed_schedule(),
             VoxelsPlugin,
             FrameTimeDiagnosticsPlugin::default(),
+            EntityCountDiagnosticsPlugin,
             InventoryPlugin,
+            SoundPlugin,
+            MenuPlugin,
+            SpectatePlugin,
+            ImpactPlugin,
+            MinimapPlugin,
+            NameplatePlugin,
+            ScreenshotPlugin,
+            ConsolePlugin,
         ))
         .register_asset_loader(ConfigAssetLoader)
         .init_asset::<Config>()
-        .add_systems(Startup, (setup_sys, spawn_ui_sys, spawn_voxel_sys, spawn_player_sys))
-        .add_systems(PreUpdate, player_input_system)
+        .add_event::<DamageEvent>()
+        .add_event::<DeathEvent>()
+        .add_event::<HitEvent>()
+        .init_resource::<RespawnWave>()
+        .init_resource::<SpawnRotation>()
+        .init_resource::<GameRng>()
+        .init_resource::<KeyBindings>()
+        .init_resource::<InputDevice>()
+        .init_resource::<WheelInput>()
+        .add_systems(Startup, (load_config_sys, setup_sys, spawn_ui_sys))
+        // Input is read every frame and gameplay plus physics step at the fixed tick rate, so they behave the same
+        // at any frame rate. Everything drawn, the HUD, config and chunk meshing stay per frame
+        .configure_sets(FixedUpdate, PlayerSet::Logic.before(PhysicsSet::SyncBackend))
+        // Clients leave the simulation to the server, they only send input and apply its snapshots
+        .configure_sets(FixedUpdate, PlayerSet::Logic.run_if(not(is_client)))
+        .configure_sets(FixedUpdate, PlayerSet::Predict.run_if(is_client).before(PhysicsSet::SyncBackend))
+        // Nothing is simulated from menus, so timers and physics pick up exactly where they left off
+        .configure_sets(FixedUpdate, (PlayerSet::Logic, PlayerSet::Predict).run_if(in_state(GameState::Playing)))
+        .add_systems(PreUpdate, (
+            seed_game_rng_sys,
+            wheel_input_sys.in_set(InputSystem),
+            (input_device_sys, player_input_system.run_if(in_state(GameState::Playing)).run_if(not_spectating)).chain(),
+            server_receive_sys.run_if(is_server).run_if(config_loaded).after(player_input_system),
+            (client_connect_sys, client_receive_sys.run_if(config_loaded)).chain().run_if(is_client),
+        ))
+        .add_systems(FixedUpdate, (
+            server_apply_input_sys.run_if(is_server).before(PlayerSet::Logic),
+            (player_look_sys, player_move_sys, noclip_collider_sys, drop_item_sys, modify_equip_state_sys, modify_item_sys, (spread_sys, hitscan_sys, launch_projectile_sys, eject_shell_sys, init_shell_sys, shell_casing_sys, projectile_sys).chain(), bot_seek_sys, damage_sys, death_sys, bot_death_sys, respawn_timer_sys, respawn_sys, recoil_sys, aim_down_sights_sys, item_pickup_sys, item_interact_sys).chain().in_set(PlayerSet::Logic),
+            (client_send_sys, player_look_sys, player_move_sys, noclip_collider_sys).chain().in_set(PlayerSet::Predict),
+            consume_latched_input_sys.after(PlayerSet::Logic).after(PlayerSet::Predict),
+            record_tick_position_sys.after(PhysicsSet::Writeback),
+            server_send_sys.run_if(is_server).after(PhysicsSet::Writeback),
+            record_prediction_sys.run_if(is_client).after(PhysicsSet::Writeback),
+        ))
         .add_systems(Update, (
-            (cursor_grab_sys, update_fps_text_sys),
-            (player_look_sys, player_move_sys, modify_equip_state_sys, modify_item_sys, item_pickup_sys).chain().in_set(PlayerSet::Logic),
-            (item_pickup_animate_sys, render_player_camera_sys, render_inventory_sys, update_hud_system).chain().in_set(PlayerSet::Render),
+            spawn_player_sys.run_if(config_loaded).run_if(not(is_client)).run_if(not(any_with_component::<LocalPlayer>())),
+            spawn_bots_sys.run_if(config_loaded).run_if(not(is_client)),
+            spawn_voxel_sys.run_if(config_loaded).run_if(not(any_with_component::<Map>())),
+            remote_player_model_sys,
+            interpolate_remote_players_sys.run_if(is_client),
+            (cursor_grab_sys, update_fps_text_sys, reload_config_sys, apply_tick_rate_sys, apply_bindings_sys, import_export_bindings_sys, toggle_debug_overlay_sys),
+            (item_pickup_animate_sys, render_player_camera_sys, render_fog_sys, view_model_motion_sys, render_inventory_sys, render_ammo_display_sys, update_hud_system, update_ammo_counter_sys, update_interact_prompt_sys, update_crosshair_sys, spawn_hit_feedback_sys, update_hit_feedback_sys, update_debug_overlay_sys).chain().in_set(PlayerSet::Render),
         ))
         .run();
 }
@@ -68,11 +197,6 @@ fn setup_sys(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
-    // println!("{}", toml::to_string(&Config::default()).unwrap());
-
-    let config: Handle<Config> = asset_server.load("default.config.ron");
-    commands.insert_resource(ConfigState { handle: config });
-
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
             illuminance: 2000.0,
@@ -101,24 +225,22 @@ fn setup_sys(
         ));
     }
 
-    commands.spawn(
-        (
-            Transform::from_xyz(8.0, 16.0, 8.0),
-            GlobalTransform::default(),
-            Collider::ball(0.5),
-            Sensor,
-            VisibilityBundle::default(),
-            ItemPickup { item_name: ItemName::from("rifle") },
-        )
-    ).with_children(|parent| {
-        parent.spawn((
-            SceneBundle {
-                scene: asset_server.load("models/rifle.glb#Scene0"),
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(Mesh::from(Capsule { radius: 0.5, depth: 1.0, ..default() })),
+            material: materials.add(StandardMaterial {
+                base_color: Color::ORANGE,
                 ..default()
-            },
-            ItemPickupVisual::default(),
-        ));
-    });
+            }),
+            transform: Transform::from_xyz(8.0, 18.0, 0.0),
+            ..default()
+        },
+        Collider::capsule_y(0.5, 0.5),
+        Health::new(100),
+    ));
+
+    let pickup_ent = spawn_item_pickup(&mut commands, &asset_server, ItemName::from("rifle"), 1, Transform::from_xyz(8.0, 16.0, 8.0));
+    commands.entity(pickup_ent).insert((Collider::ball(0.5), Sensor));
 }
 
 fn spawn_ui_sys(mut commands: Commands) {
@@ -167,65 +289,228 @@ fn spawn_ui_sys(mut commands: Commands) {
         },
         PlayerHudText
     ));
+
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                top: Val::Px(5.0),
+                right: Val::Px(5.0),
+                ..default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle { font_size: 16.0, color: Color::WHITE, ..default() },
+                    },
+                ],
+                alignment: TextAlignment::Right,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        DebugOverlayText
+    ));
+
+    commands.spawn((
+        TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(5.0),
+                right: Val::Px(5.0),
+                ..default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle { font_size: 32.0, color: Color::WHITE, ..default() },
+                    },
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle { font_size: 20.0, color: Color::ANTIQUE_WHITE, ..default() },
+                    },
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle { font_size: 14.0, color: Color::rgba(1.0, 1.0, 1.0, 0.6), ..default() },
+                    },
+                ],
+                alignment: TextAlignment::Right,
+                ..default()
+            },
+            visibility: Visibility::Hidden,
+            ..default()
+        },
+        AmmoCounterText
+    ));
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    }).with_children(|parent| {
+        parent.spawn((
+            TextBundle {
+                style: Style {
+                    margin: UiRect::top(Val::Px(64.0)),
+                    ..default()
+                },
+                text: Text {
+                    sections: vec![
+                        TextSection {
+                            value: "".to_string(),
+                            style: TextStyle { font_size: 16.0, color: Color::WHITE, ..default() },
+                        },
+                    ],
+                    ..default()
+                },
+                ..default()
+            },
+            InteractPromptText
+        ));
+    });
+
+    commands.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        ..default()
+    }).with_children(|parent| {
+        // Zero sized anchor at the center of the screen, everything else is placed relative to it
+        parent.spawn(NodeBundle::default()).with_children(|parent| {
+            let style = Style { position_type: PositionType::Absolute, ..default() };
+            for dir in [Vec2::X, Vec2::NEG_X, Vec2::Y, Vec2::NEG_Y] {
+                parent.spawn((NodeBundle { style: style.clone(), ..default() }, Outline::default(), CrosshairLine(dir)));
+            }
+            parent.spawn((NodeBundle { style, ..default() }, Outline::default(), CrosshairDot));
+        });
+
+        // Diagonal lines making an X just outside the crosshair, starting past the duration so nothing shows until the first hit
+        parent.spawn((NodeBundle::default(), HitMarker { age: HIT_MARKER_DURATION, color: Color::NONE })).with_children(|parent| {
+            for (i, dir) in [Vec2::new(1.0, 1.0), Vec2::new(-1.0, 1.0), Vec2::new(-1.0, -1.0), Vec2::new(1.0, -1.0)].into_iter().enumerate() {
+                let mut style = Style { position_type: PositionType::Absolute, ..default() };
+                place_node(&mut style, dir.normalize() * 12.0, Vec2::new(8.0, 2.0));
+                parent.spawn((
+                    NodeBundle {
+                        style,
+                        background_color: Color::NONE.into(),
+                        transform: Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_4 + FRAC_PI_2 * i as f32)),
+                        ..default()
+                    },
+                    HitMarkerLine,
+                ));
+            }
+        });
+    });
 }
 
+/// Waits on the config for the map bounds, chunks outside of them are never spawned
 fn spawn_voxel_sys(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
 ) {
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.set_indices(Some(Indices::U32(Vec::with_capacity(4096))));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, VertexAttributeValues::Float32x3(Vec::with_capacity(4096)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::Float32x3(Vec::with_capacity(4096)));
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(Vec::with_capacity(4096)));
-    let mesh_handle = meshes.add(mesh);
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let bounds = &config.map_bounds;
     let ground_mat_handle = materials.add(StandardMaterial {
         base_color: Color::DARK_GREEN,
         ..default()
     });
     commands.spawn(Map::default());
     commands.spawn((
-        Chunk::new(IVec3::ZERO),
-        PbrBundle {
-            mesh: mesh_handle.clone(),
-            material: ground_mat_handle.clone(),
-            ..default()
-        },
+        TransformBundle::from(Transform::from_xyz(0.0, bounds.floor(), 0.0)),
+        Collider::halfspace(Vec3::Y).unwrap(),
+        Bedrock,
     ));
+    for y in -UNDERGROUND_CHUNKS..=0 {
+        let chunk = Chunk::new(IVec3::new(0, y, 0));
+        if !bounds.contains(chunk.position) { continue; }
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(new_chunk_mesh()),
+                material: ground_mat_handle.clone(),
+                transform: Transform::from_translation(chunk.origin()),
+                ..default()
+            },
+            chunk,
+        ));
+    }
 }
 
-fn spawn_player_sys(mut commands: Commands) {
-    commands.spawn((
-        Collider::capsule(Vec3::Y * 0.5, Vec3::Y * 1.5, 0.5),
-        Velocity::zero(),
-        RigidBody::Dynamic,
-        Sleeping::disabled(),
-        LockedAxes::ROTATION_LOCKED,
-        AdditionalMassProperties::Mass(1.0),
-        ReadMassProperties::default(),
-        GravityScale(0.0),
-        Ccd { enabled: true },
-        TransformBundle::from(Transform::from_xyz(4.0, 18.0, 4.0)),
-        LogicalPlayer(0),
-        PlayerInput {
-            pitch: -TAU / 12.0,
-            yaw: TAU * 5.0 / 8.0,
-            ..default()
-        },
-        PlayerController {
-            ..default()
-        },
-        Inventory::default(),
-    ));
+/// Tick rate also sets how far the clock may jump in one frame, which caps how many ticks run to catch up
+fn apply_tick_rate_sys(
+    mut config_events: EventReader<AssetEvent<Config>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut fixed_time: ResMut<Time<Fixed>>,
+    mut virtual_time: ResMut<Time<Virtual>>,
+    mut rapier_config: ResMut<RapierConfiguration>,
+) {
+    let config_changed = config_events.read().any(|event|
+        event.is_loaded_with_dependencies(&config_state.handle) || event.is_modified(&config_state.handle));
+    if !config_changed { return; }
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    let tick = 1.0 / config.tick_rate;
+    fixed_time.set_timestep_seconds(tick as f64);
+    virtual_time.set_max_delta(Duration::from_secs_f32(tick * config.max_catchup_ticks as f32));
+    rapier_config.timestep_mode = TimestepMode::Fixed { dt: tick, substeps: 1 };
+}
+
+/// Most config is read every frame, this pushes what was only applied at spawn onto players when the file is edited.
+/// A config that fails to parse is never swapped in, so the last one that did stays in use
+fn reload_config_sys(
+    mut config_events: EventReader<AssetEvent<Config>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut player_query: Query<(&mut Inventory, &mut Health), With<LogicalPlayer>>,
+) {
+    if !config_events.read().any(|event| event.is_modified(&config_state.handle)) { return; }
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    for (mut inv, mut health) in player_query.iter_mut() {
+        inv.resize(config.inventory_slots);
+        health.max = config.max_health;
+        health.current = health.current.min(health.max);
+    }
+}
 
-    commands.spawn((Camera3dBundle::default(), RenderPlayer(0)));
+fn spawn_player_sys(
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut spawn_picker: SpawnPicker,
+) {
+    let config = config.get(&config_state.handle).unwrap();
+    // Hosting or playing alone we are always the first player, clients get theirs from the server
+    let player_ent = spawn_player(&mut commands, config, spawn_picker.pick(), 0);
+    commands.entity(player_ent).insert(LocalPlayer);
+    commands.spawn(render_player_camera(config, 0));
 }
 
 fn update_fps_text_sys(
     diagnostics: Res<DiagnosticsStore>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    profiler: Res<MeshingProfiler>,
     mut query: Query<&mut Text, With<TopRightText>>,
 ) {
+    let profile = config.get(&config_state.handle).is_some_and(|config| config.profile_chunk_meshing);
     for mut text in query.iter_mut() {
         let mut fps = 0.0;
         if let Some(fps_diagnostic) = diagnostics.get(FrameTimeDiagnosticsPlugin::FPS) {
@@ -244,32 +529,381 @@ fn update_fps_text_sys(
         let text = &mut text.sections[0].value;
         text.clear();
         write!(text, "{:.1} fps, {:.3} ms/frame", fps, frame_time).unwrap();
+        if profile {
+            text.push('\n');
+            profiler.write_summary(text).unwrap();
+        }
+    }
+}
+
+fn toggle_debug_overlay_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    input: BoundInput,
+    mut overlay_query: Query<&mut Visibility, With<DebugOverlayText>>,
+) {
+    let debug = config.get(&config_state.handle).is_some_and(|config| config.debug);
+    let toggled = input.just_pressed(input.bindings.debug_overlay);
+    for mut visibility in overlay_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden if debug && toggled => Visibility::Inherited,
+            _ if !debug || toggled => Visibility::Hidden,
+            visibility => visibility,
+        };
+    }
+}
+
+fn update_debug_overlay_sys(
+    diagnostics: Res<DiagnosticsStore>,
+    chunk_query: Query<(&Chunk, Option<&ChunkMeshStats>)>,
+    player_query: Query<(&Transform, &Velocity, &PlayerController), With<LocalPlayer>>,
+    mesh_query: Query<&ViewVisibility, With<Handle<Mesh>>>,
+    mut text_query: Query<(&mut Text, &Visibility), With<DebugOverlayText>>,
+) {
+    for (mut text, visibility) in text_query.iter_mut() {
+        if visibility == Visibility::Hidden { continue; }
+        let text = &mut text.sections[0].value;
+        text.clear();
+
+        let entities = diagnostics.get(EntityCountDiagnosticsPlugin::ENTITY_COUNT).and_then(|count| count.value()).unwrap_or(0.0);
+        // Each visible mesh is roughly one draw call, bevy does not report the real number
+        let drawn = mesh_query.iter().filter(|visibility| visibility.get()).count();
+        write!(text, "{} entities, {} meshes drawn", entities, drawn).unwrap();
+        write!(text, "\n{} chunks loaded", chunk_query.iter().len()).unwrap();
+
+        for (transform, vel, controller) in player_query.iter() {
+            let chunk_position = Chunk::position_of(transform.translation);
+            let triangles = chunk_query.iter()
+                .find(|(chunk, _)| chunk.position == chunk_position)
+                .and_then(|(_, stats)| stats)
+                .map_or(0, |stats| stats.triangles);
+            write!(text, "\nChunk {} with {} triangles", chunk_position, triangles).unwrap();
+
+            let v = vel.linvel;
+            write!(text, "\nVelocity {{ {:.2}, {:.2}, {:.2} }} at {:.2} m/s", v.x, v.y, v.z, v.length()).unwrap();
+            let mode = match controller.move_mode {
+                MoveMode::Noclip => "noclip",
+                MoveMode::Ground if controller.grounded => "grounded",
+                MoveMode::Ground => "airborne",
+            };
+            write!(text, "\n{}", mode).unwrap();
+        }
     }
 }
 
 fn update_hud_system(
-    mut text_query: Query<&mut Text, With<PlayerHudText>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut text_query: Query<(&mut Text, &mut Visibility), With<PlayerHudText>>,
     player_query: Query<&Transform, With<Projection>>,
-    mut item_query: Query<&mut Item>,
+    item_query: Query<(&Item, Option<&Gun>)>,
     inv_query: Query<(&Inventory, &PlayerInput)>,
+    health_query: Query<&Health, With<LocalPlayer>>,
 ) {
-    for mut text in text_query.iter_mut() {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for (mut text, mut visibility) in text_query.iter_mut() {
+        // Only a debug readout, players get the dedicated elements instead
+        *visibility = if config.debug { Visibility::Inherited } else { Visibility::Hidden };
+        if !config.debug { continue; }
+
         let text = &mut text.sections[0].value;
         text.clear();
         for transform in player_query.iter() {
             let p = transform.translation;
             write!(text, "Position {{ {:.2}, {:.2}, {:.2} }}", p.x, p.y, p.z).unwrap();
         }
+        for health in health_query.iter() {
+            write!(text, " Health {}/{}", health.current, health.max).unwrap();
+        }
         for (inv, input) in inv_query.iter() {
             write!(text, "\n{:?}", input).unwrap();
             write!(text, "\n{:?}", inv).unwrap();
-            for i in 0..inv.item_ents.0.len() {
-                if let Some(item_ent) = inv.item_ents.0[i] {
-                    if let Ok(item) = item_query.get_mut(item_ent) {
-                        write!(text, "\n{:?}", *item).unwrap();
+            for item_ent in inv.item_ents.0.iter().flatten() {
+                if let Ok((item, gun)) = item_query.get(*item_ent) {
+                    write!(text, "\n{:?}", item).unwrap();
+                    if let Some(gun) = gun {
+                        write!(text, " Ammo {}/{}", gun.ammo, gun.ammo_in_reserve).unwrap();
                     }
                 }
             }
         }
     }
 }
+
+fn update_ammo_counter_sys(
+    mut text_query: Query<(&mut Text, &mut Visibility), With<AmmoCounterText>>,
+    inv_query: Query<&Inventory, With<LocalPlayer>>,
+    item_query: Query<(&Item, &Gun)>,
+    item_props: ItemPropsLookup,
+) {
+    let equipped = inv_query.iter().next()
+        .and_then(|inv| inv.equipped_slot.and_then(|slot| inv.item_ents.get(slot)))
+        .and_then(|item_ent| item_query.get(item_ent).ok())
+        .and_then(|(item, gun)| Some((item, gun, item_props.gun_props(&item.name).ok()?)));
+
+    for (mut text, mut visibility) in text_query.iter_mut() {
+        let Some((item, gun, props)) = equipped else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Inherited;
+
+        let [mag, reserve, reloading] = &mut text.sections[..] else { continue; };
+        mag.value.clear();
+        write!(mag.value, "{} / {}", gun.ammo, props.mag_size).unwrap();
+        mag.style.color = if gun.ammo == 0 { Color::RED } else { Color::WHITE };
+        reserve.value.clear();
+        write!(reserve.value, "  {}", gun.ammo_in_reserve).unwrap();
+        reserve.style.color = if gun.ammo_in_reserve == 0 { Color::RED } else { Color::ANTIQUE_WHITE };
+        reloading.value.clear();
+        if item.is_reloading() {
+            reloading.value.push_str("\nRELOADING");
+        }
+    }
+}
+
+fn update_interact_prompt_sys(
+    bindings: Res<KeyBindings>,
+    last_device: Res<InputDevice>,
+    mut text_query: Query<&mut Text, With<InteractPromptText>>,
+    player_query: Query<&InteractTarget, With<LocalPlayer>>,
+    pickup_query: Query<&ItemPickup>,
+) {
+    for mut text in text_query.iter_mut() {
+        let text = &mut text.sections[0].value;
+        text.clear();
+        for target in player_query.iter() {
+            if let Some(pickup) = target.0.and_then(|pickup_ent| pickup_query.get(pickup_ent).ok()) {
+                match *last_device {
+                    InputDevice::KeyboardMouse => write!(text, "Press {} to pick up {}", bindings.interact, pickup.item_name),
+                    InputDevice::Gamepad => write!(text, "Press {:?} to pick up {}", bindings.gamepad.interact, pickup.item_name),
+                }.unwrap();
+                if pickup.amount > 1 {
+                    write!(text, " x{}", pickup.amount).unwrap();
+                }
+            }
+        }
+    }
+}
+
+fn spawn_hit_feedback_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut commands: Commands,
+    mut hit_events: EventReader<HitEvent>,
+    mut pitches: ResMut<Assets<Pitch>>,
+    mut tally: Local<Option<DamageTally>>,
+    local_query: Query<(), With<LocalPlayer>>,
+    mut marker_query: Query<&mut HitMarker>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    if let Some(tally) = tally.as_mut() {
+        tally.since_hit += time.delta_seconds();
+    }
+    for hit in hit_events.read() {
+        // Everyone else's hits are theirs to see, a server sees them all
+        if !local_query.contains(hit.attacker) { continue; }
+        let color = match (hit.kill, hit.headshot) {
+            _ if hit.friendly => Color::CYAN,
+            (true, _) => Color::RED,
+            (false, true) => Color::GOLD,
+            (false, false) => Color::WHITE,
+        };
+        for mut marker in marker_query.iter_mut() {
+            marker.age = 0.0;
+            marker.color = color;
+        }
+        // Teammates take nothing so there is no number or blip, only the marker
+        if hit.friendly { continue; }
+        // Keep adding to the last number while hits on the same target keep coming, it may have faded out if the window outlasts it
+        let continued = tally.as_mut().filter(|tally| {
+            config.damage_numbers == DamageNumberMode::Aggregated
+                && tally.target == hit.ent
+                && tally.since_hit <= config.damage_number_window
+                && commands.get_entity(tally.number).is_some()
+        });
+        let (number_ent, amount) = match continued {
+            Some(tally) => {
+                tally.amount += u32::from(hit.amount);
+                tally.since_hit = 0.0;
+                (tally.number, tally.amount)
+            }
+            None => {
+                let number_ent = commands.spawn(TextBundle {
+                    style: Style { position_type: PositionType::Absolute, ..default() },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                }).id();
+                let amount = u32::from(hit.amount);
+                *tally = Some(DamageTally { target: hit.ent, number: number_ent, amount, since_hit: 0.0 });
+                (number_ent, amount)
+            }
+        };
+        // Replaced rather than edited since a number spawned earlier this frame is not in the world yet
+        commands.entity(number_ent).insert((
+            Text::from_section(amount.to_string(), TextStyle { font_size: 20.0, color, ..default() }),
+            DamageNumber { position: hit.position, age: 0.0 },
+        ));
+        // Higher blip for the hits that matter more, generated so no sound files are needed
+        let frequency = if hit.kill || hit.headshot { 1320.0 } else { 880.0 };
+        commands.spawn(PitchBundle {
+            source: pitches.add(Pitch::new(frequency, Duration::from_millis(40))),
+            settings: PlaybackSettings { volume: Volume::new_relative(0.2), ..PlaybackSettings::DESPAWN },
+        });
+    }
+}
+
+fn update_hit_feedback_sys(
+    time: Res<Time>,
+    mut commands: Commands,
+    camera_query: Query<(&Camera, &GlobalTransform), With<RenderPlayer>>,
+    mut number_query: Query<(Entity, &mut DamageNumber, &mut Style, &mut Text, &mut Visibility)>,
+    mut marker_query: Query<(&mut HitMarker, &Children)>,
+    mut line_query: Query<&mut BackgroundColor, With<HitMarkerLine>>,
+) {
+    let dt = time.delta_seconds();
+    let camera = camera_query.iter().next();
+    for (number_ent, mut number, mut style, mut text, mut visibility) in number_query.iter_mut() {
+        number.age += dt;
+        if number.age >= DAMAGE_NUMBER_LIFETIME {
+            commands.entity(number_ent).despawn_recursive();
+            continue;
+        }
+        let position = number.position + Vec3::Y * DAMAGE_NUMBER_RISE_SPEED * number.age;
+        // Nothing to show when behind the camera
+        let Some(screen) = camera.and_then(|(camera, transform)| camera.world_to_viewport(transform, position)) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        *visibility = Visibility::Inherited;
+        style.left = Val::Px(screen.x);
+        style.top = Val::Px(screen.y);
+        for section in text.sections.iter_mut() {
+            section.style.color.set_a(1.0 - number.age / DAMAGE_NUMBER_LIFETIME);
+        }
+    }
+
+    for (mut marker, children) in marker_query.iter_mut() {
+        marker.age += dt;
+        let opacity = 1.0 - (marker.age / HIT_MARKER_DURATION).min(1.0);
+        for &child in children.iter() {
+            let Ok(mut background) = line_query.get_mut(child) else { continue; };
+            background.0 = with_opacity(marker.color, opacity);
+        }
+    }
+}
+
+fn update_crosshair_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    window_query: Query<&Window>,
+    camera_query: Query<(&Projection, &RenderPlayer)>,
+    spread_query: Query<(&Spread, &LogicalPlayer), With<LocalPlayer>>,
+    mut line_query: Query<(&CrosshairLine, &mut Style, &mut BackgroundColor, &mut Outline), Without<CrosshairDot>>,
+    mut dot_query: Query<(&mut Style, &mut BackgroundColor, &mut Outline), With<CrosshairDot>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let Ok(window) = window_query.get_single() else { return; };
+    let crosshair = &config.crosshair;
+
+    // Project the edge of the spread cone onto the screen so the lines show where shots can land
+    let local = spread_query.iter().next();
+    let is_hidden = local.is_some_and(|(spread, _)| spread.is_scoped);
+    let spread = local.map_or(0.0, |(spread, _)| spread.current_spread);
+    // Only our own camera, there can be others around for spectating or the minimap
+    let fov = camera_query.iter()
+        .filter(|(_, render_player)| local.is_some_and(|(_, player)| player.0 == render_player.0))
+        .find_map(|(projection, _)| match projection {
+            Projection::Perspective(perspective) => Some(perspective.fov),
+            _ => None,
+        })
+        .unwrap_or(FRAC_PI_4);
+    let spread_px = spread.tan() / (fov * 0.5).tan() * window.height() * 0.5;
+
+    let outline = Outline::new(
+        Val::Px(crosshair.outline_thickness),
+        Val::ZERO,
+        if crosshair.outline_thickness > 0.0 {
+            with_opacity(crosshair.outline_color, crosshair.outline_opacity)
+        } else {
+            Color::NONE
+        },
+    );
+
+    for (line, mut style, mut background, mut line_outline) in line_query.iter_mut() {
+        let along = line.0.abs();
+        let size = along * crosshair.line_length + along.yx() * crosshair.line_thickness;
+        let center = line.0 * (crosshair.gap + spread_px + crosshair.line_length * 0.5);
+        style.display = if is_hidden { Display::None } else { Display::Flex };
+        place_node(&mut style, center, size);
+        background.0 = with_opacity(crosshair.color, crosshair.line_opacity);
+        *line_outline = outline;
+    }
+
+    // The dot ignores spread so it always marks exactly where we are aiming
+    for (mut style, mut background, mut dot_outline) in dot_query.iter_mut() {
+        style.display = if crosshair.dot_size > 0.0 && !is_hidden { Display::Flex } else { Display::None };
+        place_node(&mut style, Vec2::ZERO, Vec2::splat(crosshair.dot_size));
+        background.0 = with_opacity(crosshair.color, crosshair.dot_opacity);
+        *dot_outline = outline;
+    }
+}
+
+/// Center is relative to the middle of the screen with y up, UI has y down
+fn place_node(style: &mut Style, center: Vec2, size: Vec2) {
+    style.left = Val::Px(center.x - size.x * 0.5);
+    style.top = Val::Px(-center.y - size.y * 0.5);
+    style.width = Val::Px(size.x);
+    style.height = Val::Px(size.y);
+}
+
+fn with_opacity(color: Color, opacity: f32) -> Color {
+    color.with_a(color.a() * opacity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends our hits on each target in one burst, returns the text of every damage number shown
+    fn damage_numbers(damage_numbers: DamageNumberMode, targets: &[usize]) -> Vec<String> {
+        let mut configs = Assets::<Config>::default();
+        let handle = configs.add(Config { damage_numbers, ..default() });
+        let mut app = App::new();
+        app.insert_resource(configs)
+            .insert_resource(ConfigState { handle })
+            .init_resource::<Time>()
+            .init_resource::<Assets<Pitch>>()
+            .add_event::<HitEvent>()
+            .add_systems(Update, spawn_hit_feedback_sys);
+        let attacker = app.world.spawn(LocalPlayer).id();
+        let dummies = [app.world.spawn_empty().id(), app.world.spawn_empty().id()];
+        for &target in targets {
+            app.world.send_event(HitEvent {
+                ent: dummies[target], attacker, position: Vec3::ZERO, amount: 20, headshot: false, kill: false, friendly: false,
+            });
+        }
+        app.update();
+        app.world.query_filtered::<&Text, With<DamageNumber>>().iter(&app.world)
+            .map(|text| text.sections[0].value.clone())
+            .collect()
+    }
+
+    #[test]
+    fn burst_on_one_target_adds_up_into_one_number() {
+        assert_eq!(damage_numbers(DamageNumberMode::Aggregated, &[0, 0, 0]), ["60"]);
+    }
+
+    #[test]
+    fn new_target_starts_a_new_number() {
+        let mut numbers = damage_numbers(DamageNumberMode::Aggregated, &[0, 0, 1]);
+        numbers.sort();
+        assert_eq!(numbers, ["20", "40"]);
+    }
+
+    #[test]
+    fn per_hit_shows_every_hit() {
+        assert_eq!(damage_numbers(DamageNumberMode::PerHit, &[0, 0, 0]), ["20", "20", "20"]);
+    }
+}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use bevy::{
+    audio::Volume,
+    ecs::system::SystemParam,
+    math::Vec3Swizzles,
+    prelude::*,
+    utils::HashMap,
+};
+use smartstring::alias::String;
+
+use crate::{Chunk, Config, config_loaded, ConfigState, DryFireEvent, EquippedEvent, FireEvent, Item, ItemPropsLookup, ItemSoundProps, ItemStateEvent, Map, MoveMode, PlayerController, SurfaceType};
+
+/// How far below a player's origin to look for what they are standing on
+const FOOTSTEP_PROBE_DEPTH: f32 = 0.25;
+/// Meters between the ears of whoever is listening
+pub const EAR_GAP: f32 = 0.2;
+
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SoundCache>()
+            .add_systems(Update, (footstep_sound_sys, fire_sound_sys, item_sound_sys).run_if(config_loaded));
+    }
+}
+
+/// Clips stay loaded once played, so playing one again never waits on a load
+#[derive(Resource, Default)]
+pub struct SoundCache {
+    handles: HashMap<String, Handle<AudioSource>>,
+}
+
+/// Which volume in the config a sound is scaled by
+#[derive(Copy, Clone, Debug)]
+pub enum SoundChannel {
+    Footstep,
+    Weapon,
+}
+
+/// Walking distance since the last footstep, on every logical player
+#[derive(Component, Default)]
+pub struct Footsteps {
+    /// Meters into the current stride
+    pub distance: f32,
+    /// Flips every step, so a cycle over both feet can be told apart from one over a single step
+    pub left_foot: bool,
+    pub last_position: Vec3,
+}
+
+impl Footsteps {
+    /// Goes from zero to one over a stride of each foot, left foot first
+    pub fn cycle(&self, stride: f32) -> f32 {
+        let foot = if self.left_foot { 0.0 } else { 1.0 };
+        (foot + (self.distance / stride).min(1.0)) / 2.0
+    }
+}
+
+/// Plays clips out in the world at the volumes from the config
+#[derive(SystemParam)]
+pub struct Sounds<'w, 's> {
+    commands: Commands<'w, 's>,
+    asset_server: Res<'w, AssetServer>,
+    cache: ResMut<'w, SoundCache>,
+    config: Res<'w, Assets<Config>>,
+    config_state: Res<'w, ConfigState>,
+}
+
+impl Sounds<'_, '_> {
+    pub fn config(&self) -> Option<&Config> {
+        self.config.get(&self.config_state.handle)
+    }
+
+    pub fn play(&mut self, path: &str, position: Vec3, channel: SoundChannel) {
+        let Some(config) = self.config.get(&self.config_state.handle) else { return; };
+        let volume = config.master_volume * match channel {
+            SoundChannel::Footstep => config.footstep_volume,
+            SoundChannel::Weapon => config.weapon_volume,
+        };
+        if volume <= 0.0 {
+            return;
+        }
+        let asset_server = &self.asset_server;
+        let source = self.cache.handles.entry(String::from(path))
+            .or_insert_with(|| asset_server.load(path.to_owned()))
+            .clone();
+        self.commands.spawn((
+            AudioBundle {
+                source,
+                settings: PlaybackSettings { volume: Volume::new_relative(volume), spatial: true, ..PlaybackSettings::DESPAWN },
+            },
+            TransformBundle::from_transform(Transform::from_translation(position)),
+        ));
+    }
+}
+
+// ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
+// ██╔══██╗██╔════╝████╗  ██║██╔══██╗██╔════╝██╔══██╗
+// ██████╔╝█████╗  ██╔██╗ ██║██║  ██║█████╗  ██████╔╝
+// ██╔══██╗██╔══╝  ██║╚██╗██║██║  ██║██╔══╝  ██╔══██╗
+// ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
+// ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
+
+fn footstep_sound(surface: SurfaceType) -> &'static str {
+    match surface {
+        SurfaceType::Grass => "sounds/footstep_grass.ogg",
+        SurfaceType::Rock => "sounds/footstep_rock.ogg",
+        SurfaceType::Metal => "sounds/footstep_metal.ogg",
+    }
+}
+
+/// Steps once per stride walked on the ground, so faster movement steps more often and jumping or standing still is silent
+pub fn footstep_sound_sys(
+    mut sounds: Sounds,
+    map_query: Query<&Map>,
+    chunk_query: Query<&Chunk>,
+    mut player_query: Query<(&Transform, &PlayerController, &mut Footsteps)>,
+) {
+    let Some(stride) = sounds.config().map(|config| config.footstep_stride) else { return; };
+    for (transform, controller, mut footsteps) in player_query.iter_mut() {
+        let moved = (transform.translation - footsteps.last_position).xz().length();
+        footsteps.last_position = transform.translation;
+        let is_grounded = matches!(controller.move_mode, MoveMode::Ground) && controller.grounded;
+        if !is_grounded {
+            continue;
+        }
+        footsteps.distance += moved;
+        if footsteps.distance >= stride {
+            footsteps.distance %= stride;
+            footsteps.left_foot = !footsteps.left_foot;
+            let feet = transform.translation - Vec3::Y * FOOTSTEP_PROBE_DEPTH;
+            let surface = map_query.get_single().map_or_else(|_| SurfaceType::default(), |map| map.surface_at(feet, &chunk_query));
+            sounds.play(footstep_sound(surface), transform.translation, SoundChannel::Footstep);
+        }
+    }
+}
+
+/// At most one shot sound per fire interval of the gun, no matter how many fire events come in
+pub fn fire_sound_sys(
+    mut sounds: Sounds,
+    time: Res<Time>,
+    item_props: ItemPropsLookup,
+    mut fire_events: EventReader<FireEvent>,
+    mut last_played: Local<HashMap<Entity, Duration>>,
+    item_query: Query<&Item>,
+    player_query: Query<&Transform>,
+) {
+    last_played.retain(|item_ent, _| item_query.contains(*item_ent));
+    for event in fire_events.read() {
+        let Ok(item) = item_query.get(event.item_ent) else { continue; };
+        let Ok(props) = item_props.config(&item.name).map(|config| config.item_props()) else { continue; };
+        let Some(path) = &props.sounds.fire else { continue; };
+        let Ok(transform) = player_query.get(event.inv_ent) else { continue; };
+        let now = time.elapsed();
+        if last_played.get(&event.item_ent).is_some_and(|last| now.saturating_sub(*last) < props.fire_interval()) {
+            continue;
+        }
+        last_played.insert(event.item_ent, now);
+        sounds.play(path, transform.translation, SoundChannel::Weapon);
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ItemSound {
+    Reload,
+    Equip,
+    DryFire,
+}
+
+impl ItemSound {
+    fn path(self, sounds: &ItemSoundProps) -> Option<&String> {
+        match self {
+            ItemSound::Reload => sounds.reload.as_ref(),
+            ItemSound::Equip => sounds.equip.as_ref(),
+            ItemSound::DryFire => sounds.dry_fire.as_ref(),
+        }
+    }
+}
+
+pub fn item_sound_sys(
+    mut sounds: Sounds,
+    item_props: ItemPropsLookup,
+    mut state_events: EventReader<ItemStateEvent>,
+    mut equipped_events: EventReader<EquippedEvent>,
+    mut dry_fire_events: EventReader<DryFireEvent>,
+    item_query: Query<&Item>,
+    player_query: Query<&Transform>,
+) {
+    let reloads = state_events.read()
+        .filter(|event| event.is_reload())
+        .map(|event| (event.item_ent, event.inv_ent, ItemSound::Reload));
+    let equips = equipped_events.read().map(|event| (event.item_ent, event.inv_ent, ItemSound::Equip));
+    let dry_fires = dry_fire_events.read().map(|event| (event.item_ent, event.inv_ent, ItemSound::DryFire));
+    for (item_ent, inv_ent, sound) in reloads.chain(equips).chain(dry_fires) {
+        let Ok(item) = item_query.get(item_ent) else { continue; };
+        let Ok(props) = item_props.config(&item.name).map(|config| config.item_props()) else { continue; };
+        let Some(path) = sound.path(&props.sounds) else { continue; };
+        let Ok(transform) = player_query.get(inv_ent) else { continue; };
+        sounds.play(path, transform.translation, SoundChannel::Weapon);
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    f32::consts::{FRAC_PI_2, FRAC_PI_4, TAU},
+    time::Duration,
+};
+
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3Swizzles,
+    prelude::*,
+    prelude::shape::Capsule,
+};
+use bevy_rapier3d::prelude::*;
+use rand::{Rng, thread_rng};
+
+use crate::{CAPSULE_BASE, CAPSULE_RADIUS, Config, ConfigState, Dead, DeathEvent, GameRng, gameplay_query_filter, Health, ImpactParticle, LogicalPlayer, player_collider, RngStream, Stance, STAND_CAPSULE_TOP, SurfaceType, Team};
+
+/// Bots are all on one team of their own, apart from every player
+pub const BOT_TEAM: Team = Team(u8::MAX);
+
+/// Spawns are dropped onto whatever is below this height over them
+const BOT_PROBE_HEIGHT: f32 = 128.0;
+/// Gap left under a new bot so it does not start inside the ground
+const BOT_SURFACE_CLEARANCE: f32 = 0.05;
+/// Close enough to the target to stop walking into it
+const BOT_STOP_DISTANCE: f32 = 2.0;
+/// How far ahead a bot looks for something in its way
+const BOT_LOOKAHEAD: f32 = 1.5;
+/// Tried in order when the way ahead is blocked, then mirrored to the other side
+const BOT_NUDGE_ANGLES: [f32; 2] = [FRAC_PI_4, FRAC_PI_2];
+const BOT_COLOR: Color = Color::rgb(0.8, 0.2, 0.2);
+/// Pieces thrown off when a bot dies
+const BOT_DEATH_PARTICLES: usize = 24;
+
+/// Target dummy that walks straight at the nearest player, with no pathfinding beyond stepping around what is right in front
+#[derive(Component, Debug)]
+pub struct Bot;
+
+/// Finds ground to put new bots on, picking where around the ring from the game rng
+#[derive(SystemParam)]
+pub struct BotSpawner<'w> {
+    physics_context: Res<'w, RapierContext>,
+    rng: ResMut<'w, GameRng>,
+}
+
+impl BotSpawner<'_> {
+    /// None until the terrain under the picked spot has meshed
+    fn pick(&mut self, center: Vec3, distance: f32) -> Option<Vec3> {
+        let angle = self.rng.stream(RngStream::Bots).gen_range(0.0..TAU);
+        let spot = center + Quat::from_rotation_y(angle) * Vec3::X * distance;
+        let origin = Vec3::new(spot.x, BOT_PROBE_HEIGHT, spot.z);
+        let (_, toi) = self.physics_context.cast_ray(origin, -Vec3::Y, BOT_PROBE_HEIGHT * 2.0, true, gameplay_query_filter())?;
+        Some(origin - Vec3::Y * (toi - BOT_SURFACE_CLEARANCE))
+    }
+}
+
+// ██╗      ██████╗  ██████╗ ██╗ ██████╗
+// ██║     ██╔═══██╗██╔════╝ ██║██╔════╝
+// ██║     ██║   ██║██║  ███╗██║██║
+// ██║     ██║   ██║██║   ██║██║██║
+// ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
+// ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
+
+/// Keeps the configured number of bots around, so killed ones come back, in a ring around the first spawn point
+pub fn spawn_bots_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut spawner: BotSpawner,
+    bot_query: Query<(), With<Bot>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let bots = &config.bots;
+    let alive = bot_query.iter().count();
+    if alive >= bots.count as usize { return; }
+
+    let center = config.spawn_points.first().map_or(Vec3::ZERO, |spawn| spawn.translation);
+    for _ in alive..bots.count as usize {
+        // Nothing to stand on until the terrain around it has meshed, try again next frame
+        let Some(position) = spawner.pick(center, bots.spawn_distance) else { return; };
+        commands.spawn((
+            (
+                player_collider(Stance::Standing),
+                Velocity::zero(),
+                RigidBody::Dynamic,
+                LockedAxes::ROTATION_LOCKED,
+                Ccd::enabled(),
+            ),
+            PbrBundle { transform: Transform::from_translation(position), ..default() },
+            Health::new(bots.max_health),
+            BOT_TEAM,
+            Name::new("Bot"),
+            Bot,
+        )).with_children(|parent| {
+            let height = STAND_CAPSULE_TOP - CAPSULE_BASE;
+            parent.spawn(PbrBundle {
+                mesh: meshes.add(Mesh::from(Capsule { radius: CAPSULE_RADIUS, depth: height, ..default() })),
+                material: materials.add(StandardMaterial { base_color: BOT_COLOR, ..default() }),
+                transform: Transform::from_xyz(0.0, CAPSULE_BASE + height * 0.5, 0.0),
+                ..default()
+            });
+        });
+    }
+}
+
+type BotTargetFilter = (With<LogicalPlayer>, Without<Dead>, Without<Bot>);
+type BotSeekQuery<'a> = (Entity, &'a mut Transform, &'a mut Velocity);
+
+/// Walks along the ground toward the nearest player, physics takes care of falling and bumping into things
+pub fn bot_seek_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    physics_context: Res<RapierContext>,
+    player_query: Query<(Entity, &Transform), BotTargetFilter>,
+    mut bot_query: Query<BotSeekQuery, (With<Bot>, Without<Dead>)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for (bot_ent, mut transform, mut velocity) in bot_query.iter_mut() {
+        let position = transform.translation;
+        let target = player_query.iter()
+            .min_by(|(_, a), (_, b)| a.translation.distance_squared(position).total_cmp(&b.translation.distance_squared(position)));
+        let to_target = target.map_or(Vec2::ZERO, |(_, target)| (target.translation - position).xz());
+        if to_target.length() <= BOT_STOP_DISTANCE {
+            velocity.linvel.x = 0.0;
+            velocity.linvel.z = 0.0;
+            continue;
+        }
+
+        // Checked at waist height so small bumps in the ground do not count
+        let eye = position + Vec3::Y * (CAPSULE_BASE + STAND_CAPSULE_TOP) * 0.5;
+        let filter = gameplay_query_filter().exclude_collider(bot_ent);
+        let target_ent = target.map(|(target_ent, _)| target_ent);
+        let is_clear = |dir: Vec2| {
+            physics_context.cast_ray(eye, Vec3::new(dir.x, 0.0, dir.y), CAPSULE_RADIUS + BOT_LOOKAHEAD, true, filter)
+                .map_or(true, |(hit_ent, _)| Some(hit_ent) == target_ent)
+        };
+        let ahead = to_target.normalize();
+        let dir = steer_around(ahead, is_clear);
+
+        velocity.linvel.x = dir.x * config.bots.speed;
+        velocity.linvel.z = dir.y * config.bots.speed;
+        // Forward is negative z
+        transform.rotation = Quat::from_rotation_y(f32::atan2(-ahead.x, -ahead.y));
+    }
+}
+
+/// Straight ahead if nothing is in the way, otherwise the smallest turn to either side that is clear
+fn steer_around(ahead: Vec2, is_clear: impl Fn(Vec2) -> bool) -> Vec2 {
+    if is_clear(ahead) { return ahead; }
+    BOT_NUDGE_ANGLES.into_iter()
+        .flat_map(|angle| [angle, -angle])
+        .map(|angle| Vec2::from_angle(angle).rotate(ahead))
+        .find(|&dir| is_clear(dir))
+        // Boxed in, walk into it and let physics push back
+        .unwrap_or(ahead)
+}
+
+/// Runs after the dead marker has been added so the despawn comes last
+pub fn bot_death_sys(
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut death_events: EventReader<DeathEvent>,
+    bot_query: Query<&Transform, With<Bot>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let impacts = &config.impacts;
+    // Only for show so they do not need to be deterministic
+    let mut rng = thread_rng();
+    for death in death_events.read() {
+        let Ok(transform) = bot_query.get(death.ent) else { continue; };
+        commands.entity(death.ent).despawn_recursive();
+        for _ in 0..BOT_DEATH_PARTICLES {
+            let offset = Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(0.0..=1.0), rng.gen_range(-1.0..=1.0));
+            commands.spawn((
+                TransformBundle::from_transform(Transform::from_translation(transform.translation + Vec3::Y * STAND_CAPSULE_TOP * offset.y)),
+                ImpactParticle {
+                    age: Duration::ZERO,
+                    lifetime: Duration::from_secs_f32(impacts.particle_lifetime * rng.gen_range(1.0..=2.0)),
+                    velocity: offset * impacts.particle_speed,
+                    surface: SurfaceType::Metal,
+                },
+            ));
+        }
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    ecs::system::{SystemParam, SystemState},
+    input::InputSystem,
+    prelude::*,
+};
+use thiserror::Error;
+
+use crate::{Config, ConfigState, InvalidField, Inventory, ItemConfig, ItemGiver, ItemName, ItemRegistryError, KeyBindings, LocalPlayer, player_input_system, PlayerInput, PlayerInputFlags, Validate, WheelInput};
+
+/// Lines kept in the scrollback, older ones are dropped
+const CONSOLE_SCROLLBACK: usize = 64;
+const CONSOLE_HEIGHT_PERCENT: f32 = 40.0;
+const CONSOLE_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.8);
+const CONSOLE_FONT_SIZE: f32 = 14.0;
+const CONSOLE_PROMPT: &str = "> ";
+
+#[derive(Debug, Error)]
+pub enum ConsoleError {
+    #[error("Unknown command {0}, try help")]
+    UnknownCommand(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Config is not loaded yet")]
+    NoConfig,
+    #[error("No local player")]
+    NoPlayer,
+    #[error("No config field {0}")]
+    UnknownField(String),
+    #[error(transparent)]
+    InvalidValue(#[from] serde_json::Error),
+    #[error(transparent)]
+    InvalidField(#[from] InvalidField),
+    #[error(transparent)]
+    ItemRegistry(#[from] ItemRegistryError),
+}
+
+/// Something that can be typed into the console, added to the app with [`AddConsoleCommand::add_console_command`]
+pub trait ConsoleCommand: Send + Sync + 'static {
+    /// First word typed, the rest are passed along as arguments
+    fn name(&self) -> &'static str;
+    /// Listed by help and shown when the arguments do not fit
+    fn usage(&self) -> &'static str;
+    /// What is returned is printed to the scrollback
+    fn run(&self, args: &[&str], world: &mut World) -> Result<String, ConsoleError>;
+}
+
+#[derive(Resource, Default)]
+pub struct ConsoleCommands(Vec<Box<dyn ConsoleCommand>>);
+
+pub trait AddConsoleCommand {
+    fn add_console_command(&mut self, command: impl ConsoleCommand) -> &mut Self;
+}
+
+impl AddConsoleCommand for App {
+    fn add_console_command(&mut self, command: impl ConsoleCommand) -> &mut Self {
+        self.init_resource::<ConsoleCommands>();
+        self.world.resource_mut::<ConsoleCommands>().0.push(Box::new(command));
+        self
+    }
+}
+
+/// Drop down for typing commands, only opens with debug on.
+/// Takes all keyboard and mouse button input while open, so typing does not move or shoot
+#[derive(Resource, Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    scrollback: VecDeque<String>,
+    /// Entered this frame, run once the input has been read
+    submitted: Vec<String>,
+}
+
+impl Console {
+    pub fn print(&mut self, line: impl Into<String>) {
+        if self.scrollback.len() == CONSOLE_SCROLLBACK {
+            self.scrollback.pop_front();
+        }
+        self.scrollback.push_back(line.into());
+    }
+}
+
+#[derive(Component)]
+pub struct ConsoleNode;
+
+#[derive(Component)]
+pub struct ConsoleText;
+
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Console>()
+            .init_resource::<ConsoleCommands>()
+            .add_console_command(SetCommand)
+            .add_console_command(GiveCommand)
+            .add_console_command(NoclipCommand)
+            .add_console_command(SpawnCommand)
+            .add_console_command(ReloadCommand)
+            .add_systems(Startup, spawn_console_sys)
+            .add_systems(PreUpdate, (console_input_sys, run_console_commands_sys).chain()
+                .after(InputSystem)
+                .before(player_input_system))
+            .add_systems(Update, update_console_sys);
+    }
+}
+
+/// Held mutably so presses can be swallowed while typing
+#[derive(SystemParam)]
+pub struct ConsoleInput<'w, 's> {
+    bindings: Res<'w, KeyBindings>,
+    key_input: ResMut<'w, Input<KeyCode>>,
+    btn_input: ResMut<'w, Input<MouseButton>>,
+    wheel: ResMut<'w, WheelInput>,
+    char_events: EventReader<'w, 's, ReceivedCharacter>,
+}
+
+fn console_input_sys(
+    config: Res<Assets<Config>>,
+    config_state: Option<Res<ConfigState>>,
+    mut input: ConsoleInput,
+    mut console: ResMut<Console>,
+    mut player_query: Query<&mut PlayerInput, With<LocalPlayer>>,
+) {
+    let debug = config_state.and_then(|config_state| config.get(&config_state.handle)).is_some_and(|config| config.debug);
+    let toggled = input.bindings.console.just_pressed(&input.key_input, &input.btn_input, &input.wheel);
+    if console.open && (toggled || input.key_input.just_pressed(KeyCode::Escape) || !debug) {
+        console.open = false;
+        // Otherwise the same press would go on to open the pause menu
+        input.key_input.reset_all();
+        return;
+    }
+    if !console.open {
+        if !toggled || !debug { return; }
+        console.open = true;
+        // Whatever was held keeps going otherwise, since input is no longer read
+        for mut player_input in player_query.iter_mut() {
+            player_input.movement = Vec3::ZERO;
+            player_input.flags.clear();
+        }
+        // Drops the character typed by the toggle key itself
+        input.char_events.clear();
+    }
+
+    for event in input.char_events.read() {
+        if !event.char.is_control() {
+            console.input.push(event.char);
+        }
+    }
+    if input.key_input.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+    if input.key_input.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        if !line.trim().is_empty() {
+            console.submitted.push(line);
+        }
+    }
+    input.key_input.reset_all();
+    input.btn_input.reset_all();
+    *input.wheel = WheelInput::default();
+}
+
+/// Commands get the whole world, they are rare enough that it does not matter they run alone
+fn run_console_commands_sys(world: &mut World) {
+    // Checked first so the console is only marked changed when there is something to run
+    if world.resource::<Console>().submitted.is_empty() { return; }
+    let submitted = std::mem::take(&mut world.resource_mut::<Console>().submitted);
+    world.resource_scope(|world, commands: Mut<ConsoleCommands>| {
+        for line in submitted {
+            let mut words = line.split_whitespace();
+            let Some(name) = words.next() else { continue; };
+            let args: Vec<&str> = words.collect();
+            let output = match name {
+                // Built in since it lists the others
+                "help" => Ok(commands.0.iter().map(|command| command.usage()).collect::<Vec<_>>().join("\n")),
+                _ => match commands.0.iter().find(|command| command.name() == name) {
+                    Some(command) => command.run(&args, world),
+                    None => Err(ConsoleError::UnknownCommand(name.into())),
+                },
+            };
+            let mut console = world.resource_mut::<Console>();
+            console.print(format!("{}{}", CONSOLE_PROMPT, line));
+            match output {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => console.print(output),
+                Err(err) => console.print(format!("Error: {}", err)),
+            }
+        }
+    });
+}
+
+/// Edits go through the config asset so everything watching it for changes picks them up
+fn modify_config(world: &mut World, modify: impl FnOnce(&mut Config) -> Result<String, ConsoleError>) -> Result<String, ConsoleError> {
+    let handle = world.get_resource::<ConfigState>().ok_or(ConsoleError::NoConfig)?.handle.clone();
+    let mut configs = world.resource_mut::<Assets<Config>>();
+    let config = configs.get(&handle).ok_or(ConsoleError::NoConfig)?;
+    let mut modified = config.clone();
+    let output = modify(&mut modified)?;
+    modified.validate()?;
+    if let Some(config) = configs.get_mut(&handle) {
+        *config = modified;
+    }
+    Ok(output)
+}
+
+/// Short names for the fields tweaked most, anything else is typed out in full
+const SET_ALIASES: &[(&str, &str)] = &[
+    ("fov", "fov_degrees"),
+    ("ads_fov", "ads_fov_degrees"),
+    ("sens", "sensitivity"),
+    ("volume", "master_volume"),
+    ("render_distance", "chunk_render_distance"),
+];
+
+/// Any config field, nested ones and list entries are reached with dots like `fog.density` or `spawn_points.0`
+struct SetCommand;
+
+impl ConsoleCommand for SetCommand {
+    fn name(&self) -> &'static str { "set" }
+
+    fn usage(&self) -> &'static str {
+        "set <field> [value], prints the field without a value, values are JSON or a bare word. \
+        Shorthands are fov, ads_fov, sens, volume and render_distance"
+    }
+
+    fn run(&self, args: &[&str], world: &mut World) -> Result<String, ConsoleError> {
+        let [field, value @ ..] = args else { return Err(ConsoleError::Usage(self.usage())); };
+        let field = SET_ALIASES.iter().find(|(alias, _)| alias == field).map_or(*field, |&(_, field)| field);
+        let pointer = format!("/{}", field.replace('.', "/"));
+        modify_config(world, |config| {
+            let mut tree = serde_json::to_value(&*config)?;
+            let node = tree.pointer_mut(&pointer).ok_or_else(|| ConsoleError::UnknownField(field.to_string()))?;
+            if !value.is_empty() {
+                let value = value.join(" ");
+                // Bare words are taken as strings so enum variants do not need quoting
+                *node = serde_json::from_str(&value).unwrap_or(serde_json::Value::String(value));
+                *config = serde_json::from_value(tree.clone())?;
+            }
+            let node = tree.pointer(&pointer).ok_or_else(|| ConsoleError::UnknownField(field.to_string()))?;
+            Ok(format!("{} = {}", field, node))
+        })
+    }
+}
+
+struct GiveCommand;
+
+impl ConsoleCommand for GiveCommand {
+    fn name(&self) -> &'static str { "give" }
+
+    fn usage(&self) -> &'static str { "give <item> [amount]" }
+
+    fn run(&self, args: &[&str], world: &mut World) -> Result<String, ConsoleError> {
+        let (item_name, amount) = match args {
+            [item_name] => (ItemName::from(*item_name), 1),
+            [item_name, amount] => (ItemName::from(*item_name), amount.parse().map_err(|_| ConsoleError::Usage(self.usage()))?),
+            _ => return Err(ConsoleError::Usage(self.usage())),
+        };
+        let mut state = SystemState::<(ItemGiver, Query<(Entity, &mut Inventory), With<LocalPlayer>>)>::new(world);
+        let (mut giver, mut player_query) = state.get_mut(world);
+        let (inv_ent, mut inv) = player_query.get_single_mut().map_err(|_| ConsoleError::NoPlayer)?;
+        let left_over = giver.give(inv_ent, &mut inv, &item_name, amount)?;
+        state.apply(world);
+        Ok(match left_over {
+            0 => format!("Gave {} {}", amount, item_name),
+            _ => format!("Gave {} {}, {} did not fit", amount - left_over, item_name, left_over),
+        })
+    }
+}
+
+/// Same as pressing the fly key, so it still needs debug on to go in
+struct NoclipCommand;
+
+impl ConsoleCommand for NoclipCommand {
+    fn name(&self) -> &'static str { "noclip" }
+
+    fn usage(&self) -> &'static str { "noclip" }
+
+    fn run(&self, _args: &[&str], world: &mut World) -> Result<String, ConsoleError> {
+        let mut player_query = world.query_filtered::<&mut PlayerInput, With<LocalPlayer>>();
+        let mut player_input = player_query.get_single_mut(world).map_err(|_| ConsoleError::NoPlayer)?;
+        player_input.flags |= PlayerInputFlags::Fly;
+        Ok(String::new())
+    }
+}
+
+/// Bots are kept at the configured count, so this raises it and the new ones stay after dying
+struct SpawnCommand;
+
+impl ConsoleCommand for SpawnCommand {
+    fn name(&self) -> &'static str { "spawn" }
+
+    fn usage(&self) -> &'static str { "spawn bot [count]" }
+
+    fn run(&self, args: &[&str], world: &mut World) -> Result<String, ConsoleError> {
+        let count: u8 = match args {
+            ["bot"] => 1,
+            ["bot", count] => count.parse().map_err(|_| ConsoleError::Usage(self.usage()))?,
+            _ => return Err(ConsoleError::Usage(self.usage())),
+        };
+        modify_config(world, |config| {
+            config.bots.count = config.bots.count.saturating_add(count);
+            Ok(format!("Keeping {} bots around", config.bots.count))
+        })
+    }
+}
+
+/// Reads the game config and every item config from disk again, ones that fail to parse keep their last good version
+struct ReloadCommand;
+
+impl ConsoleCommand for ReloadCommand {
+    fn name(&self) -> &'static str { "reload" }
+
+    fn usage(&self) -> &'static str { "reload configs" }
+
+    fn run(&self, args: &[&str], world: &mut World) -> Result<String, ConsoleError> {
+        let ["configs"] = args else { return Err(ConsoleError::Usage(self.usage())); };
+        let asset_server = world.resource::<AssetServer>();
+        let ids = world.resource::<Assets<Config>>().ids().map(|id| id.untyped())
+            .chain(world.resource::<Assets<ItemConfig>>().ids().map(|id| id.untyped()));
+        let mut reloaded = 0;
+        for path in ids.filter_map(|id| asset_server.get_path(id)) {
+            asset_server.reload(path.into_owned());
+            reloaded += 1;
+        }
+        Ok(format!("Reloading {} configs", reloaded))
+    }
+}
+
+// ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
+// ██╔══██╗██╔════╝████╗  ██║██╔══██╗██╔════╝██╔══██╗
+// ██████╔╝█████╗  ██╔██╗ ██║██║  ██║█████╗  ██████╔╝
+// ██╔══██╗██╔══╝  ██║╚██╗██║██║  ██║██╔══╝  ██╔══██╗
+// ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
+// ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
+
+fn spawn_console_sys(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                display: Display::None,
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.0),
+                height: Val::Percent(CONSOLE_HEIGHT_PERCENT),
+                flex_direction: FlexDirection::Column,
+                // Newest lines sit at the bottom and old ones are pushed out the top
+                justify_content: JustifyContent::FlexEnd,
+                overflow: Overflow::clip(),
+                padding: UiRect::all(Val::Px(5.0)),
+                ..default()
+            },
+            background_color: CONSOLE_BACKGROUND.into(),
+            z_index: ZIndex::Global(i32::MAX),
+            ..default()
+        },
+        ConsoleNode,
+    )).with_children(|parent| {
+        let style = TextStyle { font_size: CONSOLE_FONT_SIZE, color: Color::WHITE, ..default() };
+        parent.spawn((
+            TextBundle::from_sections([TextSection::new("", style.clone()), TextSection::new("", style)]),
+            ConsoleText,
+        ));
+    });
+}
+
+fn update_console_sys(
+    console: Res<Console>,
+    mut node_query: Query<&mut Style, With<ConsoleNode>>,
+    mut text_query: Query<&mut Text, With<ConsoleText>>,
+) {
+    if !console.is_changed() { return; }
+    for mut style in node_query.iter_mut() {
+        style.display = if console.open { Display::Flex } else { Display::None };
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = console.scrollback.iter().flat_map(|line| [line.as_str(), "\n"]).collect();
+        text.sections[1].value = format!("{}{}_", CONSOLE_PROMPT, console.input);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_world() -> World {
+        let mut configs = Assets::<Config>::default();
+        let handle = configs.add(Config::default());
+        let mut world = World::new();
+        world.insert_resource(configs);
+        world.insert_resource(ConfigState { handle });
+        world
+    }
+
+    fn config(world: &World) -> &Config {
+        world.resource::<Assets<Config>>().get(&world.resource::<ConfigState>().handle).unwrap()
+    }
+
+    #[test]
+    fn set_takes_shorthands_and_full_paths() {
+        let mut world = config_world();
+        assert!(SetCommand.run(&["fov", "100"], &mut world).unwrap().starts_with("fov_degrees = "));
+        assert_eq!(config(&world).fov_degrees, 100.0);
+        SetCommand.run(&["fog.density", "0.5"], &mut world).unwrap();
+        assert_eq!(config(&world).fog.density, 0.5);
+        assert!(matches!(SetCommand.run(&["nope", "1"], &mut world), Err(ConsoleError::UnknownField(field)) if field == "nope"));
+    }
+}
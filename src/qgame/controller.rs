@@ -1,79 +1,218 @@
+use std::f32::consts::TAU;
+
 use bevy::{
     math::Vec3Swizzles,
     prelude::*,
 };
 use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{AimDownSights, Config, ConfigState, EAR_GAP, Footsteps, gameplay_query_filter, Health, InteractTarget, Inventory, Item, ItemPropsLookup, PlayerInput, PlayerInputFlags, Recoil, SnapshotInterpolation, SpawnPointConfig, Spread, Team, ViewModelMotion};
 
-use crate::{PlayerInput, PlayerInputFlags};
+/// Camera height above the logical player's origin
+pub const EYE_HEIGHT: f32 = 2.0;
+const CROUCH_EYE_HEIGHT: f32 = 1.25;
 
+/// Player capsule runs from its base segment point up to the top one, crouching only lowers the top
+pub const CAPSULE_RADIUS: f32 = 0.5;
+pub const CAPSULE_BASE: f32 = 0.5;
+pub const STAND_CAPSULE_TOP: f32 = 1.5;
+const CROUCH_CAPSULE_TOP: f32 = 0.75;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum MoveMode {
     Noclip,
+    #[default]
     Ground,
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stance {
+    #[default]
+    Standing,
+    Crouching,
+}
+
 #[derive(Component)]
 pub struct LogicalPlayer(pub u8);
 
 #[derive(Component)]
 pub struct RenderPlayer(pub u8);
 
+/// The logical player driven by this machine's input, any others are remote
 #[derive(Component)]
-pub struct VisualTransform(pub Transform);
+pub struct LocalPlayer;
 
 #[derive(Component)]
+pub struct VisualTransform(pub Transform);
+
+/// Positions at the end of the last two simulation ticks, rendering blends between them
+/// so movement looks smooth when the frame rate is higher than the tick rate
+#[derive(Component, Default)]
+pub struct TickInterpolation {
+    pub previous: Vec3,
+    pub current: Vec3,
+    /// Left over from a prediction correction, drawn on top and shrunk every tick so small corrections glide
+    pub correction: Vec3,
+}
+
+/// Fraction of a prediction correction still drawn after each tick
+const CORRECTION_DECAY: f32 = 0.8;
+
+/// Moving further than this in one tick can only be a teleport, which snaps instead of smearing the camera across the map
+const MAX_INTERPOLATED_DISTANCE: f32 = 4.0;
+
+/// Extra degrees ground may tilt before we let go of it, so standing right at the max slope does not flicker
+const SLOPE_HYSTERESIS_DEGREES: f32 = 2.0;
+
+/// What movement needed to know about the world on the last tick, sampled before simulating
+/// so the tick can be replayed later without any physics queries
+#[derive(Component, Copy, Clone, Debug)]
+pub struct MoveEnvironment {
+    pub grounded: bool,
+    /// Of the surface just below, even when it is too steep to count as ground
+    pub ground_normal: Option<Vec3>,
+    pub can_stand: bool,
+    pub can_leave_noclip: bool,
+    pub move_factor: f32,
+}
+
+impl Default for MoveEnvironment {
+    fn default() -> Self {
+        Self {
+            grounded: false,
+            ground_normal: None,
+            can_stand: true,
+            can_leave_noclip: true,
+            move_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct PlayerController {
     pub move_mode: MoveMode,
-    pub gravity: f32,
-    pub walk_speed: f32,
-    pub run_speed: f32,
     pub fwd_speed: f32,
     pub side_speed: f32,
+    /// Wished speed is capped to this in the air, so steering can only add speed sideways to our velocity
     pub air_speed_cap: f32,
-    pub air_accel: f32,
-    pub max_air_speed: f32,
-    pub accel: f32,
-    pub friction: f32,
     pub friction_cutoff: f32,
-    pub jump_speed: f32,
-    pub fly_speed: f32,
-    pub fast_fly_speed: f32,
     pub fly_friction: f32,
     pub pitch: f32,
     pub yaw: f32,
     pub velocity: Vec3,
+    /// Standing on something flat enough, found once per tick so nothing else needs to cast for it
+    pub grounded: bool,
+    /// Of what we are standing on, straight up while airborne
+    pub ground_normal: Vec3,
+    /// Ticks on the ground in a row, only for holding off friction on landing so hopping keeps its speed
     pub ground_tick: u8,
+    /// Seconds since we were last on the ground, for coyote time
+    pub air_time: f32,
+    /// Whether we have jumped since last on the ground, so coyote time can not give a second jump
+    pub has_jumped: bool,
+    pub air_jumps_used: u8,
+    /// Jump held last update, air jumps need a fresh press so holding jump does not spend them all at once
+    pub jump_held: bool,
+    pub stance: Stance,
+    /// Zero standing and one fully crouched, eases the eye height between the two
+    pub crouch_progress: f32,
+    /// Seconds of sprinting spent, recovers while not sprinting
+    pub stamina_used: f32,
     pub stop_speed: f32,
 }
 
 impl Default for PlayerController {
     fn default() -> Self {
         Self {
-            move_mode: MoveMode::Noclip,
-            fly_speed: 10.0,
-            fast_fly_speed: 30.0,
-            gravity: 23.0,
-            walk_speed: 10.0,
-            run_speed: 30.0,
+            move_mode: MoveMode::Ground,
             fwd_speed: 30.0,
             side_speed: 30.0,
             air_speed_cap: 2.0,
-            air_accel: 20.0,
-            max_air_speed: 8.0,
-            accel: 10.0,
-            friction: 10.0,
             friction_cutoff: 0.1,
             fly_friction: 0.5,
             pitch: 0.0,
             yaw: 0.0,
             velocity: Vec3::ZERO,
+            grounded: false,
+            ground_normal: Vec3::Y,
             ground_tick: 0,
+            air_time: 0.0,
+            has_jumped: false,
+            air_jumps_used: 0,
+            jump_held: false,
+            stance: Stance::Standing,
+            crouch_progress: 0.0,
+            stamina_used: 0.0,
             stop_speed: 1.0,
-            jump_speed: 8.5,
         }
     }
 }
 
 
+impl PlayerController {
+    /// Where the eyes are above the origin right now, shots and the camera both come from here
+    pub fn eye_height(&self) -> f32 {
+        EYE_HEIGHT + (CROUCH_EYE_HEIGHT - EYE_HEIGHT) * smoothstep(self.crouch_progress)
+    }
+}
+
+pub fn player_collider(stance: Stance) -> Collider {
+    let top = match stance {
+        Stance::Standing => STAND_CAPSULE_TOP,
+        Stance::Crouching => CROUCH_CAPSULE_TOP,
+    };
+    Collider::capsule(Vec3::Y * CAPSULE_BASE, Vec3::Y * top, CAPSULE_RADIUS)
+}
+
+/// Everything a logical player needs to simulate, the caller adds whatever marks it as local or remote
+pub fn spawn_player(commands: &mut Commands, config: &Config, spawn: SpawnPointConfig, id: u8) -> Entity {
+    let translation = spawn.translation;
+    commands.spawn((
+        (
+            player_collider(Stance::Standing),
+            Velocity::zero(),
+            RigidBody::Dynamic,
+            Sleeping::disabled(),
+            LockedAxes::ROTATION_LOCKED,
+            AdditionalMassProperties::Mass(1.0),
+            ReadMassProperties::default(),
+            GravityScale(0.0),
+            Ccd { enabled: true },
+        ),
+        TransformBundle::from(Transform::from_translation(translation)),
+        LogicalPlayer(id),
+        PlayerInput {
+            pitch: -TAU / 12.0,
+            yaw: spawn.yaw,
+            ..default()
+        },
+        PlayerController {
+            ..default()
+        },
+        TickInterpolation { previous: translation, current: translation, ..default() },
+        MoveEnvironment::default(),
+        Footsteps { last_position: translation, ..default() },
+        ViewModelMotion::default(),
+        Inventory::with_slots(config.inventory_slots),
+        InteractTarget::default(),
+        Recoil::default(),
+        AimDownSights::default(),
+        Spread::default(),
+        (Health::new(config.max_health), Team::for_player(config, id), Name::new(format!("Player {}", id))),
+    )).id()
+}
+
+type RenderPlayerQuery<'a> = (
+    &'a Transform, Option<&'a TickInterpolation>, &'a PlayerController, &'a PlayerInput,
+    &'a LogicalPlayer, Option<&'a AimDownSights>,
+);
+
+type PlayerMoveQuery<'a> = (
+    Entity, &'a PlayerInput, &'a mut PlayerController,
+    &'a mut Collider, &'a Transform, &'a mut Velocity, &'a mut MoveEnvironment, Option<&'a Inventory>,
+);
+
 // ██╗      ██████╗  ██████╗ ██╗ ██████╗
 // ██║     ██╔═══██╗██╔════╝ ██║██╔════╝
 // ██║     ██║   ██║██║  ███╗██║██║
@@ -81,8 +220,21 @@ impl Default for PlayerController {
 // ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
 // ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
 
+/// Runs after physics has moved everything for the tick
+pub fn record_tick_position_sys(mut query: Query<(&Transform, &mut TickInterpolation)>) {
+    for (transform, mut interpolation) in query.iter_mut() {
+        if transform.translation.distance(interpolation.current) > MAX_INTERPOLATED_DISTANCE {
+            *interpolation = TickInterpolation { previous: transform.translation, current: transform.translation, ..default() };
+            continue;
+        }
+        interpolation.previous = interpolation.current;
+        interpolation.current = transform.translation;
+        interpolation.correction *= CORRECTION_DECAY;
+    }
+}
+
 pub fn player_look_sys(
-    mut query: Query<(&mut PlayerController, &PlayerInput)>
+    mut query: Query<(&mut PlayerController, &PlayerInput), Without<SnapshotInterpolation>>
 ) {
     for (mut controller, input) in query.iter_mut() {
         controller.pitch = input.pitch;
@@ -92,135 +244,273 @@ pub fn player_look_sys(
 
 pub fn player_move_sys(
     time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
     physics_context: Res<RapierContext>,
-    mut query: Query<(
-        Entity, &PlayerInput, &mut PlayerController,
-        &Collider, &mut Transform, &mut Velocity
-    )>,
+    item_props: ItemPropsLookup,
+    item_query: Query<&Item>,
+    mut query: Query<PlayerMoveQuery, Without<SnapshotInterpolation>>,
 ) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
     let dt = time.delta_seconds();
 
-    for (entity, input, mut controller, collider, transform, mut vel) in query.iter_mut() {
-        if input.flags.contains(PlayerInputFlags::Fly) {
-            controller.move_mode = match controller.move_mode {
-                MoveMode::Noclip => MoveMode::Ground,
-                MoveMode::Ground => MoveMode::Noclip
-            }
+    for (entity, input, mut controller, mut collider, transform, mut vel, mut env, inv) in query.iter_mut() {
+        let pos = transform.translation;
+        let filter = gameplay_query_filter().exclude_collider(entity);
+
+        // Collisions come back on leaving noclip, so do not let that happen inside terrain
+        let wants_leave_noclip = input.flags.contains(PlayerInputFlags::Fly) && matches!(controller.move_mode, MoveMode::Noclip);
+        env.can_leave_noclip = !wants_leave_noclip
+            || physics_context.intersection_with_shape(pos, Quat::IDENTITY, &collider, filter).is_none();
+        if !env.can_leave_noclip {
+            warn!("Can not leave noclip while inside something, move out first");
         }
 
-        let rot = look_quat(input.pitch, input.yaw);
-        let right = rot * Vec3::X;
-        let fwd = rot * -Vec3::Z;
-        let pos = transform.translation;
+        // Stay down until there is room to stand, otherwise we would end up inside the ceiling
+        let headroom = Collider::capsule(Vec3::Y * CROUCH_CAPSULE_TOP, Vec3::Y * STAND_CAPSULE_TOP, CAPSULE_RADIUS * 0.99);
+        env.can_stand = controller.stance == Stance::Standing
+            || physics_context.intersection_with_shape(pos, Quat::IDENTITY, &headroom, filter).is_none();
+
+        // Harder to leave ground than to find it, so standing right at the max slope does not flicker
+        let max_slope = config.max_ground_slope_degrees + if controller.grounded { SLOPE_HYSTERESIS_DEGREES } else { 0.0 };
+        let min_normal_y = max_slope.min(89.0).to_radians().cos();
+        env.ground_normal = find_ground(&physics_context, pos, &collider, filter, config.ground_check_distance, min_normal_y);
+        env.grounded = env.ground_normal.is_some_and(|normal| normal.y >= min_normal_y);
+        controller.grounded = env.grounded;
+        controller.ground_normal = env.ground_normal.filter(|_| env.grounded).unwrap_or(Vec3::Y);
 
+        // Heavier items slow us down
+        env.move_factor = inv
+            .and_then(|inv| inv.equipped_slot.and_then(|slot| inv.item_ents.get(slot)))
+            .and_then(|item_ent| item_query.get(item_ent).ok())
+            .and_then(|item| item_props.config(&item.name).ok())
+            .map_or(1.0, |config| config.item_props().move_factor);
+
+        let stance = controller.stance;
+        vel.linvel = simulate_move(&mut controller, input, &env, config, dt);
+        if controller.stance != stance {
+            *collider = player_collider(controller.stance);
+        }
+    }
+}
+
+/// Normal of the surface just under the capsule, however steep
+fn find_ground(
+    physics_context: &RapierContext, pos: Vec3, collider: &Collider, filter: QueryFilter, distance: f32, min_normal_y: f32,
+) -> Option<Vec3> {
+    let capsule = collider.as_capsule()?.raw;
+    let cast_capsule = Collider::capsule(capsule.segment.a.into(), capsule.segment.b.into(), capsule.radius * 0.99);
+    let (_, toi) = physics_context.cast_shape(pos, Quat::IDENTITY, -Vec3::Y, &cast_capsule, distance, true, filter)?;
+    // Already touching so there is no normal to go by, only happens when resting right on it
+    let Some(details) = toi.details else { return Some(Vec3::Y); };
+    let normal = -details.normal1;
+    if normal.y >= min_normal_y {
+        return Some(normal);
+    }
+    // On an edge of the terrain mesh the cast can hit the steep side of a neighboring triangle even on flat ground,
+    // so check straight below the bottom of the capsule before deciding it is too steep
+    let base = pos + Vec3::from(capsule.segment.a);
+    let below = physics_context.cast_ray_and_get_normal(base, -Vec3::Y, capsule.radius + distance, true, filter)
+        .map(|(_, hit)| hit.normal)
+        .filter(|normal| normal.y >= min_normal_y);
+    Some(below.unwrap_or(normal))
+}
+
+/// One tick of movement, returning the velocity to move with. Only depends on its arguments so a client can
+/// replay its inputs on top of a correction from the server and land where the server will
+pub fn simulate_move(
+    controller: &mut PlayerController,
+    input: &PlayerInput,
+    env: &MoveEnvironment,
+    config: &Config,
+    dt: f32,
+) -> Vec3 {
+    let gravity = config.gravity;
+    // Speed that peaks at the jump height, from v² = 2gh
+    let jump_speed = f32::sqrt(2.0 * gravity * config.jump_height);
+
+    if input.flags.contains(PlayerInputFlags::Fly) {
         match controller.move_mode {
-            MoveMode::Noclip => {
-                if input.movement == Vec3::ZERO {
-                    let friction = controller.fly_friction.clamp(0.0, 1.0);
-                    controller.velocity *= 1.0 - friction;
-                    if controller.velocity.length_squared() < 1e-6 {
-                        controller.velocity = Vec3::ZERO;
-                    }
-                } else {
-                    let fly_speed = if input.flags.contains(PlayerInputFlags::Sprint) {
-                        controller.fast_fly_speed
-                    } else {
-                        controller.fly_speed
-                    };
-                    controller.velocity = input.movement.normalize() * fly_speed;
+            MoveMode::Noclip if env.can_leave_noclip => {
+                // Fly velocity is relative to the view, start falling from rest instead of carrying it over
+                controller.velocity = Vec3::ZERO;
+                controller.ground_tick = 0;
+                controller.move_mode = MoveMode::Ground;
+            }
+            MoveMode::Ground if config.debug => {
+                controller.velocity = Vec3::ZERO;
+                controller.move_mode = MoveMode::Noclip;
+            }
+            _ => {}
+        }
+    }
+
+    let rot = look_quat(input.pitch, input.yaw);
+    let right = rot * Vec3::X;
+    let fwd = rot * -Vec3::Z;
+
+    let wants_crouch = input.flags.contains(PlayerInputFlags::Crouch) && matches!(controller.move_mode, MoveMode::Ground);
+    match (controller.stance, wants_crouch) {
+        (Stance::Standing, true) => controller.stance = Stance::Crouching,
+        (Stance::Crouching, false) if env.can_stand => controller.stance = Stance::Standing,
+        _ => {}
+    }
+    let crouch_step = if config.crouch_duration > 0.0 { dt / config.crouch_duration } else { 1.0 };
+    let crouch_target = if controller.stance == Stance::Crouching { 1.0 } else { 0.0 };
+    controller.crouch_progress += (crouch_target - controller.crouch_progress).clamp(-crouch_step, crouch_step);
+
+    match controller.move_mode {
+        MoveMode::Noclip => {
+            if input.movement == Vec3::ZERO {
+                let friction = controller.fly_friction.clamp(0.0, 1.0);
+                controller.velocity *= 1.0 - friction;
+                if controller.velocity.length_squared() < 1e-6 {
+                    controller.velocity = Vec3::ZERO;
                 }
-                vel.linvel = controller.velocity.x * right
-                    + controller.velocity.y * Vec3::Y
-                    + controller.velocity.z * fwd;
+            } else {
+                let fly_speed = if input.flags.contains(PlayerInputFlags::Sprint) {
+                    config.noclip_speed * config.sprint_speed_factor
+                } else {
+                    config.noclip_speed
+                };
+                controller.velocity = input.movement.normalize() * fly_speed;
             }
+            controller.velocity.x * right
+                + controller.velocity.y * Vec3::Y
+                + controller.velocity.z * fwd
+        }
 
-            MoveMode::Ground => {
-                if let Some(capsule) = collider.as_capsule() {
-                    let capsule = capsule.raw;
-                    let mut init_vel = controller.velocity;
-                    let mut end_vel = init_vel;
-                    let lateral_speed = init_vel.xz().length();
-
-                    // Capsule cast downwards to find ground
-                    let mut ground_hit = None;
-                    let cast_capsule = Collider::capsule(capsule.segment.a.into(), capsule.segment.b.into(), capsule.radius * 0.99);
-                    let cast_vel = Vec3::Y * -1.0;
-                    let max_dist = 0.125;
-                    let groups = QueryFilter::default().exclude_collider(entity);
-
-                    if let Some((_handle, hit)) = physics_context.cast_shape(
-                        pos, rot, cast_vel, &cast_capsule, max_dist, true, groups,
-                    ) {
-                        ground_hit = Some(hit);
-                    }
+        MoveMode::Ground => {
+            let mut init_vel = controller.velocity;
+            let mut end_vel = init_vel;
+            let lateral_speed = init_vel.xz().length();
 
-                    let mut wish_dir = input.movement.z * controller.fwd_speed * fwd + input.movement.x * controller.side_speed * right;
-                    let mut wish_speed = wish_dir.length();
-                    if wish_speed > 1e-6 { // Avoid division by zero
-                        wish_dir /= wish_speed; // Effectively normalize, avoid length computation twice
-                    }
+            let mut wish_dir = input.movement.z * controller.fwd_speed * fwd + input.movement.x * controller.side_speed * right;
+            let mut wish_speed = wish_dir.length();
+            if wish_speed > 1e-6 { // Avoid division by zero
+                wish_dir /= wish_speed; // Effectively normalize, avoid length computation twice
+            }
 
-                    let max_speed = if input.flags.contains(PlayerInputFlags::Sprint) {
-                        controller.run_speed
-                    } else {
-                        controller.walk_speed
-                    };
-
-                    wish_speed = f32::min(wish_speed, max_speed);
-
-                    if let Some(_ground_hit) = ground_hit {
-                        // Only apply friction after at least one tick, allows b-hopping without losing speed
-                        if controller.ground_tick >= 1 {
-                            if lateral_speed > controller.friction_cutoff {
-                                friction(lateral_speed, controller.friction, controller.stop_speed, dt, &mut end_vel);
-                            } else {
-                                end_vel.x = 0.0;
-                                end_vel.z = 0.0;
-                            }
-                            end_vel.y = 0.0;
-                        }
-                        accelerate(wish_dir, wish_speed, controller.accel, dt, &mut end_vel);
-                        if input.flags.contains(PlayerInputFlags::Jump) {
-                            // Simulate one update ahead, since this is an instant velocity change
-                            init_vel.y = controller.jump_speed;
-                            end_vel.y = init_vel.y - controller.gravity * dt;
-                        }
-                        // Increment ground tick but cap at max value
-                        controller.ground_tick = controller.ground_tick.saturating_add(1);
+            let is_moving = input.movement.x != 0.0 || input.movement.z != 0.0;
+            let has_stamina = config.max_stamina <= 0.0 || controller.stamina_used < config.max_stamina;
+            let is_sprinting = input.flags.contains(PlayerInputFlags::Sprint)
+                && controller.stance == Stance::Standing && is_moving && has_stamina;
+            if is_sprinting {
+                controller.stamina_used += dt;
+            } else {
+                controller.stamina_used = f32::max(controller.stamina_used - config.stamina_regen_rate * dt, 0.0);
+            }
+
+            let mut max_speed = config.max_speed * env.move_factor;
+            if is_sprinting {
+                max_speed *= config.sprint_speed_factor;
+            }
+            if controller.stance == Stance::Crouching {
+                max_speed *= config.crouch_speed_factor;
+            }
+
+            wish_speed = f32::min(wish_speed, max_speed);
+
+            let jump_held = input.flags.contains(PlayerInputFlags::Jump);
+            let jump_pressed = jump_held && !controller.jump_held;
+            controller.jump_held = jump_held;
+
+            let mut jump = false;
+            if env.grounded {
+                // Only apply friction after at least one tick, allows b-hopping without losing speed
+                if controller.ground_tick >= 1 {
+                    if lateral_speed > controller.friction_cutoff {
+                        friction(lateral_speed, config.friction, controller.stop_speed, dt, &mut end_vel);
                     } else {
-                        controller.ground_tick = 0;
-                        wish_speed = f32::min(wish_speed, controller.air_speed_cap);
-                        accelerate(wish_dir, wish_speed, controller.air_accel, dt, &mut end_vel);
-                        end_vel.y -= controller.gravity * dt;
-                        let air_speed = end_vel.xz().length();
-                        if air_speed > controller.max_air_speed {
-                            let ratio = controller.max_air_speed / air_speed;
-                            end_vel.x *= ratio;
-                            end_vel.z *= ratio;
-                        }
+                        end_vel.x = 0.0;
+                        end_vel.z = 0.0;
+                    }
+                }
+                // Landing stops the fall right away so downward speed never builds up on the ground,
+                // upward speed is kept so we do not cancel a jump still close to the ground
+                end_vel.y = f32::max(end_vel.y, 0.0);
+                accelerate(wish_dir, wish_speed, config.ground_accel, dt, &mut end_vel);
+                // Holding jump keeps hopping on landing
+                jump = jump_held;
+                // Still rising off a jump is not landing, otherwise coyote time would give a second jump
+                if init_vel.y <= 0.0 {
+                    controller.air_time = 0.0;
+                    controller.has_jumped = false;
+                    controller.air_jumps_used = 0;
+                }
+                // Increment ground tick but cap at max value
+                controller.ground_tick = controller.ground_tick.saturating_add(1);
+            } else {
+                controller.ground_tick = 0;
+                controller.air_time += dt;
+                wish_speed = f32::min(wish_speed, controller.air_speed_cap);
+                // Sliding down something too steep to stand on, steering may go along it but not up into it
+                if let Some(normal) = env.ground_normal {
+                    let downhill = normal.xz().normalize_or_zero();
+                    let uphill = -f32::min(wish_dir.xz().dot(downhill), 0.0);
+                    wish_dir += Vec3::new(downhill.x, 0.0, downhill.y) * uphill;
+                }
+                // Unlike the ground, only the wished speed is capped here, so turning while strafing builds speed
+                accelerate(wish_dir, wish_speed, config.air_accel * config.air_control, dt, &mut end_vel);
+                end_vel.y -= gravity * dt;
+                // Optionally steering may not take us past the max air speed, but speed we jumped with is kept
+                if config.max_air_speed > 0.0 {
+                    let speed_limit = f32::max(lateral_speed, config.max_air_speed);
+                    let air_speed = end_vel.xz().length();
+                    if air_speed > speed_limit {
+                        let ratio = speed_limit / air_speed;
+                        end_vel.x *= ratio;
+                        end_vel.z *= ratio;
                     }
+                }
 
-                    // At this point our collider may be intersecting with the ground
-                    // Fix up our collider by offsetting it to be flush with the ground
-                    // if end_vel.y < -1e6 {
-                    //     if let Some(ground_hit) = ground_hit {
-                    //         let normal = Vec3::from(*ground_hit.normal2);
-                    //         next_translation += normal * ground_hit.toi;
-                    //     }
-                    // }
-
-                    controller.velocity = end_vel;
-                    vel.linvel = (init_vel + end_vel) * 0.5;
+                let in_coyote_time = !controller.has_jumped && controller.air_time <= config.coyote_time;
+                if jump_pressed && in_coyote_time {
+                    jump = true;
+                } else if jump_pressed && controller.air_jumps_used < config.air_jumps {
+                    controller.air_jumps_used += 1;
+                    jump = true;
                 }
             }
+            if jump {
+                // Simulate one update ahead, since this is an instant velocity change
+                init_vel.y = jump_speed;
+                end_vel.y = init_vel.y - gravity * dt;
+                controller.has_jumped = true;
+            }
+
+            // At this point our collider may be intersecting with the ground
+            // Fix up our collider by offsetting it to be flush with the ground
+            // if end_vel.y < -1e6 {
+            //     if let Some(ground_hit) = ground_hit {
+            //         let normal = Vec3::from(*ground_hit.normal2);
+            //         next_translation += normal * ground_hit.toi;
+            //     }
+            // }
+
+            controller.velocity = end_vel;
+            let mut move_vel = (init_vel + end_vel) * 0.5;
+            if env.grounded && !jump {
+                // Follow the slope, so walking up a hill is not slowed by pushing into it and walking down does not launch us
+                if let Some(normal) = env.ground_normal {
+                    move_vel.y -= (normal.x * move_vel.x + normal.z * move_vel.z) / normal.y;
+                }
+            }
+            move_vel
         }
     }
 }
 
-fn look_quat(pitch: f32, yaw: f32) -> Quat {
+pub fn look_quat(pitch: f32, yaw: f32) -> Quat {
     Quat::from_euler(EulerRot::ZYX, 0.0, yaw, pitch)
 }
 
+/// Eases a linear zero to one progress so transitions start and end gently
+pub fn smoothstep(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
 fn friction(lateral_speed: f32, friction: f32, stop_speed: f32, dt: f32, velocity: &mut Vec3) {
     let control = f32::max(lateral_speed, stop_speed);
     let drop = control * friction * dt;
@@ -240,6 +530,20 @@ fn accelerate(wish_dir: Vec3, wish_speed: f32, accel: f32, dt: f32, velocity: &m
     velocity.z += wish_dir.z;
 }
 
+/// Noclip flies through everything, so the collider is only enabled while on the ground
+pub fn noclip_collider_sys(
+    mut commands: Commands,
+    query: Query<(Entity, &PlayerController, Has<ColliderDisabled>)>,
+) {
+    for (entity, controller, is_disabled) in query.iter() {
+        match (&controller.move_mode, is_disabled) {
+            (MoveMode::Noclip, false) => { commands.entity(entity).insert(ColliderDisabled); }
+            (MoveMode::Ground, true) => { commands.entity(entity).remove::<ColliderDisabled>(); }
+            _ => {}
+        }
+    }
+}
+
 // ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
 // ██╔══██╗██╔════╝████╗  ██║██╔══██╗██╔════╝██╔══██╗
 // ██████╔╝█████╗  ██╔██╗ ██║██║  ██║█████╗  ██████╔╝
@@ -247,17 +551,50 @@ fn accelerate(wish_dir: Vec3, wish_speed: f32, accel: f32, dt: f32, velocity: &m
 // ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
 // ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
 
+/// Camera that follows a player, the field of view is kept up to date by [`render_player_camera_sys`]
+pub fn render_player_camera(config: &Config, player_id: u8) -> impl Bundle {
+    let projection = Projection::Perspective(PerspectiveProjection { fov: config.hip_fov(), ..default() });
+    (Camera3dBundle { projection, ..default() }, config.fog_settings(), SpatialListener::new(EAR_GAP), RenderPlayer(player_id))
+}
+
+/// Kept in step with the config since the render distance and fog can change while playing
+pub fn render_fog_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut fog_query: Query<&mut FogSettings, With<RenderPlayer>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for mut fog in fog_query.iter_mut() {
+        *fog = config.fog_settings();
+    }
+}
+
+/// The only place the field of view is written, so settings and aiming down sights do not fight over it
 pub fn render_player_camera_sys(
-    logical_query: Query<(&Transform, &PlayerController, &LogicalPlayer), With<LogicalPlayer>>,
-    mut render_query: Query<(&mut Transform, &RenderPlayer), Without<LogicalPlayer>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    fixed_time: Res<Time<Fixed>>,
+    logical_query: Query<RenderPlayerQuery, With<LogicalPlayer>>,
+    mut render_query: Query<(&mut Transform, &mut Projection, &RenderPlayer), Without<LogicalPlayer>>,
 ) {
-    for (logical_transform, controller, logical_player_id) in logical_query.iter() {
-        for (mut render_transform, render_player_id) in render_query.iter_mut() {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let overstep = fixed_time.overstep_percentage();
+    for (logical_transform, interpolation, controller, input, logical_player_id, ads) in logical_query.iter() {
+        for (mut render_transform, mut projection, render_player_id) in render_query.iter_mut() {
             if logical_player_id.0 != render_player_id.0 {
                 continue;
             }
-            render_transform.translation = logical_transform.translation + Vec3::Y * 2.0;
-            render_transform.rotation = look_quat(controller.pitch, controller.yaw);
+            let position = interpolation.map_or(logical_transform.translation, |interpolation| {
+                interpolation.previous.lerp(interpolation.current, overstep) + interpolation.correction
+            });
+            render_transform.translation = position + Vec3::Y * controller.eye_height();
+            // Look comes straight from input every frame so aiming never waits on a tick
+            render_transform.rotation = look_quat(input.pitch, input.yaw);
+            if let Projection::Perspective(perspective) = projection.as_mut() {
+                let ads_progress = ads.map_or(0.0, |ads| smoothstep(ads.progress));
+                let hip_fov = config.hip_fov();
+                perspective.fov = hip_fov + (config.ads_fov_degrees.to_radians() - hip_fov) * ads_progress;
+            }
         }
     }
 }
@@ -307,4 +644,59 @@ pub fn render_player_camera_sys(
 //             }
 //         }
 //     }
-// }
\ No newline at end of file
+// }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::{CommandQueue, RunSystemOnce};
+
+    use crate::MAX_FOV_DEGREES;
+
+    use super::*;
+
+    fn camera_fov(config: Config, ads: Option<AimDownSights>) -> f32 {
+        let mut configs = Assets::<Config>::default();
+        let config_state = ConfigState { handle: configs.add(config) };
+        let mut app = App::new();
+        app.insert_resource(configs)
+            .insert_resource(config_state)
+            .init_resource::<Time<Fixed>>();
+        let logical = app.world.spawn((Transform::default(), PlayerController::default(), PlayerInput::default(), LogicalPlayer(0))).id();
+        if let Some(ads) = ads {
+            app.world.entity_mut(logical).insert(ads);
+        }
+        let camera = app.world.spawn((Transform::default(), Projection::Perspective(default()), RenderPlayer(0))).id();
+        app.world.run_system_once(render_player_camera_sys);
+        let Projection::Perspective(perspective) = app.world.get::<Projection>(camera).unwrap() else { unreachable!() };
+        perspective.fov
+    }
+
+    #[test]
+    fn config_fov_sets_the_projection() {
+        for fov_degrees in [70.0, 90.0, 110.0] {
+            let fov = camera_fov(Config { fov_degrees, ..default() }, None);
+            assert!((fov - fov_degrees.to_radians()).abs() < 1e-6, "{} gave {}", fov_degrees, fov.to_degrees());
+        }
+        let clamped = camera_fov(Config { fov_degrees: 150.0, ..default() }, None);
+        assert!((clamped - MAX_FOV_DEGREES.to_radians()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn players_spawn_on_the_ground_outside_debug() {
+        let config = Config { debug: false, ..default() };
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let player = spawn_player(&mut Commands::new(&mut queue, &world), &config, SpawnPointConfig::default(), 0);
+        queue.apply(&mut world);
+        world.run_system_once(noclip_collider_sys);
+        assert_eq!(world.get::<PlayerController>(player).unwrap().move_mode, MoveMode::Ground);
+        assert!(!world.entity(player).contains::<ColliderDisabled>());
+    }
+
+    #[test]
+    fn aiming_overrides_the_config_fov() {
+        let config = Config { fov_degrees: 90.0, ads_fov_degrees: 40.0, ..default() };
+        let fov = camera_fov(config, Some(AimDownSights { progress: 1.0 }));
+        assert!((fov - 40f32.to_radians()).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,189 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+use thiserror::Error;
+
+use crate::{Chunk, ChunkMeshStats, ConfigAssetLoader, Config, FrozenTerrain, KeyBindings, load_config_sys, new_chunk_mesh, voxel_polygonize_system, VoxelsPlugin, write_chunk_obj};
+
+/// Frames to wait for every chunk to mesh before giving up, the config and pipeline take a few to be ready
+const HEADLESS_MAX_FRAMES: u32 = 600;
+
+#[derive(Debug, Error)]
+pub enum HeadlessError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Usage: qgame --headless [chunk count] [obj path]")]
+    Usage,
+    #[error("Exited before meshing finished")]
+    Exited,
+    #[error("Only {meshed} of {chunks} chunks meshed in {frames} frames")]
+    TimedOut { meshed: usize, chunks: usize, frames: u32 },
+}
+
+/// Meshes chunks without a window then exits, for checking terrain output without playing
+#[derive(Resource, Debug)]
+pub struct Headless {
+    pub chunks: usize,
+    /// Every chunk is written here as one OBJ once they have all meshed
+    pub obj_path: Option<PathBuf>,
+    pub frames: u32,
+    /// Start of the latest frame that meshed, which is the one that finishes
+    pub meshing_started: Option<Instant>,
+    /// Left for whoever ran the app to report, since exiting carries no status
+    pub result: Option<Result<HeadlessReport, HeadlessError>>,
+}
+
+/// Throughput of the frame that meshed every chunk, startup is left out
+#[derive(Clone, Copy, Debug)]
+pub struct HeadlessReport {
+    pub chunks: usize,
+    pub triangles: usize,
+    pub meshing: Duration,
+}
+
+impl Headless {
+    pub fn new(chunks: usize) -> Self {
+        Self { chunks, obj_path: None, frames: 0, meshing_started: None, result: None }
+    }
+
+    /// Arguments after `--headless`
+    pub fn from_args(mut args: impl Iterator<Item=std::string::String>) -> Result<Self, HeadlessError> {
+        let chunks = match args.next() {
+            Some(count) => count.parse().ok().filter(|&count| count > 0).ok_or(HeadlessError::Usage)?,
+            None => 1,
+        };
+        let obj_path = args.next().map(PathBuf::from);
+        match args.next() {
+            Some(_) => Err(HeadlessError::Usage),
+            None => Ok(Self { obj_path, ..Self::new(chunks) }),
+        }
+    }
+}
+
+impl HeadlessReport {
+    pub fn chunks_per_second(&self) -> f64 {
+        self.chunks as f64 / self.meshing.as_secs_f64()
+    }
+
+    pub fn triangles_per_second(&self) -> f64 {
+        self.triangles as f64 / self.meshing.as_secs_f64()
+    }
+}
+
+impl fmt::Display for HeadlessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Meshed {} chunks into {} triangles in {:.2?} ({:.0} chunks/s, {:.0} triangles/s)",
+               self.chunks, self.triangles, self.meshing, self.chunks_per_second(), self.triangles_per_second())
+    }
+}
+
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .insert_resource(FrozenTerrain)
+            .add_systems(Startup, spawn_headless_chunks_sys)
+            .add_systems(PreUpdate, start_headless_meshing_sys.before(voxel_polygonize_system))
+            .add_systems(Update, finish_headless_sys);
+    }
+}
+
+/// Only what meshing needs, there is no window so the app is driven by a plain loop until meshing finishes
+pub fn run_headless(headless: Headless) -> Result<HeadlessReport, HeadlessError> {
+    let mut app = App::new();
+    app
+        .insert_resource(headless)
+        .add_plugins((
+            DefaultPlugins
+                .set(WindowPlugin { primary_window: None, exit_condition: ExitCondition::DontExit, close_when_requested: false })
+                .disable::<WinitPlugin>(),
+            ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)),
+            VoxelsPlugin,
+            HeadlessPlugin,
+        ))
+        .register_asset_loader(ConfigAssetLoader)
+        .init_asset::<Config>()
+        .init_resource::<KeyBindings>()
+        .add_systems(Startup, load_config_sys);
+    app.run();
+    app.world.remove_resource::<Headless>().and_then(|headless| headless.result)
+        .unwrap_or(Err(HeadlessError::Exited))
+}
+
+/// In a row along x, so the OBJ shows them side by side
+fn spawn_headless_chunks_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    headless: Res<Headless>,
+) {
+    for x in 0..headless.chunks {
+        let chunk = Chunk::new(IVec3::new(x as i32, 0, 0));
+        commands.spawn((
+            TransformBundle::from(Transform::from_translation(chunk.origin())),
+            meshes.add(new_chunk_mesh()),
+            chunk,
+        ));
+    }
+}
+
+fn start_headless_meshing_sys(mut headless: ResMut<Headless>) {
+    if headless.result.is_none() {
+        headless.meshing_started = Some(Instant::now());
+    }
+}
+
+type HeadlessChunkQuery<'a> = (&'a GlobalTransform, &'a Handle<Mesh>, Option<&'a ChunkMeshStats>);
+
+fn finish_headless_sys(
+    mut headless: ResMut<Headless>,
+    meshes: Res<Assets<Mesh>>,
+    chunk_query: Query<HeadlessChunkQuery, With<Chunk>>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    if headless.result.is_some() { return; }
+    headless.frames += 1;
+
+    let meshed = chunk_query.iter().filter(|(_, _, stats)| stats.is_some()).count();
+    let result = if meshed >= headless.chunks {
+        let triangles = chunk_query.iter().filter_map(|(_, _, stats)| stats).map(|stats| stats.triangles).sum();
+        let meshing = headless.meshing_started.map_or(Duration::ZERO, |started| started.elapsed());
+        let report = HeadlessReport { chunks: meshed, triangles, meshing };
+        info!("{}", report);
+        match &headless.obj_path {
+            Some(path) => {
+                let chunks = chunk_query.iter().filter_map(|(transform, mesh, _)| Some((transform, meshes.get(mesh)?)));
+                write_obj(path, chunks).map(|_| info!("Wrote {}", path.display()))
+            }
+            None => Ok(()),
+        }.map(|_| report)
+    } else if headless.frames >= HEADLESS_MAX_FRAMES {
+        Err(HeadlessError::TimedOut { meshed, chunks: headless.chunks, frames: headless.frames })
+    } else {
+        return;
+    };
+    headless.result = Some(result);
+    exit_events.send(AppExit);
+}
+
+/// Every chunk in one file, each in its own place in the world
+fn write_obj<'a>(path: &Path, chunks: impl Iterator<Item=(&'a GlobalTransform, &'a Mesh)>) -> Result<(), HeadlessError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let mut first_vertex = 1;
+    for (transform, mesh) in chunks {
+        first_vertex += write_chunk_obj(&mut out, transform, mesh, first_vertex)?;
+    }
+    out.flush()?;
+    Ok(())
+}
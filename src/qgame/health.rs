@@ -0,0 +1,486 @@
+use std::time::Duration;
+
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use bevy_rapier3d::prelude::*;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{AimDownSights, Config, ConfigState, GameRng, gameplay_query_filter, Inventory, LogicalPlayer, MoveMode, player_collider, PlayerController, PlayerInput, Recoil, RngStream, Spread, Stance, TickInterpolation};
+
+/// Spawns buried in terrain are moved to the surface found by casting down from this far above them
+const SPAWN_PROBE_HEIGHT: f32 = 128.0;
+/// Gap left between the surface and a nudged spawn so the player does not start touching the ground
+const SPAWN_SURFACE_CLEARANCE: f32 = 0.05;
+
+#[derive(Component, Debug)]
+pub struct Health {
+    pub current: u16,
+    pub max: u16,
+}
+
+/// Hits this close to the top of a collider count as headshots
+const HEAD_HEIGHT: f32 = 0.5;
+
+/// Players on the same team can only hurt each other with friendly fire on
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Team(pub u8);
+
+impl Team {
+    pub fn for_player(config: &Config, player_id: u8) -> Self {
+        match config.teams {
+            0 => Team(player_id),
+            teams => Team(player_id % teams),
+        }
+    }
+}
+
+/// Sent to hurt something, does nothing if it has no health
+#[derive(Event)]
+pub struct DamageEvent {
+    pub ent: Entity,
+    /// Whoever fired the shot or projectile, none for the world
+    pub attacker: Option<Entity>,
+    pub amount: u16,
+    /// Where it landed in world space
+    pub position: Vec3,
+    /// Multiplies the amount for hits to the head. Direct hits like bullets can be headshots, blasts leave this out
+    pub headshot_factor: Option<f32>,
+}
+
+/// Sent for damage someone dealt that landed on something still alive, for feedback like hit markers
+#[derive(Event)]
+pub struct HitEvent {
+    /// What was hit
+    pub ent: Entity,
+    /// Who dealt it, so feedback only shows for our own hits
+    pub attacker: Entity,
+    pub position: Vec3,
+    pub amount: u16,
+    pub headshot: bool,
+    pub kill: bool,
+    /// Landed on a teammate with friendly fire off, so nothing was taken
+    pub friendly: bool,
+}
+
+/// Sent once when something's health reaches zero
+#[derive(Event)]
+pub struct DeathEvent {
+    pub ent: Entity,
+}
+
+/// Present while dead, tracks how long until we respawn
+#[derive(Component, Default, Debug)]
+pub struct Dead {
+    pub dur: Duration,
+    /// Set once the respawn mode lets us come back this frame
+    pub can_respawn: bool,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RespawnMode {
+    /// Back on the next frame
+    Instant,
+    /// Everyone waits out the respawn delay on their own
+    #[default]
+    Individual,
+    /// Everyone dead comes back together each wave interval,
+    /// so dying just before a wave is a short wait and just after is a full one
+    Wave,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DamageNumberMode {
+    /// A number for every hit
+    PerHit,
+    /// Hits on the same target add up into one number until the window passes without another
+    #[default]
+    Aggregated,
+}
+
+/// Shared clock all wave respawns go off of
+#[derive(Resource, Default, Debug)]
+pub struct RespawnWave {
+    pub elapsed: Duration,
+}
+
+/// Tags an entity placed in the scene as somewhere players can appear, on top of the ones in the config.
+/// Facing comes from the yaw of its rotation
+#[derive(Component)]
+pub struct SpawnPoint;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnPointConfig {
+    pub translation: Vec3,
+    /// Radians, the way the player faces on spawning
+    pub yaw: f32,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpawnSelection {
+    /// Always the first one that is clear
+    #[default]
+    First,
+    Random,
+    /// Each spawn takes the one after the last, so players joining together do not stack up
+    RoundRobin,
+}
+
+/// Which spawn round robin selection hands out next
+#[derive(Resource, Default, Debug)]
+pub struct SpawnRotation(pub usize);
+
+/// Picks where a player should appear, used for joining and respawning alike
+#[derive(SystemParam)]
+pub struct SpawnPicker<'w, 's> {
+    config: Res<'w, Assets<Config>>,
+    config_state: Res<'w, ConfigState>,
+    physics_context: Res<'w, RapierContext>,
+    rotation: ResMut<'w, SpawnRotation>,
+    rng: ResMut<'w, GameRng>,
+    spawn_query: Query<'w, 's, &'static Transform, (With<SpawnPoint>, Without<LogicalPlayer>)>,
+}
+
+impl SpawnPicker<'_, '_> {
+    /// Prefers spawns that are clear, if terrain has grown over all of them the chosen one is moved up to the surface
+    pub fn pick(&mut self) -> SpawnPointConfig {
+        let Some(config) = self.config.get(&self.config_state.handle) else { return SpawnPointConfig::default(); };
+        let spawns: Vec<SpawnPointConfig> = config.spawn_points.iter().copied()
+            .chain(self.spawn_query.iter().map(|transform| SpawnPointConfig {
+                translation: transform.translation,
+                yaw: transform.rotation.to_euler(EulerRot::YXZ).0,
+            }))
+            .collect();
+        if spawns.is_empty() {
+            return SpawnPointConfig::default();
+        }
+
+        let start = match config.spawn_selection {
+            SpawnSelection::First => 0,
+            SpawnSelection::Random => self.rng.stream(RngStream::Spawns).gen_range(0..spawns.len()),
+            SpawnSelection::RoundRobin => {
+                let start = self.rotation.0 % spawns.len();
+                self.rotation.0 = start + 1;
+                start
+            }
+        };
+        let clear_spawn = (0..spawns.len())
+            .map(|offset| spawns[(start + offset) % spawns.len()])
+            .find(|spawn| self.is_clear(spawn.translation));
+        clear_spawn.unwrap_or_else(|| {
+            let mut spawn = spawns[start];
+            spawn.translation = self.surface_above(spawn.translation);
+            spawn
+        })
+    }
+
+    fn is_clear(&self, translation: Vec3) -> bool {
+        let collider = player_collider(Stance::Standing);
+        self.physics_context.intersection_with_shape(translation, Quat::IDENTITY, &collider, gameplay_query_filter()).is_none()
+    }
+
+    /// Top of whatever is below the probe height over this spot, or the spot itself if there is nothing
+    fn surface_above(&self, translation: Vec3) -> Vec3 {
+        let origin = translation + Vec3::Y * SPAWN_PROBE_HEIGHT;
+        match self.physics_context.cast_ray(origin, -Vec3::Y, SPAWN_PROBE_HEIGHT * 2.0, true, gameplay_query_filter()) {
+            Some((_, toi)) => origin - Vec3::Y * (toi - SPAWN_SURFACE_CLEARANCE),
+            None => translation,
+        }
+    }
+}
+
+impl DamageEvent {
+    /// How much this takes off, scaled up for headshots
+    pub fn scaled_amount(&self, headshot: bool) -> u16 {
+        match self.headshot_factor {
+            Some(factor) if headshot => (self.amount as f32 * factor).round() as u16,
+            _ => self.amount,
+        }
+    }
+}
+
+impl Health {
+    pub fn new(max: u16) -> Self {
+        Self { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+
+    /// Returns true if this damage is what killed us
+    pub fn damage(&mut self, amount: u16) -> bool {
+        let was_alive = !self.is_dead();
+        self.current = self.current.saturating_sub(amount);
+        was_alive && self.is_dead()
+    }
+}
+
+// ██╗      ██████╗  ██████╗ ██╗ ██████╗
+// ██║     ██╔═══██╗██╔════╝ ██║██╔════╝
+// ██║     ██║   ██║██║  ███╗██║██║
+// ██║     ██║   ██║██║   ██║██║██║
+// ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
+// ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
+
+type RespawnPlayerQuery<'a> = (
+    &'a mut Transform, &'a mut Velocity, &'a mut PlayerController, &'a mut PlayerInput, &'a mut Inventory,
+    &'a mut Recoil, &'a mut Spread, &'a mut AimDownSights, &'a mut TickInterpolation,
+);
+
+pub fn damage_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut damage_events: EventReader<DamageEvent>,
+    mut death_events: EventWriter<DeathEvent>,
+    mut hit_events: EventWriter<HitEvent>,
+    team_query: Query<&Team>,
+    mut health_query: Query<(&mut Health, Option<(&Collider, &GlobalTransform)>)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for damage in damage_events.read() {
+        let Ok((mut health, body)) = health_query.get_mut(damage.ent) else { continue; };
+        if health.is_dead() { continue; }
+
+        let headshot = damage.headshot_factor.is_some() && body.is_some_and(|(collider, transform)| {
+            let top = transform.translation().y + collider.raw.compute_local_aabb().maxs.y;
+            damage.position.y >= top - HEAD_HEIGHT
+        });
+        // Only blasts can reach whoever fired them, shots skip the shooter
+        if damage.attacker == Some(damage.ent) {
+            if !config.explosive_self_damage { continue; }
+        } else if !config.friendly_fire {
+            let teammates = damage.attacker
+                .and_then(|attacker| team_query.get(attacker).ok())
+                .is_some_and(|team| team_query.get(damage.ent).ok() == Some(team));
+            if teammates {
+                // Only someone can be a teammate, so there is always an attacker here
+                if let Some(attacker) = damage.attacker.filter(|_| config.friendly_hit_marker) {
+                    hit_events.send(HitEvent { ent: damage.ent, attacker, position: damage.position, amount: 0, headshot, kill: false, friendly: true });
+                }
+                continue;
+            }
+        }
+        let amount = damage.scaled_amount(headshot);
+        let kill = health.damage(amount);
+        if kill {
+            death_events.send(DeathEvent { ent: damage.ent });
+        }
+        // Nobody to give feedback to when the world did it
+        if let Some(attacker) = damage.attacker {
+            hit_events.send(HitEvent { ent: damage.ent, attacker, position: damage.position, amount, headshot, kill, friendly: false });
+        }
+    }
+}
+
+pub fn death_sys(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathEvent>,
+) {
+    for death in death_events.read() {
+        commands.entity(death.ent).insert(Dead::default());
+    }
+}
+
+pub fn respawn_timer_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut wave: ResMut<RespawnWave>,
+    mut dead_query: Query<&mut Dead>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    // Keep the wave clock running even with nobody dead so waves stay on a fixed schedule
+    let mut wave_due = false;
+    if config.respawn_mode == RespawnMode::Wave {
+        let interval = Duration::from_secs_f32(config.respawn_wave_interval);
+        wave.elapsed = wave.elapsed.saturating_add(time.delta());
+        if wave.elapsed >= interval {
+            // Carry over the remainder so waves do not drift with the frame rate
+            wave.elapsed = wave.elapsed.saturating_sub(interval);
+            wave_due = true;
+        }
+    }
+
+    for mut dead in dead_query.iter_mut() {
+        dead.dur = dead.dur.saturating_add(time.delta());
+        dead.can_respawn = match config.respawn_mode {
+            RespawnMode::Instant => true,
+            RespawnMode::Individual => dead.dur.as_secs_f32() >= config.respawn_delay,
+            RespawnMode::Wave => wave_due,
+        };
+    }
+}
+
+pub fn respawn_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut commands: Commands,
+    mut spawn_picker: SpawnPicker,
+    mut dead_query: Query<(Entity, &Dead, &mut Health)>,
+    mut player_query: Query<RespawnPlayerQuery, With<LogicalPlayer>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for (ent, dead, mut health) in dead_query.iter_mut() {
+        if !dead.can_respawn { continue; }
+
+        health.current = health.max;
+        commands.entity(ent).remove::<Dead>();
+
+        // Anything that is not a player, like a test dummy, just comes back where it is
+        let Ok((mut transform, mut vel, mut controller, mut input, mut inv, mut recoil, mut spread, mut ads, mut interpolation)) = player_query.get_mut(ent) else { continue; };
+        let spawn = spawn_picker.pick();
+        transform.translation = spawn.translation;
+        input.yaw = spawn.yaw;
+        // Teleporting, so do not blend in from where we died
+        *interpolation = TickInterpolation { previous: transform.translation, current: transform.translation, ..default() };
+        *vel = Velocity::zero();
+        controller.velocity = Vec3::ZERO;
+        // Dying while flying should not bring us back through the floor
+        controller.move_mode = MoveMode::Ground;
+        *recoil = Recoil::default();
+        *spread = Spread::default();
+        *ads = AimDownSights::default();
+        if !config.keep_inventory_on_respawn {
+            for &item_ent in inv.item_ents.0.iter().flatten() {
+                commands.entity(item_ent).despawn_recursive();
+            }
+            *inv = Inventory::with_slots(config.inventory_slots);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::scene::SceneSpawner;
+
+    use crate::{DryFireEvent, FireEvent, Gun, hitscan_sys, ImpactEvent, Item, ItemConfig, ItemRegistry, ItemStateEvent, modify_item_sys, PlayerInputFlags};
+
+    use super::*;
+
+    const SHOT_DAMAGE: u16 = 20;
+    const RIFLE: &str = include_str!("../../assets/items/rifle.item.ron");
+
+    fn damage_app() -> App {
+        let mut configs = Assets::<Config>::default();
+        let handle = configs.add(Config::default());
+        let mut app = App::new();
+        app.insert_resource(configs)
+            .insert_resource(ConfigState { handle })
+            .add_event::<DamageEvent>()
+            .add_event::<DeathEvent>()
+            .add_event::<HitEvent>()
+            .add_systems(Update, damage_sys);
+        app
+    }
+
+    /// Hits a standing dummy at the origin this far below the top of its collider, returns the health it lost
+    fn hit_dummy(depth: f32, headshot_factor: Option<f32>) -> u16 {
+        let mut app = damage_app();
+        let collider = player_collider(Stance::Standing);
+        let top = collider.raw.compute_local_aabb().maxs.y;
+        let dummy = app.world.spawn((Health::new(100), collider, GlobalTransform::IDENTITY)).id();
+        app.world.send_event(DamageEvent {
+            ent: dummy,
+            attacker: None,
+            amount: SHOT_DAMAGE,
+            position: Vec3::Y * (top - depth),
+            headshot_factor,
+        });
+        app.update();
+        let health = app.world.get::<Health>(dummy).unwrap();
+        health.max - health.current
+    }
+
+    #[test]
+    fn body_shot_is_not_scaled() {
+        assert_eq!(hit_dummy(HEAD_HEIGHT * 2.0, Some(2.5)), SHOT_DAMAGE);
+    }
+
+    #[test]
+    fn headshot_is_scaled() {
+        assert_eq!(hit_dummy(HEAD_HEIGHT * 0.5, Some(2.5)), 50);
+    }
+
+    #[test]
+    fn blast_to_the_head_is_not_scaled() {
+        assert_eq!(hit_dummy(HEAD_HEIGHT * 0.5, None), SHOT_DAMAGE);
+    }
+
+    #[test]
+    fn hits_name_their_attacker() {
+        let mut app = damage_app();
+        let shooter = app.world.spawn_empty().id();
+        let dummy = app.world.spawn(Health::new(100)).id();
+        for attacker in [Some(shooter), None] {
+            app.world.send_event(DamageEvent { ent: dummy, attacker, amount: SHOT_DAMAGE, position: Vec3::ZERO, headshot_factor: None });
+        }
+        app.update();
+        let hits = app.world.resource::<Events<HitEvent>>();
+        let attackers: Vec<_> = hits.get_reader().read(hits).map(|hit| hit.attacker).collect();
+        assert_eq!(attackers, [shooter]);
+    }
+
+    /// Pulls the trigger of an equipped rifle with this much in the magazine at a dummy standing in front of it,
+    /// everything from the item to the health goes through physics and the real systems.
+    /// Returns the health the dummy lost and the rounds left
+    fn shoot_dummy(ammo: u16) -> (u16, u16) {
+        let mut app = damage_app();
+        let mut item_configs = Assets::<ItemConfig>::default();
+        let mut registry = ItemRegistry::default();
+        let rifle: ItemConfig = ron::from_str(RIFLE).unwrap();
+        registry.register(rifle.item_props().name.clone(), item_configs.add(rifle));
+        app.add_plugins((MinimalPlugins, RapierPhysicsPlugin::<NoUserData>::default()))
+            .insert_resource(item_configs)
+            .insert_resource(registry)
+            // Only used for colliders built from meshes and scenes
+            .init_resource::<Assets<Mesh>>()
+            .init_resource::<SceneSpawner>()
+            .init_resource::<GameRng>()
+            .add_event::<FireEvent>()
+            .add_event::<DryFireEvent>()
+            .add_event::<ItemStateEvent>()
+            .add_event::<ImpactEvent>()
+            .add_systems(Update, (modify_item_sys, hitscan_sys).chain().before(damage_sys));
+
+        let shooter = app.world.spawn((TransformBundle::default(), PlayerController::default(), PlayerInput::default(), Spread::default())).id();
+        let item_ent = app.world.spawn((
+            Item { name: "rifle".into(), amount: 1, state_name: "idle".into(), state_dur: Duration::ZERO, inv_ent: shooter, inv_slot: 0 },
+            Gun { ammo, ammo_in_reserve: 0 },
+        )).id();
+        let mut inv = Inventory::with_slots(1);
+        inv.item_ents.0[0] = Some(item_ent);
+        inv.equipped_slot = Some(0);
+        inv.equip_state_name = "equipped".into();
+        app.world.entity_mut(shooter).insert(inv);
+        // Looking down negative z, tall enough that a shot from eye height is never a headshot
+        let dummy = app.world.spawn((
+            Health::new(100),
+            Collider::cuboid(1.0, 4.0, 1.0),
+            TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -5.0)),
+        )).id();
+
+        // Colliders only make it into the physics world at the end of a frame
+        app.update();
+        app.world.get_mut::<PlayerInput>(shooter).unwrap().flags = PlayerInputFlags::Fire.into();
+        app.update();
+
+        let health = app.world.get::<Health>(dummy).unwrap();
+        (health.max - health.current, app.world.get::<Gun>(item_ent).unwrap().ammo)
+    }
+
+    #[test]
+    fn shot_spends_a_round_and_damages_what_it_hits() {
+        // The rifle does 25 to the body
+        assert_eq!(shoot_dummy(30), (25, 29));
+    }
+
+    #[test]
+    fn empty_gun_does_no_damage() {
+        assert_eq!(shoot_dummy(0), (0, 0));
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use bevy::{
+    prelude::*,
+    prelude::shape::{Cube, Quad},
+};
+use rand::{Rng, thread_rng};
+
+use crate::{Chunk, Config, config_loaded, ConfigState, GameState, Health, Map, SurfaceType};
+
+/// Decals sit this far off the surface so they do not flicker against it
+const DECAL_OFFSET: f32 = 0.005;
+/// Meters per second squared pulling particles down, they are not physics bodies
+const PARTICLE_GRAVITY: f32 = 9.81;
+
+/// Sent where a shot hit something
+#[derive(Event)]
+pub struct ImpactEvent {
+    pub ent: Entity,
+    pub position: Vec3,
+    /// Of the surface that was hit, pointing back out of it
+    pub normal: Vec3,
+}
+
+/// Mark left on the world where a shot landed, fades out at the end of its lifetime
+#[derive(Component, Debug)]
+pub struct ImpactDecal {
+    pub age: Duration,
+    pub lifetime: Duration,
+    pub fade: Duration,
+    pub color: Color,
+}
+
+/// Bit of debris thrown off the surface, shrinks away over its lifetime
+#[derive(Component, Debug)]
+pub struct ImpactParticle {
+    pub age: Duration,
+    pub lifetime: Duration,
+    pub velocity: Vec3,
+    /// What it was knocked off of, picks the color
+    pub surface: SurfaceType,
+}
+
+pub struct ImpactPlugin;
+
+impl Plugin for ImpactPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_event::<ImpactEvent>()
+            .add_systems(Update, (
+                (spawn_impact_sys, init_impact_sys).chain().run_if(config_loaded),
+                // Frozen along with everything else when paused
+                (impact_decal_sys, impact_particle_sys).run_if(in_state(GameState::Playing)),
+            ).chain());
+    }
+}
+
+impl SurfaceType {
+    /// Debris thrown off by a hit
+    fn impact_color(self) -> Color {
+        match self {
+            SurfaceType::Grass => Color::rgb(0.25, 0.4, 0.15),
+            SurfaceType::Rock => Color::rgb(0.45, 0.42, 0.4),
+            SurfaceType::Metal => Color::rgb(1.0, 0.8, 0.4),
+        }
+    }
+}
+
+// ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
+// ██╔══██╗██╔════╝████╗  ██║██╔══██╗██╔════╝██╔══██╗
+// ██████╔╝█████╗  ██╔██╗ ██║██║  ██║█████╗  ██████╔╝
+// ██╔══██╗██╔══╝  ██║╚██╗██║██║  ██║██╔══╝  ██╔══██╗
+// ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
+// ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
+
+/// Only the world gets marked, anything with health shows hits its own way
+pub fn spawn_impact_sys(
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut impact_events: EventReader<ImpactEvent>,
+    map_query: Query<&Map>,
+    chunk_query: Query<&Chunk>,
+    health_query: Query<(), With<Health>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let impacts = &config.impacts;
+    // Only for show so they do not need to be deterministic
+    let mut rng = thread_rng();
+    for impact in impact_events.read() {
+        if health_query.contains(impact.ent) { continue; }
+        let normal = impact.normal.normalize_or_zero();
+        if normal == Vec3::ZERO { continue; }
+
+        let surface = map_query.get_single().map_or_else(|_| SurfaceType::default(), |map| map.surface_at(impact.position, &chunk_query));
+        commands.spawn((
+            // Quads face along z
+            TransformBundle::from_transform(Transform::from_translation(impact.position + normal * DECAL_OFFSET)
+                .with_rotation(Quat::from_rotation_arc(Vec3::Z, normal))),
+            ImpactDecal {
+                age: Duration::ZERO,
+                lifetime: Duration::from_secs_f32(impacts.decal_lifetime),
+                fade: Duration::from_secs_f32(impacts.decal_fade.min(impacts.decal_lifetime)),
+                color: impacts.decal_color,
+            },
+        ));
+        for _ in 0..impacts.particle_count {
+            let spray = Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * impacts.particle_spread;
+            commands.spawn((
+                TransformBundle::from_transform(Transform::from_translation(impact.position + normal * impacts.particle_size)),
+                ImpactParticle {
+                    age: Duration::ZERO,
+                    lifetime: Duration::from_secs_f32(impacts.particle_lifetime * rng.gen_range(0.5..=1.0)),
+                    velocity: (normal + spray).normalize_or_zero() * impacts.particle_speed * rng.gen_range(0.5..=1.0),
+                    surface,
+                },
+            ));
+        }
+    }
+}
+
+type NewImpactQuery<'a> = (Entity, Option<&'a ImpactDecal>, Option<&'a ImpactParticle>);
+type NewImpactFilter = Or<(Added<ImpactDecal>, Added<ImpactParticle>)>;
+
+/// Meshes and materials are added once the entities exist, each decal gets its own material so it can fade out on its own
+pub fn init_impact_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    asset_server: Res<AssetServer>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    new_query: Query<NewImpactQuery, NewImpactFilter>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let impacts = &config.impacts;
+    for (impact_ent, decal, particle) in new_query.iter() {
+        let (mesh, material) = match decal {
+            Some(decal) => (
+                Mesh::from(Quad::new(Vec2::splat(impacts.decal_size))),
+                StandardMaterial {
+                    base_color: decal.color,
+                    base_color_texture: impacts.decal_texture.as_ref().map(|path| asset_server.load(path)),
+                    alpha_mode: AlphaMode::Blend,
+                    perceptual_roughness: 1.0,
+                    ..default()
+                },
+            ),
+            None => (
+                Mesh::from(Cube { size: impacts.particle_size }),
+                StandardMaterial {
+                    base_color: particle.map_or_else(SurfaceType::default, |particle| particle.surface).impact_color(),
+                    unlit: true,
+                    ..default()
+                },
+            ),
+        };
+        commands.entity(impact_ent).insert((meshes.add(mesh), materials.add(material), VisibilityBundle::default()));
+    }
+}
+
+pub fn impact_decal_sys(
+    time: Res<Time>,
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut decal_query: Query<(Entity, &mut ImpactDecal, Option<&Handle<StandardMaterial>>)>,
+) {
+    let mut alive = Vec::new();
+    for (decal_ent, mut decal, material) in decal_query.iter_mut() {
+        decal.age = decal.age.saturating_add(time.delta());
+        if decal.age >= decal.lifetime {
+            commands.entity(decal_ent).despawn_recursive();
+            continue;
+        }
+        alive.push((decal.age, decal_ent));
+
+        let fade_start = decal.lifetime - decal.fade;
+        if decal.age < fade_start { continue; }
+        let Some(material) = material.and_then(|material| materials.get_mut(material)) else { continue; };
+        let fade = (decal.age - fade_start).as_secs_f32() / decal.fade.as_secs_f32();
+        material.base_color.set_a(decal.color.a() * (1.0 - fade));
+    }
+
+    // Oldest go first so sustained fire can not pile up marks
+    let Some(max_decals) = config.get(&config_state.handle).map(|config| config.impacts.max_decals as usize) else { return; };
+    if alive.len() > max_decals {
+        alive.sort_unstable_by(|(age, _), (other_age, _)| other_age.cmp(age));
+        for &(_, decal_ent) in &alive[..alive.len() - max_decals] {
+            commands.entity(decal_ent).despawn_recursive();
+        }
+    }
+}
+
+pub fn impact_particle_sys(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut particle_query: Query<(Entity, &mut ImpactParticle, &mut Transform)>,
+) {
+    let dt = time.delta_seconds();
+    for (particle_ent, mut particle, mut transform) in particle_query.iter_mut() {
+        particle.age = particle.age.saturating_add(time.delta());
+        if particle.age >= particle.lifetime {
+            commands.entity(particle_ent).despawn_recursive();
+            continue;
+        }
+        particle.velocity.y -= PARTICLE_GRAVITY * dt;
+        transform.translation += particle.velocity * dt;
+        transform.scale = Vec3::splat(1.0 - particle.age.as_secs_f32() / particle.lifetime.as_secs_f32());
+    }
+}
@@ -1,4 +1,9 @@
-use std::f32::consts::FRAC_PI_2;
+use std::{
+    collections::BTreeMap,
+    f32::consts::{FRAC_PI_2, TAU},
+    fmt,
+    io,
+};
 
 use bevy::{
     asset::{
@@ -7,16 +12,18 @@ use bevy::{
         io::Reader,
         LoadContext,
     },
-    input::mouse::MouseMotion,
+    ecs::system::SystemParam,
+    input::mouse::{MouseMotion, MouseWheel},
     prelude::*,
     reflect::TypePath,
     utils::BoxedFuture,
-    window::CursorGrabMode,
+    window::{CursorGrabMode, WindowFocused},
 };
 use flagset::{flags, FlagSet};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::RonLoaderError;
+use crate::{AimDownSights, CHUNK_SZ, DamageNumberMode, Dead, DEFAULT_INVENTORY_SLOTS, ensure_field, GameState, InvalidField, Inventory, ItemConfig, LocalPlayer, parse_by_extension, RespawnMode, RonLoaderError, SpawnPointConfig, SpawnSelection, Validate};
 
 flags! {
     pub enum PlayerInputFlags: u32 {
@@ -24,11 +31,34 @@ flags! {
         Sprint,
         Fly,
         Fire,
-        Reload
+        Reload,
+        Drop,
+        Interact,
+        Aim,
+        Crouch
     }
 }
 
-#[derive(Component, Default, Debug)]
+/// Flags from a single press rather than a hold, kept until a simulation tick has seen them.
+/// Frames can run without a tick in between, so clearing these every frame could drop a press
+fn latched_flags() -> FlagSet<PlayerInputFlags> {
+    PlayerInputFlags::Fly | PlayerInputFlags::Drop | PlayerInputFlags::Interact
+}
+
+/// Rebinds players have saved, kept outside the assets so a shared config never overwrites them
+const SAVED_BINDINGS_PATH: &str = "bindings.ron";
+
+/// Settings players changed in game, merged over the loaded config so the file only needs what they touched
+pub const USER_CONFIG_PATH: &str = "user.config.toml";
+
+/// Keep pitch just shy of straight up or down so the view never flips
+pub const PITCH_LIMIT: f32 = FRAC_PI_2 - 0.001953125;
+
+/// Any field of view outside this is clamped, narrower feels zoomed in and wider distorts the edges
+pub const MIN_FOV_DEGREES: f32 = 60.0;
+pub const MAX_FOV_DEGREES: f32 = 120.0;
+
+#[derive(Component, Clone, Default, Debug)]
 pub struct PlayerInput {
     pub movement: Vec3,
     pub flags: FlagSet<PlayerInputFlags>,
@@ -37,115 +67,1239 @@ pub struct PlayerInput {
     pub wanted_item_slot: Option<u8>,
 }
 
-#[derive(Asset, Copy, Clone, Debug, PartialEq, Serialize, Deserialize, TypePath)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputBinding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    /// Only ever pressed for the update the wheel turned in, there is nothing to hold
+    Wheel(WheelDirection),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WheelDirection {
+    Up,
+    Down,
+}
+
+/// Which ways the scroll wheel turned this update, gathered once so every system sees the same turn
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WheelInput {
+    pub up: bool,
+    pub down: bool,
+}
+
+/// Stick and button layout for a gamepad, used alongside the keyboard and mouse rather than instead of them
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GamepadBindings {
+    pub jump: GamepadButtonType,
+    pub sprint: GamepadButtonType,
+    pub crouch: GamepadButtonType,
+    pub fire: GamepadButtonType,
+    pub reload: GamepadButtonType,
+    pub interact: GamepadButtonType,
+    pub aim: GamepadButtonType,
+    pub next_slot: GamepadButtonType,
+    pub prev_slot: GamepadButtonType,
+    /// Stick deflection below this fraction is ignored, the rest is rescaled so movement still starts from zero
+    pub move_dead_zone: f32,
+    pub look_dead_zone: f32,
+    /// Radians turned per second with the look stick all the way over
+    pub look_sensitivity: f32,
+    /// Look stick deflection is raised to this power, above one gives finer aim near the center
+    pub look_response_exponent: f32,
+}
+
+/// Whichever was used last decides how prompts show bindings
+#[derive(Resource, Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum InputDevice {
+    #[default]
+    KeyboardMouse,
+    Gamepad,
+}
+
+/// Which actions can share a binding without getting in each other's way
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BindingContext {
+    Always,
+    Walking,
+    Flying,
+}
+
+/// Starts from the config's bindings with any saved rebinds on top, this is what input actually reads
+#[derive(Resource, Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBindings {
+    pub forward: InputBinding,
+    pub back: InputBinding,
+    pub left: InputBinding,
+    pub right: InputBinding,
+    /// Only while flying
+    pub up: InputBinding,
+    /// Only while flying
+    pub down: InputBinding,
+    pub sprint: InputBinding,
+    pub jump: InputBinding,
+    pub crouch: InputBinding,
+    /// Toggles noclip, entering it needs debug on in the config
+    pub fly: InputBinding,
+    pub fire: InputBinding,
+    pub reload: InputBinding,
+    pub drop: InputBinding,
+    pub interact: InputBinding,
+    pub aim: InputBinding,
+    pub dump_meshing_profile: InputBinding,
+    /// Writes the chunk we are standing in out as an OBJ, only with debug on
+    pub export_chunk_mesh: InputBinding,
+    /// Shows or hides the stats overlay, only with debug on
+    pub debug_overlay: InputBinding,
+    /// Detaches the camera to fly around while the player stays put, only with debug on
+    pub spectate: InputBinding,
+    /// Opens the command console, only with debug on
+    pub console: InputBinding,
+    /// Cycle through occupied inventory slots, skipping empty ones
+    pub next_slot: InputBinding,
+    pub prev_slot: InputBinding,
+    /// Back to the slot equipped before this one
+    pub quick_swap: InputBinding,
+    /// Writes the current bindings out so they can be shared
+    pub export_bindings: InputBinding,
+    /// Reads saved bindings back in, like after dropping in a file from someone else
+    pub import_bindings: InputBinding,
+    /// Saves the frame as a PNG in the screenshots folder
+    pub screenshot: InputBinding,
+    /// Held while taking a screenshot to leave the HUD out of it
+    pub screenshot_hide_hud: InputBinding,
+    /// Equips the inventory slot at the same index
+    pub slots: [InputBinding; 10],
+    pub gamepad: GamepadBindings,
+}
+
+/// Sizes are in logical pixels, opacities multiply the alpha of the color they go with
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CrosshairConfig {
+    pub color: Color,
+    pub line_length: f32,
+    pub line_thickness: f32,
+    /// Distance from the center to each line before any spread is added
+    pub gap: f32,
+    pub line_opacity: f32,
+    /// Zero hides the dot
+    pub dot_size: f32,
+    pub dot_opacity: f32,
+    /// Zero hides the outline
+    pub outline_thickness: f32,
+    pub outline_color: Color,
+    pub outline_opacity: f32,
+}
+
+/// Marks and debris left where shots hit the world
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImpactConfig {
+    /// Asset path of the mark, tinted by the color, a plain square without one
+    pub decal_texture: Option<std::string::String>,
+    pub decal_color: Color,
+    /// Width in meters
+    pub decal_size: f32,
+    /// Seconds a mark stays, the last of which are spent fading out
+    pub decal_lifetime: f32,
+    pub decal_fade: f32,
+    /// Oldest marks are removed past this
+    pub max_decals: u16,
+    /// Per hit, zero throws none
+    pub particle_count: u8,
+    pub particle_size: f32,
+    /// Meters per second off the surface
+    pub particle_speed: f32,
+    /// How far particles stray from straight out of the surface, zero sprays them all along the normal
+    pub particle_spread: f32,
+    pub particle_lifetime: f32,
+}
+
+/// Target dummies for testing weapons against, only the server or a local game has them
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    /// Kept alive at all times, killed ones are replaced
+    pub count: u8,
+    pub max_health: u16,
+    /// Meters per second
+    pub speed: f32,
+    /// Meters from the first spawn point that bots appear at
+    pub spawn_distance: f32,
+}
+
+/// Vertical extent of the world in chunks, so nothing is generated forever up or down
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapBounds {
+    /// Lowest chunk y that is generated, everything under it is bedrock with a solid floor at its bottom
+    pub min_chunk_y: i32,
+    /// Highest chunk y that is generated, everything over it is open air
+    pub max_chunk_y: i32,
+}
+
+/// Tunnels carved out under the surface by 3D noise, depths are meters below zero
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaveConfig {
+    pub enabled: bool,
+    /// Noise cycles per meter, higher makes smaller and more frequent caves
+    pub frequency: f32,
+    /// Noise above this is hollowed out, from -1 to 1 where higher leaves less open space
+    pub threshold: f32,
+    pub min_depth: f32,
+    pub max_depth: f32,
+}
+
+/// Top down view of the loaded chunks in the top right corner
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinimapConfig {
+    pub enabled: bool,
+    /// Width and height in pixels
+    pub size: f32,
+    /// Meters from the player to the edge of the map, lower zooms in
+    pub range: f32,
+    /// Pixels in from the right and down from the top of the window
+    pub position: Vec2,
+}
+
+/// Health bars over bots and other players, with their names above
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NameplateConfig {
+    pub enabled: bool,
+    pub show_names: bool,
+    /// Meters above the target's feet
+    pub height: f32,
+    /// Width and height of the bar in pixels
+    pub size: Vec2,
+    /// Meters out that plates stay fully visible, past this they fade out over the fade distance
+    pub fade_start: f32,
+    pub fade_distance: f32,
+    /// Zero hides the bar until its target has taken damage
+    pub full_health_opacity: f32,
+}
+
+/// Distance haze out at the chunk render distance, so chunks streaming in fade in instead of popping
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FogConfig {
+    pub enabled: bool,
+    /// Best kept close to the sky so the end of the world blends into it
+    pub color: Color,
+    /// Share of the render distance the fog thickens over, ending just past the furthest chunks
+    pub density: f32,
+}
+
+/// Procedural motion of the held item, offsets are in meters relative to the camera
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewModelConfig {
+    /// Sideways then vertical swing at full walking speed, one sideways swing takes two footsteps
+    pub bob_amplitude: Vec2,
+    /// Offset per radian per second of looking around, the item trails behind the view
+    pub sway_factor: f32,
+    pub max_sway: f32,
+    /// How quickly sway catches up, higher feels tighter
+    pub sway_smoothing: f32,
+    pub breath_amplitude: f32,
+    /// Breaths per second while idle
+    pub breath_frequency: f32,
+    /// Scales all of the above when fully aimed in, so the sights stay steady
+    pub ads_factor: f32,
+}
+
+#[derive(Asset, Clone, Debug, PartialEq, Serialize, Deserialize, TypePath)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Radians turned per pixel of raw mouse movement, so it feels the same at any frame rate
     pub sensitivity: f32,
-    pub key_forward: KeyCode,
-    pub key_back: KeyCode,
-    pub key_left: KeyCode,
-    pub key_right: KeyCode,
-    pub key_up: KeyCode,
-    pub key_down: KeyCode,
-    pub key_sprint: KeyCode,
-    pub key_jump: KeyCode,
-    pub key_fly: KeyCode,
-    pub key_crouch: KeyCode,
-    pub key_fire: KeyCode,
-    pub key_reload: KeyCode,
+    /// Multiplies sensitivity for turning left and right only
+    pub horizontal_sensitivity_factor: f32,
+    /// Multiplies sensitivity for looking up and down only
+    pub vertical_sensitivity_factor: f32,
+    pub invert_y: bool,
+    /// Seconds for mouse look to catch up to the hand, zero turns smoothing off for one to one input.
+    /// Look always comes from raw mouse motion, this only evens out platforms that deliver it in batches
+    pub mouse_smoothing: f32,
+    pub bindings: KeyBindings,
+    pub inventory_slots: u8,
+    /// Simulation ticks per second, gameplay runs at this rate regardless of frame rate
+    pub tick_rate: f32,
+    /// Most ticks run in one frame to catch up after a stall, past this the simulation slows down instead
+    pub max_catchup_ticks: u8,
+    /// Seeds all gameplay randomness like spread and spawns, so a recorded run can be replayed
+    pub rng_seed: u64,
+    pub validate_chunk_meshes: bool,
+    /// Enables developer commands like noclip
+    pub debug: bool,
+    /// Times each chunk meshing stage for the debug overlay, the dump key writes it out to a file
+    pub profile_chunk_meshing: bool,
+    /// Chunks with their center further than this many meters from the camera are hidden, zero to draw all of them
+    pub chunk_render_distance: f32,
+    /// Most chunk colliders swapped in per frame once cooked in the background, nearest first
+    pub chunk_collider_budget: u16,
+    /// Faces meeting at a sharper angle than this keep a hard edge, raise it if noisy terrain looks faceted
+    pub normal_crease_angle_degrees: f32,
+    pub interact_range: f32,
+    pub auto_pickup: bool,
+    pub queue_weapon_switch: bool,
+    /// Vertical field of view when not aiming, clamped between 60 and 120
+    pub fov_degrees: f32,
+    pub ads_fov_degrees: f32,
+    /// Seconds to go fully in or out of aiming down sights
+    pub ads_duration: f32,
+    /// Sensitivity multiplier when fully aimed in
+    pub ads_sensitivity_factor: f32,
+    pub max_health: u16,
+    /// Players are split across this many teams by id, zero puts everyone on a team of their own
+    pub teams: u8,
+    /// Whether shots and blasts hurt teammates
+    pub friendly_fire: bool,
+    /// Blocked hits on teammates still show a hit marker, in their own color
+    pub friendly_hit_marker: bool,
+    pub damage_numbers: DamageNumberMode,
+    /// Seconds without a hit before an aggregated damage number stops adding up
+    pub damage_number_window: f32,
+    /// Whether our own blasts hurt us
+    pub explosive_self_damage: bool,
+    pub respawn_mode: RespawnMode,
+    /// Seconds spent dead before respawning in individual mode
+    pub respawn_delay: f32,
+    /// Seconds between respawn waves in wave mode
+    pub respawn_wave_interval: f32,
+    pub keep_inventory_on_respawn: bool,
+    /// Where players appear when joining and respawning, spawn point entities in the scene are used as well
+    pub spawn_points: Vec<SpawnPointConfig>,
+    pub spawn_selection: SpawnSelection,
+    /// Flying speed in noclip, sprinting multiplies it like walking
+    pub noclip_speed: f32,
+    /// Top walking speed on the ground, before item and stance factors
+    pub max_speed: f32,
+    /// How quickly ground movement reaches the wished velocity
+    pub ground_accel: f32,
+    /// How quickly air movement turns towards the wished direction, strafe jumping gains speed from this
+    pub air_accel: f32,
+    /// How quickly lateral speed bleeds off on the ground
+    pub friction: f32,
+    /// Scales air acceleration, zero for no steering mid-air and one for the full amount
+    pub air_control: f32,
+    /// Fastest air steering can take lateral speed, does not slow down faster jumps, zero for no limit
+    pub max_air_speed: f32,
+    /// Downward acceleration while airborne, in meters per second squared
+    pub gravity: f32,
+    /// Meters a jump from flat ground peaks at
+    pub jump_height: f32,
+    /// How far below the capsule ground is still found, too small and we lose contact going down slopes
+    pub ground_check_distance: f32,
+    /// Steepest surface that counts as ground to stand and jump on, anything steeper we slide down
+    pub max_ground_slope_degrees: f32,
+    /// Seconds after walking off a ledge that jumping still works as if grounded
+    pub coyote_time: f32,
+    /// Extra jumps allowed before landing again, zero for none
+    pub air_jumps: u8,
+    /// Walk speed multiplier while sprinting
+    pub sprint_speed_factor: f32,
+    /// Seconds of sprinting before running out, zero for unlimited
+    pub max_stamina: f32,
+    /// Seconds of stamina recovered per second of not sprinting
+    pub stamina_regen_rate: f32,
+    /// Walk speed multiplier while crouched
+    pub crouch_speed_factor: f32,
+    /// Seconds to go fully in or out of a crouch
+    pub crouch_duration: f32,
+    /// Scales every sound, zero mutes the game
+    pub master_volume: f32,
+    pub footstep_volume: f32,
+    pub weapon_volume: f32,
+    /// Meters walked on the ground between footsteps, so faster movement steps more often
+    pub footstep_stride: f32,
+    pub crosshair: CrosshairConfig,
+    pub view_model: ViewModelConfig,
+    pub impacts: ImpactConfig,
+    pub minimap: MinimapConfig,
+    pub nameplates: NameplateConfig,
+    pub fog: FogConfig,
+    pub caves: CaveConfig,
+    pub map_bounds: MapBounds,
+    pub bots: BotConfig,
 }
 
 #[derive(Resource)]
 pub struct ConfigState {
-    pub(crate) handle: Handle<Config>,
+    pub handle: Handle<Config>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
-            key_forward: KeyCode::W,
-            key_back: KeyCode::S,
-            key_left: KeyCode::A,
-            key_right: KeyCode::D,
-            key_up: KeyCode::Q,
-            key_down: KeyCode::E,
-            key_sprint: KeyCode::ShiftLeft,
-            key_jump: KeyCode::Space,
-            key_fly: KeyCode::F,
-            key_crouch: KeyCode::ControlLeft,
-            key_fire: KeyCode::Q,
             sensitivity: 0.5,
-            key_reload: KeyCode::R,
+            horizontal_sensitivity_factor: 1.0,
+            vertical_sensitivity_factor: 1.0,
+            invert_y: false,
+            mouse_smoothing: 0.0,
+            bindings: KeyBindings::default(),
+            inventory_slots: DEFAULT_INVENTORY_SLOTS,
+            tick_rate: 60.0,
+            max_catchup_ticks: 5,
+            rng_seed: 0,
+            validate_chunk_meshes: false,
+            debug: cfg!(debug_assertions),
+            profile_chunk_meshing: false,
+            chunk_render_distance: 512.0,
+            chunk_collider_budget: 4,
+            normal_crease_angle_degrees: 50.0,
+            interact_range: 3.0,
+            auto_pickup: false,
+            queue_weapon_switch: true,
+            fov_degrees: 60.0,
+            ads_fov_degrees: 30.0,
+            ads_duration: 0.2,
+            ads_sensitivity_factor: 0.6,
+            max_health: 100,
+            teams: 0,
+            friendly_fire: false,
+            friendly_hit_marker: true,
+            damage_numbers: DamageNumberMode::default(),
+            damage_number_window: 0.5,
+            explosive_self_damage: true,
+            respawn_mode: RespawnMode::default(),
+            respawn_delay: 3.0,
+            respawn_wave_interval: 10.0,
+            keep_inventory_on_respawn: false,
+            spawn_points: vec![SpawnPointConfig { translation: Vec3::new(4.0, 18.0, 4.0), yaw: TAU * 5.0 / 8.0 }],
+            spawn_selection: SpawnSelection::default(),
+            noclip_speed: 10.0,
+            max_speed: 10.0,
+            ground_accel: 10.0,
+            air_accel: 20.0,
+            friction: 10.0,
+            air_control: 1.0,
+            max_air_speed: 0.0,
+            gravity: 23.0,
+            jump_height: 1.6,
+            ground_check_distance: 0.125,
+            max_ground_slope_degrees: 50.0,
+            coyote_time: 0.1,
+            air_jumps: 0,
+            sprint_speed_factor: 3.0,
+            max_stamina: 0.0,
+            stamina_regen_rate: 0.5,
+            crouch_speed_factor: 0.5,
+            crouch_duration: 0.15,
+            master_volume: 1.0,
+            footstep_volume: 0.4,
+            weapon_volume: 0.8,
+            footstep_stride: 2.0,
+            crosshair: CrosshairConfig::default(),
+            view_model: ViewModelConfig::default(),
+            impacts: ImpactConfig::default(),
+            minimap: MinimapConfig::default(),
+            nameplates: NameplateConfig::default(),
+            fog: FogConfig::default(),
+            caves: CaveConfig::default(),
+            map_bounds: MapBounds::default(),
+            bots: BotConfig::default(),
         }
     }
 }
 
-fn get_pressed(key_input: &Res<Input<KeyCode>>, key: KeyCode) -> f32 {
-    if key_input.pressed(key) {
-        1.0
-    } else {
-        0.0
+impl Default for KeyBindings {
+    fn default() -> Self {
+        use InputBinding::*;
+        Self {
+            forward: Key(KeyCode::W),
+            back: Key(KeyCode::S),
+            left: Key(KeyCode::A),
+            right: Key(KeyCode::D),
+            up: Key(KeyCode::Space),
+            down: Key(KeyCode::ControlLeft),
+            sprint: Key(KeyCode::ShiftLeft),
+            jump: Key(KeyCode::Space),
+            crouch: Key(KeyCode::ControlLeft),
+            fly: Key(KeyCode::F),
+            fire: Key(KeyCode::Q),
+            reload: Key(KeyCode::R),
+            drop: Key(KeyCode::G),
+            interact: Key(KeyCode::E),
+            aim: Mouse(MouseButton::Right),
+            dump_meshing_profile: Key(KeyCode::F9),
+            export_chunk_mesh: Key(KeyCode::F8),
+            debug_overlay: Key(KeyCode::F3),
+            spectate: Key(KeyCode::F7),
+            console: Key(KeyCode::Grave),
+            next_slot: Wheel(WheelDirection::Down),
+            prev_slot: Wheel(WheelDirection::Up),
+            quick_swap: Key(KeyCode::X),
+            export_bindings: Key(KeyCode::F10),
+            import_bindings: Key(KeyCode::F11),
+            screenshot: Key(KeyCode::F12),
+            screenshot_hide_hud: Key(KeyCode::AltLeft),
+            slots: [
+                Key(KeyCode::Key1), Key(KeyCode::Key2), Key(KeyCode::Key3), Key(KeyCode::Key4), Key(KeyCode::Key5),
+                Key(KeyCode::Key6), Key(KeyCode::Key7), Key(KeyCode::Key8), Key(KeyCode::Key9), Key(KeyCode::Key0),
+            ],
+            gamepad: GamepadBindings::default(),
+        }
+    }
+}
+
+impl Default for GamepadBindings {
+    fn default() -> Self {
+        Self {
+            jump: GamepadButtonType::South,
+            sprint: GamepadButtonType::LeftThumb,
+            crouch: GamepadButtonType::East,
+            fire: GamepadButtonType::RightTrigger2,
+            reload: GamepadButtonType::West,
+            interact: GamepadButtonType::North,
+            aim: GamepadButtonType::LeftTrigger2,
+            next_slot: GamepadButtonType::RightTrigger,
+            prev_slot: GamepadButtonType::LeftTrigger,
+            move_dead_zone: 0.15,
+            look_dead_zone: 0.15,
+            look_sensitivity: 4.0,
+            look_response_exponent: 2.0,
+        }
+    }
+}
+
+impl Validate for GamepadBindings {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field((0.0..1.0).contains(&self.move_dead_zone), "move_dead_zone", "must be at least 0 and below 1")?;
+        ensure_field((0.0..1.0).contains(&self.look_dead_zone), "look_dead_zone", "must be at least 0 and below 1")?;
+        ensure_field(self.look_sensitivity > 0.0, "look_sensitivity", "must be above zero")?;
+        ensure_field(self.look_response_exponent > 0.0, "look_response_exponent", "must be above zero")?;
+        Ok(())
+    }
+}
+
+/// Ignores deflection inside the dead zone and rescales the rest to still cover zero to one
+fn apply_dead_zone(stick: Vec2, dead_zone: f32) -> Vec2 {
+    let deflection = stick.length();
+    if deflection <= dead_zone {
+        return Vec2::ZERO;
+    }
+    let scaled = ((deflection - dead_zone) / (1.0 - dead_zone)).min(1.0);
+    stick * (scaled / deflection)
+}
+
+impl InputBinding {
+    pub fn pressed(self, key_input: &Input<KeyCode>, btn_input: &Input<MouseButton>, wheel: &WheelInput) -> bool {
+        match self {
+            InputBinding::Key(key) => key_input.pressed(key),
+            InputBinding::Mouse(button) => btn_input.pressed(button),
+            InputBinding::Wheel(direction) => wheel.turned(direction),
+        }
+    }
+
+    pub fn just_pressed(self, key_input: &Input<KeyCode>, btn_input: &Input<MouseButton>, wheel: &WheelInput) -> bool {
+        match self {
+            InputBinding::Key(key) => key_input.just_pressed(key),
+            InputBinding::Mouse(button) => btn_input.just_pressed(button),
+            InputBinding::Wheel(direction) => wheel.turned(direction),
+        }
+    }
+}
+
+impl WheelInput {
+    pub fn turned(&self, direction: WheelDirection) -> bool {
+        match direction {
+            WheelDirection::Up => self.up,
+            WheelDirection::Down => self.down,
+        }
     }
 }
 
-fn get_axis(key_input: &Res<Input<KeyCode>>, key_pos: KeyCode, key_neg: KeyCode) -> f32 {
-    get_pressed(key_input, key_pos) - get_pressed(key_input, key_neg)
+impl fmt::Display for InputBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputBinding::Key(key) => write!(f, "{:?}", key),
+            InputBinding::Mouse(button) => write!(f, "Mouse {:?}", button),
+            InputBinding::Wheel(direction) => write!(f, "Wheel {:?}", direction),
+        }
+    }
 }
 
+impl KeyBindings {
+    fn actions(&self) -> impl Iterator<Item=(&'static str, InputBinding, BindingContext)> + '_ {
+        use BindingContext::*;
+        const SLOT_NAMES: [&str; 10] = ["slot 0", "slot 1", "slot 2", "slot 3", "slot 4", "slot 5", "slot 6", "slot 7", "slot 8", "slot 9"];
+        [
+            ("forward", self.forward, Always),
+            ("back", self.back, Always),
+            ("left", self.left, Always),
+            ("right", self.right, Always),
+            ("up", self.up, Flying),
+            ("down", self.down, Flying),
+            ("sprint", self.sprint, Always),
+            ("jump", self.jump, Walking),
+            ("crouch", self.crouch, Walking),
+            ("fly", self.fly, Always),
+            ("fire", self.fire, Always),
+            ("reload", self.reload, Always),
+            ("drop", self.drop, Always),
+            ("interact", self.interact, Always),
+            ("aim", self.aim, Always),
+            ("dump_meshing_profile", self.dump_meshing_profile, Always),
+            ("export_chunk_mesh", self.export_chunk_mesh, Always),
+            ("debug_overlay", self.debug_overlay, Always),
+            ("spectate", self.spectate, Always),
+            ("console", self.console, Always),
+            ("next_slot", self.next_slot, Always),
+            ("prev_slot", self.prev_slot, Always),
+            ("quick_swap", self.quick_swap, Always),
+            ("export_bindings", self.export_bindings, Always),
+            ("import_bindings", self.import_bindings, Always),
+            ("screenshot", self.screenshot, Always),
+            ("screenshot_hide_hud", self.screenshot_hide_hud, Always),
+        ].into_iter().chain(SLOT_NAMES.into_iter().zip(self.slots).map(|(name, binding)| (name, binding, Always)))
+    }
+
+    /// Action names match the ones in the saved bindings file
+    pub fn action_mut(&mut self, action: &str) -> Option<&mut InputBinding> {
+        Some(match action {
+            "forward" => &mut self.forward,
+            "back" => &mut self.back,
+            "left" => &mut self.left,
+            "right" => &mut self.right,
+            "up" => &mut self.up,
+            "down" => &mut self.down,
+            "sprint" => &mut self.sprint,
+            "jump" => &mut self.jump,
+            "crouch" => &mut self.crouch,
+            "fly" => &mut self.fly,
+            "fire" => &mut self.fire,
+            "reload" => &mut self.reload,
+            "drop" => &mut self.drop,
+            "interact" => &mut self.interact,
+            "aim" => &mut self.aim,
+            "dump_meshing_profile" => &mut self.dump_meshing_profile,
+            "export_chunk_mesh" => &mut self.export_chunk_mesh,
+            "debug_overlay" => &mut self.debug_overlay,
+            "spectate" => &mut self.spectate,
+            "console" => &mut self.console,
+            "next_slot" => &mut self.next_slot,
+            "prev_slot" => &mut self.prev_slot,
+            "quick_swap" => &mut self.quick_swap,
+            "export_bindings" => &mut self.export_bindings,
+            "import_bindings" => &mut self.import_bindings,
+            "screenshot" => &mut self.screenshot,
+            "screenshot_hide_hud" => &mut self.screenshot_hide_hud,
+            _ => {
+                let slot = action.strip_prefix("slot ")?.parse::<usize>().ok()?;
+                self.slots.get_mut(slot)?
+            }
+        })
+    }
+
+    /// Each binding is its own string so one that no longer parses, like a renamed key, does not take the rest down with it
+    pub fn to_saved(self) -> Result<String, ron::Error> {
+        let mut saved = BTreeMap::new();
+        for (action, binding, _) in self.actions() {
+            saved.insert(action, ron::to_string(&binding)?);
+        }
+        ron::ser::to_string_pretty(&saved, ron::ser::PrettyConfig::default())
+    }
+
+    /// Applies saved bindings on top of these, entries that are invalid are skipped with a warning and keep what we had
+    pub fn apply_saved(&mut self, saved: &str) -> Result<(), ron::error::SpannedError> {
+        let saved: BTreeMap<String, String> = ron::from_str(saved)?;
+        for (action, binding) in saved {
+            let Some(slot) = self.action_mut(&action) else {
+                warn!("Saved binding for unknown action {}, ignoring it", action);
+                continue;
+            };
+            match ron::from_str::<InputBinding>(&binding) {
+                Ok(binding) => *slot = binding,
+                Err(err) => warn!("Saved binding {} for {} is invalid, keeping {}: {}", binding, action, slot, err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Pairs of actions sharing a binding that could both be wanted at the same time,
+    /// sharing between walking and flying is fine since only one applies at once
+    pub fn conflicts(&self) -> Vec<(&'static str, &'static str, InputBinding)> {
+        let actions: Vec<_> = self.actions().collect();
+        let mut conflicts = Vec::new();
+        for (i, &(name, binding, context)) in actions.iter().enumerate() {
+            for &(other_name, other_binding, other_context) in &actions[i + 1..] {
+                let exclusive = matches!((context, other_context), (BindingContext::Walking, BindingContext::Flying) | (BindingContext::Flying, BindingContext::Walking));
+                if binding == other_binding && !exclusive {
+                    conflicts.push((name, other_name, binding));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+impl Default for CrosshairConfig {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            line_length: 8.0,
+            line_thickness: 2.0,
+            gap: 4.0,
+            line_opacity: 0.9,
+            dot_size: 2.0,
+            dot_opacity: 1.0,
+            outline_thickness: 1.0,
+            outline_color: Color::BLACK,
+            outline_opacity: 0.6,
+        }
+    }
+}
+
+impl Validate for CrosshairConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        for (value, field) in [
+            (self.line_length, "line_length"), (self.line_thickness, "line_thickness"), (self.gap, "gap"),
+            (self.dot_size, "dot_size"), (self.outline_thickness, "outline_thickness"),
+        ] {
+            ensure_field(value >= 0.0, field, "must not be negative")?;
+        }
+        for (value, field) in [(self.line_opacity, "line_opacity"), (self.dot_opacity, "dot_opacity"), (self.outline_opacity, "outline_opacity")] {
+            ensure_field((0.0..=1.0).contains(&value), field, "must be between 0 and 1")?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for ImpactConfig {
+    fn default() -> Self {
+        Self {
+            decal_texture: None,
+            decal_color: Color::rgba(0.05, 0.05, 0.05, 0.9),
+            decal_size: 0.1,
+            decal_lifetime: 10.0,
+            decal_fade: 2.0,
+            max_decals: 128,
+            particle_count: 6,
+            particle_size: 0.03,
+            particle_speed: 3.0,
+            particle_spread: 0.6,
+            particle_lifetime: 0.5,
+        }
+    }
+}
+
+impl Validate for ImpactConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        for (value, field) in [
+            (self.decal_size, "decal_size"), (self.decal_lifetime, "decal_lifetime"), (self.decal_fade, "decal_fade"),
+            (self.particle_size, "particle_size"), (self.particle_speed, "particle_speed"), (self.particle_spread, "particle_spread"),
+            (self.particle_lifetime, "particle_lifetime"),
+        ] {
+            ensure_field(value >= 0.0, field, "must not be negative")?;
+        }
+        ensure_field(self.decal_fade <= self.decal_lifetime, "decal_fade", "must not be longer than decal_lifetime")
+    }
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self { count: 3, max_health: 100, speed: 4.0, spawn_distance: 16.0 }
+    }
+}
+
+impl Validate for BotConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.max_health > 0, "max_health", "must be above zero")?;
+        ensure_field(self.speed >= 0.0, "speed", "must not be negative")?;
+        ensure_field(self.spawn_distance >= 0.0, "spawn_distance", "must not be negative")
+    }
+}
+
+impl Default for MapBounds {
+    fn default() -> Self {
+        Self { min_chunk_y: -4, max_chunk_y: 4 }
+    }
+}
+
+impl Validate for MapBounds {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.max_chunk_y >= self.min_chunk_y, "max_chunk_y", "must not be below min_chunk_y")
+    }
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        Self { enabled: true, frequency: 0.04, threshold: 0.25, min_depth: 4.0, max_depth: 96.0 }
+    }
+}
+
+impl Validate for CaveConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.frequency > 0.0, "frequency", "must be above zero")?;
+        ensure_field((-1.0..=1.0).contains(&self.threshold), "threshold", "must be between -1 and 1")?;
+        ensure_field(self.min_depth >= 0.0, "min_depth", "must not be negative")?;
+        ensure_field(self.max_depth > self.min_depth, "max_depth", "must be above min_depth")
+    }
+}
+
+impl Default for MinimapConfig {
+    fn default() -> Self {
+        Self { enabled: true, size: 160.0, range: 96.0, position: Vec2::new(10.0, 10.0) }
+    }
+}
+
+impl Validate for MinimapConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.size > 0.0, "size", "must be above zero")?;
+        ensure_field(self.range > 0.0, "range", "must be above zero")
+    }
+}
+
+impl Default for NameplateConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            show_names: true,
+            height: 2.3,
+            size: Vec2::new(48.0, 5.0),
+            fade_start: 24.0,
+            fade_distance: 8.0,
+            full_health_opacity: 0.0,
+        }
+    }
+}
+
+impl Validate for NameplateConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.size.x > 0.0 && self.size.y > 0.0, "size", "must be above zero")?;
+        ensure_field(self.fade_start >= 0.0, "fade_start", "must not be negative")?;
+        ensure_field(self.fade_distance > 0.0, "fade_distance", "must be above zero")?;
+        ensure_field((0.0..=1.0).contains(&self.full_health_opacity), "full_health_opacity", "must be between 0 and 1")
+    }
+}
+
+impl Default for FogConfig {
+    fn default() -> Self {
+        Self { enabled: true, color: Color::rgb(0.05, 0.05, 0.07), density: 0.3 }
+    }
+}
+
+impl Validate for FogConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.density > 0.0 && self.density <= 1.0, "density", "must be above zero and at most 1")
+    }
+}
+
+impl Default for ViewModelConfig {
+    fn default() -> Self {
+        Self {
+            bob_amplitude: Vec2::new(0.015, 0.008),
+            sway_factor: 0.008,
+            max_sway: 0.04,
+            sway_smoothing: 12.0,
+            breath_amplitude: 0.003,
+            breath_frequency: 0.25,
+            ads_factor: 0.15,
+        }
+    }
+}
+
+impl Validate for ViewModelConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        for (value, field) in [
+            (self.bob_amplitude.x, "bob_amplitude.x"), (self.bob_amplitude.y, "bob_amplitude.y"), (self.sway_factor, "sway_factor"),
+            (self.max_sway, "max_sway"), (self.sway_smoothing, "sway_smoothing"), (self.breath_amplitude, "breath_amplitude"),
+            (self.breath_frequency, "breath_frequency"),
+        ] {
+            ensure_field(value >= 0.0, field, "must not be negative")?;
+        }
+        ensure_field((0.0..=1.0).contains(&self.ads_factor), "ads_factor", "must be between 0 and 1")
+    }
+}
+
+impl Config {
+    /// Not aiming, in radians
+    pub fn hip_fov(&self) -> f32 {
+        self.fov_degrees.clamp(MIN_FOV_DEGREES, MAX_FOV_DEGREES).to_radians()
+    }
+
+    /// Follows the chunk render distance, clear when that is off since nothing is ever cut off
+    pub fn fog_settings(&self) -> FogSettings {
+        let fog = &self.fog;
+        let enabled = fog.enabled && self.chunk_render_distance > 0.0;
+        // Chunks are hidden by their centers, so their far halves reach past the render distance
+        let end = self.chunk_render_distance + CHUNK_SZ as f32 * 0.5;
+        FogSettings {
+            color: if enabled { fog.color } else { Color::NONE },
+            falloff: FogFalloff::Linear { start: end * (1.0 - fog.density), end },
+            ..default()
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.sensitivity > 0.0, "sensitivity", "must be above zero")?;
+        ensure_field(self.horizontal_sensitivity_factor > 0.0, "horizontal_sensitivity_factor", "must be above zero")?;
+        ensure_field(self.vertical_sensitivity_factor > 0.0, "vertical_sensitivity_factor", "must be above zero")?;
+        ensure_field(self.mouse_smoothing >= 0.0, "mouse_smoothing", "must not be negative")?;
+        ensure_field(self.inventory_slots > 0, "inventory_slots", "must be above zero")?;
+        ensure_field(self.tick_rate > 0.0, "tick_rate", "must be above zero")?;
+        ensure_field(self.max_catchup_ticks > 0, "max_catchup_ticks", "must be above zero")?;
+        ensure_field(self.chunk_render_distance >= 0.0, "chunk_render_distance", "must not be negative")?;
+        ensure_field(self.chunk_collider_budget > 0, "chunk_collider_budget", "must be above zero")?;
+        ensure_field((0.0..=180.0).contains(&self.normal_crease_angle_degrees), "normal_crease_angle_degrees", "must be between 0 and 180")?;
+        ensure_field(self.interact_range >= 0.0, "interact_range", "must not be negative")?;
+        ensure_field(self.fov_degrees.is_finite(), "fov_degrees", "must be finite")?;
+        ensure_field(self.ads_fov_degrees > 0.0 && self.ads_fov_degrees < 180.0, "ads_fov_degrees", "must be between 0 and 180")?;
+        ensure_field(self.ads_duration >= 0.0, "ads_duration", "must not be negative")?;
+        ensure_field(self.ads_sensitivity_factor > 0.0, "ads_sensitivity_factor", "must be above zero")?;
+        ensure_field(self.max_health > 0, "max_health", "must be above zero")?;
+        ensure_field(self.damage_number_window >= 0.0, "damage_number_window", "must not be negative")?;
+        ensure_field(self.respawn_delay >= 0.0, "respawn_delay", "must not be negative")?;
+        ensure_field(self.respawn_wave_interval > 0.0, "respawn_wave_interval", "must be above zero")?;
+        ensure_field((0.0..=1.0).contains(&self.air_control), "air_control", "must be between 0 and 1")?;
+        ensure_field(self.max_air_speed >= 0.0, "max_air_speed", "must not be negative")?;
+        ensure_field(self.spawn_points.iter().all(|spawn| spawn.translation.is_finite() && spawn.yaw.is_finite()), "spawn_points", "must all be finite")?;
+        ensure_field(self.noclip_speed > 0.0, "noclip_speed", "must be above zero")?;
+        ensure_field(self.max_speed > 0.0, "max_speed", "must be above zero")?;
+        ensure_field(self.ground_accel >= 0.0, "ground_accel", "must not be negative")?;
+        ensure_field(self.air_accel >= 0.0, "air_accel", "must not be negative")?;
+        ensure_field(self.friction >= 0.0, "friction", "must not be negative")?;
+        ensure_field(self.gravity >= 0.0, "gravity", "must not be negative")?;
+        ensure_field(self.jump_height >= 0.0, "jump_height", "must not be negative")?;
+        ensure_field(self.ground_check_distance > 0.0, "ground_check_distance", "must be above zero")?;
+        ensure_field((0.0..90.0).contains(&self.max_ground_slope_degrees), "max_ground_slope_degrees", "must be at least 0 and below 90")?;
+        ensure_field(self.coyote_time >= 0.0, "coyote_time", "must not be negative")?;
+        ensure_field(self.sprint_speed_factor > 0.0, "sprint_speed_factor", "must be above zero")?;
+        ensure_field(self.max_stamina >= 0.0, "max_stamina", "must not be negative")?;
+        ensure_field(self.stamina_regen_rate >= 0.0, "stamina_regen_rate", "must not be negative")?;
+        ensure_field(self.crouch_speed_factor > 0.0, "crouch_speed_factor", "must be above zero")?;
+        ensure_field(self.crouch_duration >= 0.0, "crouch_duration", "must not be negative")?;
+        ensure_field((0.0..=1.0).contains(&self.master_volume), "master_volume", "must be between 0 and 1")?;
+        ensure_field((0.0..=1.0).contains(&self.footstep_volume), "footstep_volume", "must be between 0 and 1")?;
+        ensure_field((0.0..=1.0).contains(&self.weapon_volume), "weapon_volume", "must be between 0 and 1")?;
+        ensure_field(self.footstep_stride > 0.0, "footstep_stride", "must be above zero")?;
+        self.bindings.gamepad.validate().map_err(|err| err.within("gamepad").within("bindings"))?;
+        self.crosshair.validate().map_err(|err| err.within("crosshair"))?;
+        self.view_model.validate().map_err(|err| err.within("view_model"))?;
+        self.impacts.validate().map_err(|err| err.within("impacts"))?;
+        self.minimap.validate().map_err(|err| err.within("minimap"))?;
+        self.nameplates.validate().map_err(|err| err.within("nameplates"))?;
+        self.fog.validate().map_err(|err| err.within("fog"))?;
+        self.caves.validate().map_err(|err| err.within("caves"))?;
+        self.map_bounds.validate().map_err(|err| err.within("map_bounds"))?;
+        self.bots.validate().map_err(|err| err.within("bots"))
+    }
+}
+
+pub fn load_config_sys(asset_server: Res<AssetServer>, mut commands: Commands) {
+    let config: Handle<Config> = asset_server.load("default.config.ron");
+    commands.insert_resource(ConfigState { handle: config });
+}
+
+pub fn config_loaded(config: Res<Assets<Config>>, config_state: Option<Res<ConfigState>>) -> bool {
+    config_state.is_some_and(|config_state| config.contains(&config_state.handle))
+}
+
+/// Reads keys and buttons through the player's current bindings
+#[derive(SystemParam)]
+pub struct BoundInput<'w> {
+    pub bindings: Res<'w, KeyBindings>,
+    key_input: Res<'w, Input<KeyCode>>,
+    btn_input: Res<'w, Input<MouseButton>>,
+    wheel: Res<'w, WheelInput>,
+    gamepads: Res<'w, Gamepads>,
+    pad_input: Res<'w, Input<GamepadButton>>,
+    pad_axes: Res<'w, Axis<GamepadAxis>>,
+}
+
+impl BoundInput<'_> {
+    pub fn pressed(&self, binding: InputBinding) -> bool {
+        binding.pressed(&self.key_input, &self.btn_input, &self.wheel)
+    }
+
+    pub fn just_pressed(&self, binding: InputBinding) -> bool {
+        binding.just_pressed(&self.key_input, &self.btn_input, &self.wheel)
+    }
+
+    fn get_pressed(&self, binding: InputBinding) -> f32 {
+        if self.pressed(binding) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    pub fn get_axis(&self, pos: InputBinding, neg: InputBinding) -> f32 {
+        self.get_pressed(pos) - self.get_pressed(neg)
+    }
+
+    /// Only the first connected gamepad is read
+    fn gamepad(&self) -> Option<Gamepad> {
+        self.gamepads.iter().next()
+    }
+
+    pub fn pad_pressed(&self, button: GamepadButtonType) -> bool {
+        self.gamepad().is_some_and(|gamepad| self.pad_input.pressed(GamepadButton::new(gamepad, button)))
+    }
+
+    pub fn pad_just_pressed(&self, button: GamepadButtonType) -> bool {
+        self.gamepad().is_some_and(|gamepad| self.pad_input.just_pressed(GamepadButton::new(gamepad, button)))
+    }
+
+    /// Right is positive x and up is positive y, already past the dead zone
+    fn pad_stick(&self, x: GamepadAxisType, y: GamepadAxisType, dead_zone: f32) -> Vec2 {
+        let Some(gamepad) = self.gamepad() else { return Vec2::ZERO; };
+        let axis = |axis_type| self.pad_axes.get(GamepadAxis::new(gamepad, axis_type)).unwrap_or(0.0);
+        apply_dead_zone(Vec2::new(axis(x), axis(y)), dead_zone)
+    }
+
+    /// Device that had something pressed or moved this update, if any
+    fn used_device(&self) -> Option<InputDevice> {
+        let pad = &self.bindings.gamepad;
+        if self.key_input.get_just_pressed().next().is_some() || self.btn_input.get_just_pressed().next().is_some() {
+            Some(InputDevice::KeyboardMouse)
+        } else if self.pad_input.get_just_pressed().next().is_some()
+            || self.pad_stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY, pad.move_dead_zone) != Vec2::ZERO
+            || self.pad_stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY, pad.look_dead_zone) != Vec2::ZERO {
+            Some(InputDevice::Gamepad)
+        } else {
+            None
+        }
+    }
+}
+
+/// Any turns are summed so a flick that goes back and forth within one update counts as where it ended up
+pub fn wheel_input_sys(mut wheel_events: EventReader<MouseWheel>, mut wheel: ResMut<WheelInput>) {
+    let turn: f32 = wheel_events.read().map(|event| event.y).sum();
+    *wheel = WheelInput { up: turn > 0.0, down: turn < 0.0 };
+}
+
+/// Next occupied slot after the equipped one going forwards or backwards, wrapping around
+fn cycle_slot(inv: &Inventory, forwards: bool) -> Option<u8> {
+    let count = inv.item_ents.0.len();
+    if count == 0 { return None; }
+    let start = inv.equipped_slot.map_or(if forwards { count - 1 } else { 0 }, usize::from);
+    (1..=count)
+        .map(|step| if forwards { (start + step) % count } else { (start + count - step) % count })
+        .find(|&slot| inv.item_ents.get(slot as u8).is_some())
+        .map(|slot| slot as u8)
+}
+
+/// Read whenever the config loads or changes so saved rebinds always win over it
+fn load_saved_bindings(bindings: &mut KeyBindings) {
+    match std::fs::read_to_string(SAVED_BINDINGS_PATH) {
+        Ok(saved) => if let Err(err) = bindings.apply_saved(&saved) {
+            warn!("Could not read saved bindings from {}, using the config's: {}", SAVED_BINDINGS_PATH, err);
+        },
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => warn!("Could not open saved bindings at {}: {}", SAVED_BINDINGS_PATH, err),
+    }
+    for (action, other_action, binding) in bindings.conflicts() {
+        warn!("{} and {} are both bound to {}", action, other_action, binding);
+    }
+}
+
+pub fn apply_bindings_sys(
+    mut config_events: EventReader<AssetEvent<Config>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    let config_changed = config_events.read().any(|event|
+        event.is_loaded_with_dependencies(&config_state.handle) || event.is_modified(&config_state.handle));
+    if !config_changed { return; }
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    *bindings = config.bindings;
+    load_saved_bindings(&mut bindings);
+}
+
+pub fn import_export_bindings_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    key_input: Res<Input<KeyCode>>,
+    btn_input: Res<Input<MouseButton>>,
+    wheel: Res<WheelInput>,
+    mut bindings: ResMut<KeyBindings>,
+) {
+    if bindings.export_bindings.just_pressed(&key_input, &btn_input, &wheel) {
+        let result = bindings.to_saved()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            .and_then(|saved| std::fs::write(SAVED_BINDINGS_PATH, saved));
+        match result {
+            Ok(()) => info!("Exported bindings to {}", SAVED_BINDINGS_PATH),
+            Err(err) => error!("Could not export bindings to {}: {}", SAVED_BINDINGS_PATH, err),
+        }
+    }
+    if bindings.import_bindings.just_pressed(&key_input, &btn_input, &wheel) {
+        let Some(config) = config.get(&config_state.handle) else { return; };
+        *bindings = config.bindings;
+        load_saved_bindings(&mut bindings);
+        info!("Imported bindings from {}", SAVED_BINDINGS_PATH);
+    }
+}
+
+/// Runs at the end of each simulation tick once everything has had a chance to act on the presses
+pub fn consume_latched_input_sys(mut query: Query<&mut PlayerInput>) {
+    for mut input in query.iter_mut() {
+        input.flags -= latched_flags();
+    }
+}
+
+/// Grabbed when play starts or resumes and on clicking into the window, menus always get it back
 pub fn cursor_grab_sys(
     mut windows: Query<&mut Window>,
+    mut focus_events: EventReader<WindowFocused>,
     btn: Res<Input<MouseButton>>,
-    key: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
 ) {
     let mut window = windows.single_mut();
-    if btn.just_pressed(MouseButton::Left) {
+    let playing = *state.get() == GameState::Playing;
+    // Let go when tabbing away so the cursor is not trapped, clicking back in picks it up again
+    let lost_focus = focus_events.read().last().is_some_and(|event| !event.focused);
+    if playing && !lost_focus && (state.is_changed() || btn.just_pressed(MouseButton::Left)) {
         window.cursor.grab_mode = CursorGrabMode::Locked;
         window.cursor.visible = false;
     }
-    if key.just_pressed(KeyCode::Escape) {
+    if (!playing || lost_focus) && window.cursor.grab_mode != CursorGrabMode::None {
         window.cursor.grab_mode = CursorGrabMode::None;
         window.cursor.visible = true;
     }
 }
 
+type PlayerInputQuery<'a> = (&'a mut PlayerInput, Option<&'a Inventory>, Option<&'a AimDownSights>, Option<&'a Dead>);
+
+pub fn input_device_sys(
+    input: BoundInput,
+    mut mouse_events: EventReader<MouseMotion>,
+    mut last_device: ResMut<InputDevice>,
+) {
+    let mouse_moved = mouse_events.read().any(|event| event.delta != Vec2::ZERO);
+    let device = if mouse_moved { Some(InputDevice::KeyboardMouse) } else { input.used_device() };
+    if let Some(device) = device {
+        if *last_device != device { *last_device = device; }
+    }
+}
+
+/// Raw mouse movement, optionally smoothed out when the platform delivers it in uneven batches
+#[derive(SystemParam)]
+pub struct MouseLook<'w, 's> {
+    events: EventReader<'w, 's, MouseMotion>,
+    /// Pixels per second carried over from previous frames
+    smoothed_rate: Local<'s, Vec2>,
+}
+
+impl MouseLook<'_, '_> {
+    /// Pixels moved since last frame. Without smoothing this is every raw event summed, so nothing is lost or delayed.
+    /// There is deliberately no per frame cursor mode to switch to, the cursor is locked while playing so its position
+    /// never moves, and summing raw events already is the one to one path.
+    /// Smoothing treats the movement as a rate and eases toward it, which evens out batched events but trails the
+    /// hand by about the smoothing time. Movement is spread out rather than dropped, so the total turn ends up the same.
+    fn delta(&mut self, smoothing: f32, dt: f32) -> Vec2 {
+        let delta = self.events.read().map(|event| event.delta).sum::<Vec2>();
+        if smoothing <= 0.0 || dt <= 0.0 {
+            *self.smoothed_rate = Vec2::ZERO;
+            return delta;
+        }
+        let blend = 1.0 - (-dt / smoothing).exp();
+        *self.smoothed_rate = self.smoothed_rate.lerp(delta / dt, blend);
+        *self.smoothed_rate * dt
+    }
+
+    /// Drops movement made while the cursor was free, so clicking around does not turn the view once it is grabbed again
+    fn discard(&mut self) {
+        self.events.clear();
+        *self.smoothed_rate = Vec2::ZERO;
+    }
+}
+
+impl PlayerInput {
+    /// Positive x turns right and positive y looks down, like the mouse moves
+    pub fn turn(&mut self, look: Vec2) {
+        self.pitch = (self.pitch - look.y).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        self.yaw -= look.x;
+    }
+}
+
+impl Config {
+    /// Per axis factors and inversion, applied to mouse and stick alike
+    fn look_axes(&self, look: Vec2) -> Vec2 {
+        let look = look * Vec2::new(self.horizontal_sensitivity_factor, self.vertical_sensitivity_factor);
+        if self.invert_y { Vec2::new(look.x, -look.y) } else { look }
+    }
+}
+
 pub fn player_input_system(
-    key_input: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    input: BoundInput,
     config: Res<Assets<Config>>,
     config_state: Res<ConfigState>,
     mut window: Query<&mut Window>,
-    mut mouse_events: EventReader<MouseMotion>,
-    mut query: Query<&mut PlayerInput>)
+    mut mouse_look: MouseLook,
+    mut query: Query<PlayerInputQuery, With<LocalPlayer>>)
 {
     if let Some(config) = config.get(&config_state.handle) {
-        for mut player_input in query.iter_mut() {
+        let pad = &input.bindings.gamepad;
+        for (mut player_input, inv, ads, dead) in query.iter_mut() {
+            if dead.is_some() {
+                player_input.movement = Vec3::ZERO;
+                player_input.flags.clear();
+                continue;
+            }
+
             let window = window.single_mut();
+            let grabbed = window.cursor.grab_mode != CursorGrabMode::None;
+            if !grabbed { mouse_look.discard(); }
             if window.focused {
-                let mut mouse_delta = Vec2::ZERO;
-                for mouse_event in mouse_events.read() {
-                    mouse_delta += mouse_event.delta;
+                let mut mouse_delta = if grabbed { mouse_look.delta(config.mouse_smoothing, time.delta_seconds()) } else { Vec2::ZERO };
+                let ads_factor = 1.0 + (config.ads_sensitivity_factor - 1.0) * ads.map_or(0.0, |ads| ads.progress);
+                mouse_delta *= config.sensitivity * ads_factor;
+
+                // Sticks give a rate rather than a distance like the mouse does, so scale by time,
+                // with a curve so small deflections still allow precise aim
+                let stick = input.pad_stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY, pad.look_dead_zone);
+                let stick_deflection = stick.length();
+                if stick_deflection > 0.0 {
+                    let curved = stick * (stick_deflection.powf(pad.look_response_exponent) / stick_deflection);
+                    // Stick up is positive but mouse up is negative, flip to match
+                    mouse_delta += Vec2::new(curved.x, -curved.y) * pad.look_sensitivity * ads_factor * time.delta_seconds();
                 }
-                mouse_delta *= config.sensitivity;
 
-                player_input.pitch = (player_input.pitch - mouse_delta.y).clamp(
-                    -FRAC_PI_2 + 0.001953125,
-                    FRAC_PI_2 - 0.001953125,
-                );
-                player_input.yaw = player_input.yaw - mouse_delta.x;
+                player_input.turn(config.look_axes(mouse_delta));
             }
 
+            let bindings = &input.bindings;
+            let latched = player_input.flags & latched_flags();
+            let move_stick = input.pad_stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY, pad.move_dead_zone);
             player_input.movement = Vec3::new(
-                get_axis(&key_input, config.key_right, config.key_left),
-                get_axis(&key_input, config.key_up, config.key_down),
-                get_axis(&key_input, config.key_forward, config.key_back),
+                (input.get_axis(bindings.right, bindings.left) + move_stick.x).clamp(-1.0, 1.0),
+                input.get_axis(bindings.up, bindings.down),
+                (input.get_axis(bindings.forward, bindings.back) + move_stick.y).clamp(-1.0, 1.0),
             );
             player_input.flags.clear();
-            if key_input.pressed(config.key_sprint) { player_input.flags |= PlayerInputFlags::Sprint; }
-            if key_input.pressed(config.key_jump) { player_input.flags |= PlayerInputFlags::Jump; }
-            if key_input.pressed(config.key_fire) { player_input.flags |= PlayerInputFlags::Fire; }
-            if key_input.pressed(config.key_reload) { player_input.flags |= PlayerInputFlags::Reload; }
-            if key_input.just_pressed(config.key_fly) { player_input.flags |= PlayerInputFlags::Fly; }
-            if key_input.pressed(KeyCode::Key1) { player_input.wanted_item_slot = Some(0); }
-            if key_input.pressed(KeyCode::Key2) { player_input.wanted_item_slot = Some(1); }
-            if key_input.pressed(KeyCode::Key3) { player_input.wanted_item_slot = Some(2); }
+            player_input.flags |= latched;
+            if input.pressed(bindings.sprint) || input.pad_pressed(pad.sprint) { player_input.flags |= PlayerInputFlags::Sprint; }
+            if input.pressed(bindings.jump) || input.pad_pressed(pad.jump) { player_input.flags |= PlayerInputFlags::Jump; }
+            if input.pressed(bindings.fire) || input.pad_pressed(pad.fire) { player_input.flags |= PlayerInputFlags::Fire; }
+            if input.pressed(bindings.reload) || input.pad_pressed(pad.reload) { player_input.flags |= PlayerInputFlags::Reload; }
+            if input.just_pressed(bindings.fly) { player_input.flags |= PlayerInputFlags::Fly; }
+            if input.just_pressed(bindings.drop) { player_input.flags |= PlayerInputFlags::Drop; }
+            if input.just_pressed(bindings.interact) || input.pad_just_pressed(pad.interact) { player_input.flags |= PlayerInputFlags::Interact; }
+            if input.pressed(bindings.aim) || input.pad_pressed(pad.aim) { player_input.flags |= PlayerInputFlags::Aim; }
+            if input.pressed(bindings.crouch) || input.pad_pressed(pad.crouch) { player_input.flags |= PlayerInputFlags::Crouch; }
+            for (slot, &binding) in bindings.slots.iter().enumerate() {
+                if input.pressed(binding) { player_input.wanted_item_slot = Some(slot as u8); }
+            }
+            if let Some(inv) = inv {
+                if input.just_pressed(bindings.next_slot) || input.pad_just_pressed(pad.next_slot) { player_input.wanted_item_slot = cycle_slot(inv, true).or(player_input.wanted_item_slot); }
+                if input.just_pressed(bindings.prev_slot) || input.pad_just_pressed(pad.prev_slot) { player_input.wanted_item_slot = cycle_slot(inv, false).or(player_input.wanted_item_slot); }
+                // The previous slot may have been emptied since, like by dropping what was in it
+                let prev_slot = inv.prev_equipped_slot.filter(|&slot| inv.item_ents.get(slot).is_some());
+                if input.just_pressed(bindings.quick_swap) { player_input.wanted_item_slot = prev_slot.or(player_input.wanted_item_slot); }
+            }
         }
     }
 }
@@ -162,17 +1316,205 @@ impl AssetLoader for ConfigAssetLoader {
         &'a self,
         reader: &'a mut Reader,
         _settings: &'a Self::Settings,
-        _load_context: &'a mut LoadContext,
+        load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<Config, Self::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
-            let asset = ron::de::from_bytes::<Config>(&bytes)?;
-            Ok(asset)
+            // Same config either way, the last extension picks the format
+            let asset: Config = parse_by_extension(load_context.path(), &bytes)?;
+            asset.validate()?;
+            Ok(apply_user_config(asset))
         })
     }
 
     fn extensions(&self) -> &[&str] {
-        &["config.ron"]
+        &["config.ron", "config.json", "config.toml"]
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum UserConfigError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Read(#[from] toml::de::Error),
+    #[error(transparent)]
+    Write(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Invalid(#[from] InvalidField),
+}
+
+/// Tables merge key by key, anything else in the overlay replaces what was there
+fn merge_toml(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_toml(base, overlay),
+            (_, value) => { base.insert(key, value); }
+        }
+    }
+}
+
+fn read_user_config() -> Result<toml::Table, UserConfigError> {
+    match std::fs::read_to_string(USER_CONFIG_PATH) {
+        Ok(user) => Ok(toml::from_str(&user)?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(toml::Table::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl Config {
+    /// This config with a partial one in TOML on top
+    pub fn merged(&self, overlay: toml::Table) -> Result<Config, UserConfigError> {
+        let toml::Value::Table(mut merged) = toml::Value::try_from(self)? else { unreachable!("config serializes to a table") };
+        merge_toml(&mut merged, overlay);
+        let config: Config = toml::Value::Table(merged).try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// A user config that does not read or breaks validation is skipped rather than failing the whole load
+fn apply_user_config(config: Config) -> Config {
+    match read_user_config().and_then(|user| config.merged(user)) {
+        Ok(merged) => merged,
+        Err(err) => {
+            warn!("Could not apply user config from {}, using the defaults: {}", USER_CONFIG_PATH, err);
+            config
+        }
+    }
+}
+
+/// Writes these fields into the user config, anything else already in it is kept
+pub fn write_user_config(fields: toml::Table) -> Result<(), UserConfigError> {
+    let mut user = read_user_config()?;
+    merge_toml(&mut user, fields);
+    std::fs::write(USER_CONFIG_PATH, toml::to_string_pretty(&user)?)?;
+    Ok(())
+}
+
+/// The rifle is a good starting point for any gun
+const SAMPLE_GUN: &str = include_str!("../../assets/items/rifle.item.ron");
+
+#[derive(Debug, Error)]
+pub enum ConfigDumpError {
+    #[error(transparent)]
+    Toml(#[from] toml::ser::Error),
+    #[error("Sample gun does not parse: {0}")]
+    Sample(#[from] ron::error::SpannedError),
+    #[error("Usage: qgame dump-config [gun]")]
+    Usage,
+}
+
+/// Default config, or a sample gun with `gun`, as TOML to scaffold from. Loads as `*.config.toml` or `*.item.toml`
+pub fn dump_config(mut args: impl Iterator<Item=std::string::String>) -> Result<std::string::String, ConfigDumpError> {
+    let toml = match args.next().as_deref() {
+        None => toml::to_string(&Config::default())?,
+        Some("gun") => toml::to_string(&ron::de::from_str::<ItemConfig>(SAMPLE_GUN)?)?,
+        Some(_) => return Err(ConfigDumpError::Usage),
+    };
+    match args.next() {
+        Some(_) => Err(ConfigDumpError::Usage),
+        None => Ok(toml),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::ConfigParseError;
+
+    use super::*;
+
+    const DEFAULT_CONFIG: &str = include_str!("../../assets/default.config.ron");
+
+    fn default_file() -> Config {
+        ron::de::from_str(DEFAULT_CONFIG).unwrap()
+    }
+
+    #[test]
+    fn default_file_agrees_on_switch_queuing() {
+        assert_eq!(default_file().queue_weapon_switch, Config::default().queue_weapon_switch);
+    }
+
+    #[test]
+    fn default_config_round_trips_through_toml() {
+        let config = Config::default();
+        assert_eq!(toml::from_str::<Config>(&toml::to_string(&config).unwrap()).unwrap(), config);
+    }
+
+    #[test]
+    fn sample_gun_round_trips_through_toml() {
+        let gun = ron::de::from_str::<ItemConfig>(SAMPLE_GUN).unwrap();
+        assert_eq!(toml::from_str::<ItemConfig>(&toml::to_string(&gun).unwrap()).unwrap(), gun);
     }
-}
\ No newline at end of file
+
+    fn mouse_turn(config: &Config, mouse_delta: Vec2) -> PlayerInput {
+        let mut input = PlayerInput::default();
+        input.turn(config.look_axes(mouse_delta * config.sensitivity));
+        input
+    }
+
+    #[test]
+    fn mouse_delta_turns_by_sensitivity() {
+        let delta = Vec2::new(0.4, 0.2);
+        for sensitivity in [0.5, 2.0] {
+            let config = Config { sensitivity, ..default() };
+            let input = mouse_turn(&config, delta);
+            assert!((input.yaw - -0.4 * sensitivity).abs() < 1e-6, "yaw {} at {}", input.yaw, sensitivity);
+            assert!((input.pitch - -0.2 * sensitivity).abs() < 1e-6, "pitch {} at {}", input.pitch, sensitivity);
+        }
+    }
+
+    #[test]
+    fn axis_factors_and_invert_only_touch_their_axis() {
+        let delta = Vec2::new(0.4, 0.2);
+        let config = Config { sensitivity: 1.0, horizontal_sensitivity_factor: 0.5, invert_y: true, ..default() };
+        let input = mouse_turn(&config, delta);
+        assert!((input.yaw - -0.2).abs() < 1e-6);
+        assert!((input.pitch - 0.2).abs() < 1e-6);
+        // Pitch stops short of straight up however far the mouse goes
+        assert_eq!(mouse_turn(&config, Vec2::new(0.0, 100.0)).pitch, PITCH_LIMIT);
+    }
+
+    #[test]
+    fn default_file_agrees_on_crosshair() {
+        assert_eq!(default_file().crosshair, CrosshairConfig::default());
+    }
+
+    #[test]
+    fn default_file_agrees_on_mesh_validation() {
+        assert_eq!(default_file().validate_chunk_meshes, Config::default().validate_chunk_meshes);
+    }
+
+    #[test]
+    fn every_format_loads_the_same_config() {
+        let config = default_file();
+        let formats = [
+            ("default.config.ron", ron::ser::to_string(&config).unwrap()),
+            ("default.config.json", serde_json::to_string(&config).unwrap()),
+            ("default.config.toml", toml::to_string(&config).unwrap()),
+        ];
+        for (path, text) in formats {
+            let parsed: Config = parse_by_extension(Path::new(path), text.as_bytes()).unwrap();
+            assert_eq!(parsed, config, "{}", path);
+        }
+    }
+
+    #[test]
+    fn extension_picks_the_parser() {
+        let json = serde_json::to_string(&Config::default()).unwrap();
+        assert!(parse_by_extension::<Config>(Path::new("default.config.json"), json.as_bytes()).is_ok());
+        assert!(matches!(parse_by_extension::<Config>(Path::new("default.config.toml"), json.as_bytes()), Err(ConfigParseError::TomlError(_))));
+        assert!(matches!(parse_by_extension::<Config>(Path::new("default.config.ron"), json.as_bytes()), Err(ConfigParseError::RonSpannedError(_))));
+    }
+
+    #[test]
+    fn dumped_config_loads_back() {
+        let dumped = dump_config(std::iter::empty()).unwrap();
+        assert_eq!(toml::from_str::<Config>(&dumped).unwrap(), Config::default());
+        let dumped = dump_config(["gun".into()].into_iter()).unwrap();
+        assert!(matches!(toml::from_str::<ItemConfig>(&dumped).unwrap(), ItemConfig::Gun(_)));
+        assert!(matches!(dump_config(["gun".into(), "extra".into()].into_iter()), Err(ConfigDumpError::Usage)));
+    }
+}
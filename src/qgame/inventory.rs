@@ -1,5 +1,6 @@
 use std::{
-    f32::consts::TAU,
+    any::TypeId,
+    f32::consts::{PI, TAU},
     option::Option,
     time::Duration,
 };
@@ -10,7 +11,10 @@ use bevy::{
         AsyncReadExt,
         io::Reader,
         LoadContext,
+        LoadedFolder,
     },
+    ecs::system::SystemParam,
+    math::Vec3Swizzles,
     prelude::*,
     reflect::TypePath,
     utils::{BoxedFuture, HashMap},
@@ -18,8 +22,9 @@ use bevy::{
 use bevy_rapier3d::prelude::*;
 use serde::{Deserialize, Serialize};
 use smartstring::alias::String;
+use thiserror::Error;
 
-use crate::{PlayerInput, PlayerInputFlags, RonLoaderError};
+use crate::{AimDownSights, Config, ConfigState, DryFireEvent, ensure_field, FireEvent, Footsteps, gameplay_query_filter, InvalidField, LocalPlayer, look_quat, MoveMode, parse_by_extension, PlayerController, PlayerInput, PlayerInputFlags, RonLoaderError, smoothstep, Validate};
 
 const EQUIPPING_STATE: &str = "equipping";
 const EQUIPPED_STATE: &str = "equipped";
@@ -29,38 +34,189 @@ const IDLE_STATE: &str = "idle";
 const RELOAD_STATE: &str = "reload";
 const FIRE_STATE: &str = "fire";
 
+pub const DEFAULT_INVENTORY_SLOTS: u8 = 10;
+
+const DROP_SPEED: f32 = 6.0;
+
+/// States an item's config does not time, or any state before its config loads, last this long
+const UNTIMED_STATE_DURATION: Duration = Duration::from_secs(2);
+
+/// Equipped item position relative to the camera at the hip and when aiming down sights
+const HIP_OFFSET: Vec3 = Vec3::new(0.4, -0.3, -1.0);
+const SIGHT_OFFSET: Vec3 = Vec3::new(0.0, -0.2, -0.7);
+
 pub type ItemName = String;
 type ItemStateName = String;
 type EquipStateName = String;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ItemStateProps {
     pub duration: Duration,
     pub is_persistent: bool,
 }
 
-#[derive(Serialize, Deserialize, TypePath)]
+/// Clips for an item, paths are relative to the assets folder and left out ones stay silent
+#[derive(Debug, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ItemSoundProps {
+    #[serde(default)]
+    pub fire: Option<String>,
+    /// Played for each pull of the trigger on an empty magazine
+    #[serde(default)]
+    pub dry_fire: Option<String>,
+    /// Played as the reload starts
+    #[serde(default)]
+    pub reload: Option<String>,
+    /// Played once equipping finishes and the item is ready
+    #[serde(default)]
+    pub equip: Option<String>,
+}
+
+/// Where the item goes when put away, it moves between here and the held position while equipping and unequipping
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EquipAnimationProps {
+    /// Added to the held position once fully lowered, relative to the camera
+    pub lowered_offset: Vec3,
+    /// Rotation about the camera's x, y then z axes once fully lowered, in radians
+    pub lowered_angles: Vec3,
+}
+
+impl Default for EquipAnimationProps {
+    fn default() -> Self {
+        Self {
+            lowered_offset: Vec3::new(0.0, -0.6, 0.2),
+            lowered_angles: Vec3::new(-1.0, 0.0, 0.0),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, TypePath)]
+#[serde(deny_unknown_fields)]
 pub struct ItemProps {
     pub name: ItemName,
     pub move_factor: f32,
     pub states: HashMap<ItemStateName, ItemStateProps>,
     pub equip_states: HashMap<EquipStateName, ItemStateProps>,
+    #[serde(default)]
+    pub sounds: ItemSoundProps,
+    #[serde(default)]
+    pub equip_animation: EquipAnimationProps,
+    /// Most of this item one slot can hold, picking up more tops up existing stacks before taking a new slot.
+    /// Leave out for items that never stack
+    #[serde(default)]
+    pub max_stack: Option<u16>,
 }
 
-#[derive(Serialize, Deserialize, TypePath)]
+#[derive(Debug, PartialEq, Serialize, Deserialize, TypePath)]
+#[serde(deny_unknown_fields)]
 pub struct WeaponProps {
     pub damage: u16,
     pub headshot_factor: f32,
     pub item_props: ItemProps,
 }
 
-#[derive(Asset, Serialize, Deserialize, TypePath)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecoilProps {
+    /// Kick per shot in radians of (pitch, yaw), indexed by shots since the trigger was pulled
+    pub pattern: Vec<Vec2>,
+    /// Random yaw added either side of each kick, in radians
+    pub yaw_jitter: f32,
+    /// How fast kick the player has not compensated for returns, in radians per second
+    pub recovery_rate: f32,
+}
+
+/// Half-angles of the cone shots land in, all in radians
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpreadProps {
+    /// Standing still and settled, keep small so the first shot lands where we aim
+    pub base: f32,
+    /// Added at walk speed and above, scaled down linearly when slower
+    pub moving: f32,
+    /// Added while not on the ground
+    pub airborne: f32,
+    /// Added after every shot, never past max bloom
+    pub bloom_per_shot: f32,
+    pub max_bloom: f32,
+    /// How fast bloom and stance spread settle back down, in radians per second
+    pub recovery_rate: f32,
+}
+
+/// Guns with these fire a physical body instead of a hitscan ray
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectileProps {
+    pub speed: f32,
+    /// Multiplier on world gravity, zero flies straight
+    pub gravity_scale: f32,
+    pub radius: f32,
+    /// Seconds before exploding on its own, zero to never time out
+    pub fuse: f32,
+    pub explode_on_contact: bool,
+    /// Damage falls off linearly from full at the center to nothing at this distance
+    pub blast_radius: f32,
+}
+
+/// Shows a node of the gun's model only while the magazine holds at least some ammo,
+/// like one per revolver chamber or the magazine itself
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmmoNodeProps {
+    /// Name of the node in the model
+    pub node: String,
+    pub min_ammo: u16,
+}
+
+/// Spent casings thrown out of the gun each shot
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShellEjectProps {
+    /// Node in the gun's model they come out of, the gun itself is used if the model does not have it
+    pub port_node: String,
+    /// Relative to the port node, does not need to be normalized
+    pub direction: Vec3,
+    pub speed: f32,
+    /// Most random spin in radians per second around each axis
+    pub tumble: f32,
+    pub radius: f32,
+    pub length: f32,
+    pub color: Color,
+    /// Seconds before they are removed, the end of which is spent fading out
+    pub lifetime: f32,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, TypePath)]
+#[serde(deny_unknown_fields)]
 pub struct GunProps {
     pub mag_size: u16,
     pub starting_ammo_in_reserve: u16,
+    pub recoil: RecoilProps,
+    pub hipfire_spread: SpreadProps,
+    pub ads_spread: SpreadProps,
+    #[serde(default)]
+    pub projectile: Option<ProjectileProps>,
+    #[serde(default)]
+    pub ammo_display: Vec<AmmoNodeProps>,
+    /// Leave out for guns that should not drop casings
+    #[serde(default)]
+    pub shell_eject: Option<ShellEjectProps>,
+    /// Scoped guns hide the crosshair while aiming down sights
+    #[serde(default)]
+    pub has_scope: bool,
     pub weapon_props: WeaponProps,
 }
 
+/// Any kind of item config, the variant name tags which kind a file holds
+#[derive(Asset, Debug, PartialEq, Serialize, Deserialize, TypePath)]
+pub enum ItemConfig {
+    Gun(Box<GunProps>),
+    Weapon(WeaponProps),
+    Item(ItemProps),
+}
+
 #[derive(Component, Debug)]
 pub struct Item {
     pub name: ItemName,
@@ -71,46 +227,420 @@ pub struct Item {
     pub inv_slot: u8,
 }
 
+/// Sent whenever an item enters a state, firing included
+#[derive(Event)]
+pub struct ItemStateEvent {
+    pub item_ent: Entity,
+    pub inv_ent: Entity,
+    pub state: ItemStateName,
+}
+
+/// Sent once an item finishes equipping and can be used
+#[derive(Event)]
+pub struct EquippedEvent {
+    pub item_ent: Entity,
+    pub inv_ent: Entity,
+}
+
+/// Everything items announce as they change state
+#[derive(SystemParam)]
+pub struct ItemEvents<'w> {
+    fire: EventWriter<'w, FireEvent>,
+    dry_fire: EventWriter<'w, DryFireEvent>,
+    state: EventWriter<'w, ItemStateEvent>,
+}
+
 #[derive(Component)]
 pub struct ItemPickup {
     pub item_name: ItemName,
+    pub amount: u16,
+}
+
+/// Puts items into inventories, stacking them as far as their configs allow
+#[derive(SystemParam)]
+pub struct ItemGiver<'w, 's> {
+    commands: Commands<'w, 's>,
+    item_query: Query<'w, 's, &'static mut Item>,
+    item_props: ItemPropsLookup<'w>,
 }
 
 #[derive(Component, Default)]
 pub struct ItemPickupVisual;
 
-#[derive(Component)]
+/// Closest pickup the player is looking at within interaction range
+#[derive(Component, Default)]
+pub struct InteractTarget(pub Option<Entity>);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
 pub struct Gun {
     pub ammo: u16,
     pub ammo_in_reserve: u16,
 }
 
+impl Gun {
+    /// Fresh from the config, a full magazine and the starting reserve
+    pub fn full(props: &GunProps) -> Self {
+        Self { ammo: props.mag_size, ammo_in_reserve: props.starting_ammo_in_reserve }
+    }
+
+    pub fn can_reload(&self, mag_size: u16) -> bool {
+        self.ammo < mag_size && self.ammo_in_reserve > 0
+    }
+
+    /// Tops the magazine up from the reserve as far as it has rounds, returns how many moved
+    pub fn reload(&mut self, mag_size: u16) -> u16 {
+        let moved = mag_size.saturating_sub(self.ammo).min(self.ammo_in_reserve);
+        self.ammo += moved;
+        self.ammo_in_reserve -= moved;
+        moved
+    }
+}
+
 #[derive(Debug)]
-pub struct Items(pub [Option<Entity>; 10]);
+pub struct Items(pub Vec<Option<Entity>>);
+
+impl Items {
+    /// Item entity in a slot, treating out of range slots as empty
+    pub fn get(&self, slot: u8) -> Option<Entity> {
+        self.0.get(slot as usize).copied().flatten()
+    }
+}
 
 #[derive(Component)]
 pub struct ItemVisual;
 
+/// Bob, sway and breathing of the held item, added on top of where it would be held still
+#[derive(Component, Default)]
+pub struct ViewModelMotion {
+    /// Eases towards how fast we are moving so bob does not pop in and out when starting or stopping
+    pub bob_weight: f32,
+    pub sway: Vec2,
+    /// Yaw and pitch last frame, for how fast we are looking around
+    pub last_look: Vec2,
+    pub offset: Vec3,
+    /// How far the equipped item is put away, zero held and one out of view
+    pub lowered: f32,
+}
+
+/// How quickly bob fades in and out with movement, higher is snappier
+const BOB_EASE_RATE: f32 = 8.0;
+
 #[derive(Component, Debug)]
 pub struct Inventory {
     pub equipped_slot: Option<u8>,
     pub prev_equipped_slot: Option<u8>,
+    /// Slot to switch to as soon as the current equip finishes
+    pub queued_slot: Option<u8>,
     pub equip_state_name: EquipStateName,
     pub equip_state_dur: Duration,
     pub item_ents: Items,
 }
 
+/// Keeps every item config in the items folder loaded
+#[derive(Resource)]
+pub struct ItemPropsState {
+    handle: Handle<LoadedFolder>,
+}
+
+#[derive(Debug, Error)]
+pub enum ItemRegistryError {
+    #[error("No item config named {0}")]
+    UnknownItem(ItemName),
+    #[error("Item {0} is not a gun")]
+    NotAGun(ItemName),
+}
+
+/// Every item config in the items folder, keyed by item name
+#[derive(Resource, Default)]
+pub struct ItemRegistry {
+    configs: HashMap<ItemName, Handle<ItemConfig>>,
+}
+
+/// Looks up item configs by name from systems
+#[derive(SystemParam)]
+pub struct ItemPropsLookup<'w> {
+    registry: Res<'w, ItemRegistry>,
+    configs: Res<'w, Assets<ItemConfig>>,
+}
+
 pub struct InventoryPlugin;
 
 impl Plugin for InventoryPlugin {
-    fn build(&self, _app: &mut App) {}
+    fn build(&self, app: &mut App) {
+        app
+            .register_asset_loader(ItemConfigAssetLoader)
+            .init_asset::<ItemConfig>()
+            .add_event::<FireEvent>()
+            .add_event::<DryFireEvent>()
+            .add_event::<ItemStateEvent>()
+            .add_event::<EquippedEvent>()
+            .init_resource::<ItemRegistry>()
+            .add_systems(Startup, load_item_props_sys)
+            .add_systems(Update, (build_item_registry_sys, reload_item_configs_sys).chain());
+    }
+}
+
+fn load_item_props_sys(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(ItemPropsState { handle: asset_server.load_folder("items") });
+}
+
+/// Rebuilt once the folder finishes loading and again whenever a config in it is edited,
+/// so renaming an item on disk moves it to its new name
+fn build_item_registry_sys(
+    mut folder_events: EventReader<AssetEvent<LoadedFolder>>,
+    mut config_events: EventReader<AssetEvent<ItemConfig>>,
+    state: Res<ItemPropsState>,
+    folders: Res<Assets<LoadedFolder>>,
+    configs: Res<Assets<ItemConfig>>,
+    mut registry: ResMut<ItemRegistry>,
+) {
+    let folder_loaded = folder_events.read().any(|event| event.is_loaded_with_dependencies(&state.handle));
+    let config_modified = config_events.read().any(|event| matches!(event, AssetEvent::Modified { .. }));
+    if !folder_loaded && !config_modified { return; }
+    let Some(folder) = folders.get(&state.handle) else { return; };
+
+    registry.configs.clear();
+    for handle in &folder.handles {
+        if handle.type_id() != TypeId::of::<ItemConfig>() { continue; }
+        let handle = handle.clone().typed::<ItemConfig>();
+        let Some(config) = configs.get(&handle) else { continue; };
+        let name = &config.item_props().name;
+        if !registry.register(name.clone(), handle) {
+            warn!("Multiple item configs are named {}, keeping the first one found", name);
+        }
+    }
+}
+
+/// Most props are read every frame so edits apply right away, this fixes up what was copied onto items when they were made.
+/// A config that fails to parse is never swapped in, so items keep running on the last one that did
+pub fn reload_item_configs_sys(
+    mut config_events: EventReader<AssetEvent<ItemConfig>>,
+    item_props: ItemPropsLookup,
+    mut gun_query: Query<(&Item, &mut Gun)>,
+) {
+    if !config_events.read().any(|event| matches!(event, AssetEvent::Modified { .. })) { return; }
+
+    for (item, mut gun) in gun_query.iter_mut() {
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        if gun.ammo > props.mag_size {
+            gun.ammo_in_reserve = gun.ammo_in_reserve.saturating_add(gun.ammo - props.mag_size);
+            gun.ammo = props.mag_size;
+        }
+    }
+}
+
+impl ItemConfig {
+    pub fn item_props(&self) -> &ItemProps {
+        match self {
+            ItemConfig::Gun(gun_props) => &gun_props.weapon_props.item_props,
+            ItemConfig::Weapon(weapon_props) => &weapon_props.item_props,
+            ItemConfig::Item(item_props) => item_props,
+        }
+    }
+
+    /// Per item gun state a newly given item starts with, none for anything that is not a gun
+    pub fn starting_gun(&self) -> Option<Gun> {
+        match self {
+            ItemConfig::Gun(gun_props) => Some(Gun::full(gun_props)),
+            _ => None,
+        }
+    }
+}
+
+impl ItemProps {
+    /// Shortest time between shots, zero for items that can not fire
+    pub fn fire_interval(&self) -> Duration {
+        self.states.get(FIRE_STATE).map_or(Duration::ZERO, |fire| fire.duration)
+    }
+
+    /// Most one slot can hold, one for items that do not stack
+    pub fn stack_limit(&self) -> u16 {
+        self.max_stack.unwrap_or(1)
+    }
+
+    /// Zero to one through an equip state, straight to the end for instant or unknown states
+    fn equip_progress(&self, equip_state: &str, dur: Duration) -> f32 {
+        match self.equip_states.get(equip_state) {
+            Some(props) if !props.duration.is_zero() => (dur.as_secs_f32() / props.duration.as_secs_f32()).min(1.0),
+            _ => 1.0,
+        }
+    }
+}
+
+impl ItemStateEvent {
+    pub fn is_reload(&self) -> bool {
+        self.state == RELOAD_STATE
+    }
+}
+
+fn validate_states(
+    states: &HashMap<ItemStateName, ItemStateProps>, field: &str, required: &[&str],
+) -> Result<(), InvalidField> {
+    for &name in required {
+        ensure_field(states.contains_key(name), name, "is missing a required state").map_err(|err| err.within(field))?;
+    }
+    for (name, state) in states {
+        ensure_field(state.is_persistent || !state.duration.is_zero(), "duration", "must be above zero unless the state is persistent")
+            .map_err(|err| err.within(name).within(field))?;
+    }
+    Ok(())
+}
+
+impl Validate for ItemProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(!self.name.is_empty(), "name", "must not be empty")?;
+        ensure_field((0.0..=2.0).contains(&self.move_factor), "move_factor", "must be between 0 and 2")?;
+        validate_states(&self.states, "states", &[IDLE_STATE])?;
+        validate_states(&self.equip_states, "equip_states", &[EQUIPPING_STATE, EQUIPPED_STATE, UNEQUIPPING_STATE, UNEQUIPPED_STATE])?;
+        ensure_field(self.max_stack.map_or(true, |max_stack| max_stack > 0), "max_stack", "must be above zero")?;
+        self.sounds.validate().map_err(|err| err.within("sounds"))
+    }
+}
+
+impl Validate for ItemSoundProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        for (path, field) in [(&self.fire, "fire"), (&self.dry_fire, "dry_fire"), (&self.reload, "reload"), (&self.equip, "equip")] {
+            ensure_field(path.as_ref().map_or(true, |path| !path.is_empty()), field, "must not be empty")?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for WeaponProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.headshot_factor >= 0.0, "headshot_factor", "must not be negative")?;
+        self.item_props.validate().map_err(|err| err.within("item_props"))
+    }
+}
+
+impl Validate for RecoilProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.yaw_jitter >= 0.0, "yaw_jitter", "must not be negative")?;
+        ensure_field(self.recovery_rate >= 0.0, "recovery_rate", "must not be negative")
+    }
+}
+
+impl Validate for SpreadProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        for (value, field) in [
+            (self.base, "base"), (self.moving, "moving"), (self.airborne, "airborne"),
+            (self.bloom_per_shot, "bloom_per_shot"), (self.max_bloom, "max_bloom"), (self.recovery_rate, "recovery_rate"),
+        ] {
+            ensure_field(value >= 0.0, field, "must not be negative")?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for ProjectileProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.speed > 0.0, "speed", "must be above zero")?;
+        ensure_field(self.radius > 0.0, "radius", "must be above zero")?;
+        ensure_field(self.fuse >= 0.0, "fuse", "must not be negative")?;
+        ensure_field(self.blast_radius >= 0.0, "blast_radius", "must not be negative")?;
+        // Otherwise it would sit there forever
+        ensure_field(self.explode_on_contact || self.fuse > 0.0, "fuse", "must be above zero when not exploding on contact")
+    }
+}
+
+impl Validate for ShellEjectProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(!self.port_node.is_empty(), "port_node", "must not be empty")?;
+        ensure_field(self.speed >= 0.0, "speed", "must not be negative")?;
+        ensure_field(self.tumble >= 0.0, "tumble", "must not be negative")?;
+        ensure_field(self.radius > 0.0, "radius", "must be above zero")?;
+        ensure_field(self.length > 0.0, "length", "must be above zero")?;
+        ensure_field(self.lifetime > 0.0, "lifetime", "must be above zero")
+    }
+}
+
+impl Validate for GunProps {
+    fn validate(&self) -> Result<(), InvalidField> {
+        ensure_field(self.mag_size > 0, "mag_size", "must be above zero")?;
+        self.recoil.validate().map_err(|err| err.within("recoil"))?;
+        self.hipfire_spread.validate().map_err(|err| err.within("hipfire_spread"))?;
+        self.ads_spread.validate().map_err(|err| err.within("ads_spread"))?;
+        if let Some(projectile) = &self.projectile {
+            projectile.validate().map_err(|err| err.within("projectile"))?;
+        }
+        if let Some(shell_eject) = &self.shell_eject {
+            shell_eject.validate().map_err(|err| err.within("shell_eject"))?;
+        }
+        for (index, ammo_node) in self.ammo_display.iter().enumerate() {
+            ensure_field(!ammo_node.node.is_empty(), "node", "must not be empty")
+                .map_err(|err| err.within(&format!("ammo_display[{}]", index)))?;
+        }
+        let states = &self.weapon_props.item_props.states;
+        for name in [FIRE_STATE, RELOAD_STATE] {
+            ensure_field(states.contains_key(name), name, "is missing a required state for guns")
+                .map_err(|err| err.within("weapon_props.item_props.states"))?;
+        }
+        self.weapon_props.validate().map_err(|err| err.within("weapon_props"))
+    }
+}
+
+impl Validate for ItemConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        match self {
+            ItemConfig::Gun(gun_props) => gun_props.validate(),
+            ItemConfig::Weapon(weapon_props) => weapon_props.validate(),
+            ItemConfig::Item(item_props) => item_props.validate(),
+        }
+    }
+}
+
+impl ItemRegistry {
+    /// Keeps whichever config got a name first, returns whether this one did
+    pub fn register(&mut self, name: ItemName, handle: Handle<ItemConfig>) -> bool {
+        if self.configs.contains_key(&name) {
+            return false;
+        }
+        self.configs.insert(name, handle);
+        true
+    }
+}
+
+impl ItemPropsLookup<'_> {
+    pub fn config(&self, item_name: &ItemName) -> Result<&ItemConfig, ItemRegistryError> {
+        self.registry.configs.get(item_name)
+            .and_then(|handle| self.configs.get(handle))
+            .ok_or_else(|| ItemRegistryError::UnknownItem(item_name.clone()))
+    }
+
+    pub fn gun_props(&self, item_name: &ItemName) -> Result<&GunProps, ItemRegistryError> {
+        match self.config(item_name)? {
+            ItemConfig::Gun(gun_props) => Ok(gun_props),
+            _ => Err(ItemRegistryError::NotAGun(item_name.clone())),
+        }
+    }
+}
+
+impl ItemGiver<'_, '_> {
+    /// Takes as much of a pickup as fits, it is only removed from the world once all of it is taken
+    pub fn take_pickup(&mut self, inv_ent: Entity, inv: &mut Inventory, pickup_ent: Entity, pickup: &mut ItemPickup) -> bool {
+        // Touching it twice in one tick should not despawn it twice
+        if pickup.amount == 0 { return false; }
+        let config = self.item_props.config(&pickup.item_name).ok();
+        let left_over = inv.push_item(inv_ent, &mut self.commands, &mut self.item_query, &pickup.item_name, pickup.amount, config);
+        pickup.amount = left_over;
+        if left_over == 0 {
+            self.commands.entity(pickup_ent).despawn_recursive();
+        }
+        left_over == 0
+    }
+
+    /// Straight into the inventory without a pickup, returns how many did not fit
+    pub fn give(&mut self, inv_ent: Entity, inv: &mut Inventory, item_name: &ItemName, amount: u16) -> Result<u16, ItemRegistryError> {
+        let config = self.item_props.config(item_name)?;
+        Ok(inv.push_item(inv_ent, &mut self.commands, &mut self.item_query, item_name, amount, Some(config)))
+    }
 }
 
 #[derive(Default)]
-pub struct GunPropsAssetLoader;
+pub struct ItemConfigAssetLoader;
 
-impl AssetLoader for GunPropsAssetLoader {
-    type Asset = GunProps;
+impl AssetLoader for ItemConfigAssetLoader {
+    type Asset = ItemConfig;
     type Settings = ();
     type Error = RonLoaderError;
 
@@ -118,18 +648,20 @@ impl AssetLoader for GunPropsAssetLoader {
         &'a self,
         reader: &'a mut Reader,
         _settings: &'a Self::Settings,
-        _load_context: &'a mut LoadContext,
-    ) -> BoxedFuture<'a, Result<GunProps, Self::Error>> {
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<ItemConfig, Self::Error>> {
         Box::pin(async move {
             let mut bytes = Vec::new();
             reader.read_to_end(&mut bytes).await?;
-            let asset: GunProps = ron::de::from_bytes(&bytes)?;
+            // Picked by extension like the game config
+            let asset: ItemConfig = parse_by_extension(load_context.path(), &bytes)?;
+            asset.validate()?;
             Ok(asset)
         })
     }
 
     fn extensions(&self) -> &[&str] {
-        &["config.ron"]
+        &["item.ron", "item.json", "item.toml"]
     }
 }
 
@@ -142,39 +674,67 @@ impl AssetLoader for GunPropsAssetLoader {
 
 pub fn modify_equip_state_sys(
     time: Res<Time>,
-    _asset_server: Res<AssetServer>,
-    mut inv_query: Query<(&PlayerInput, &mut Inventory)>,
+    item_props: ItemPropsLookup,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut equipped_events: EventWriter<EquippedEvent>,
+    mut inv_query: Query<(Entity, &PlayerInput, &mut Inventory)>,
     mut item_query: Query<&mut Item>,
 ) {
-    for (input, mut inv) in inv_query.iter_mut() {
-        let has_valid_wanted = input.wanted_item_slot.is_some()
-            && inv.item_ents.0[input.wanted_item_slot.unwrap() as usize].is_some();
+    let queue_switch = config.get(&config_state.handle).is_some_and(|config| config.queue_weapon_switch);
+    for (inv_ent, input, mut inv) in inv_query.iter_mut() {
+        let has_valid_wanted = input.wanted_item_slot
+            .is_some_and(|slot| inv.item_ents.get(slot).is_some());
+        let wants_switch = has_valid_wanted && input.wanted_item_slot != inv.equipped_slot;
+
+        // When queueing, let the current equip finish instead of interrupting it
+        // Asking for the slot that is already equipping cancels any pending switch
+        let is_equipping = inv.equip_state_name == EQUIPPING_STATE;
+        inv.queued_slot = if queue_switch && is_equipping && wants_switch {
+            input.wanted_item_slot
+        } else {
+            None
+        };
 
         // Handle unequipping current item
-        let is_alr_unequipping = inv.equip_state_name == UNEQUIPPING_STATE;
-        if has_valid_wanted && input.wanted_item_slot != inv.equipped_slot && !is_alr_unequipping {
+        let is_alr_unequipping = inv.equip_state_name == UNEQUIPPING_STATE
+            || inv.equip_state_name == UNEQUIPPED_STATE;
+        if wants_switch && inv.queued_slot.is_none() && !is_alr_unequipping {
             inv.equip_state_name = EquipStateName::from(UNEQUIPPING_STATE);
             inv.equip_state_dur = Duration::ZERO;
         }
-        if inv.equipped_slot.is_none() { return; }
-        if inv.equipped_slot.is_none() { return; }
-
-        // Handle finishing equip state
-        inv.equip_state_dur = inv.equip_state_dur.saturating_add(time.delta());
-        while inv.equip_state_dur > Duration::from_millis(2000) {
-            match inv.equip_state_name.as_str() {
-                EQUIPPING_STATE => {
-                    inv.equip_state_name = EquipStateName::from(EQUIPPED_STATE);
-                }
-                UNEQUIPPING_STATE => {
-                    inv.equip_state_name = EquipStateName::from(UNEQUIPPED_STATE);
+        if inv.equipped_slot.is_some() {
+            // Handle finishing equip state
+            inv.equip_state_dur = inv.equip_state_dur.saturating_add(time.delta());
+            let equip_states = inv.equipped_slot
+                .and_then(|slot| inv.item_ents.get(slot))
+                .and_then(|item_ent| item_query.get(item_ent).ok())
+                .and_then(|item| item_props.config(&item.name).ok())
+                .map(|config| &config.item_props().equip_states);
+            while let Some(duration) = state_duration(equip_states, &inv.equip_state_name).filter(|&duration| inv.equip_state_dur > duration) {
+                match inv.equip_state_name.as_str() {
+                    EQUIPPING_STATE => {
+                        // Roll straight into the queued switch with the leftover time
+                        let next_state = if inv.queued_slot.take().is_some() { UNEQUIPPING_STATE } else { EQUIPPED_STATE };
+                        inv.equip_state_name = EquipStateName::from(next_state);
+                        if let Some(item_ent) = inv.equipped_slot.and_then(|slot| inv.item_ents.get(slot)).filter(|_| next_state == EQUIPPED_STATE) {
+                            equipped_events.send(EquippedEvent { item_ent, inv_ent });
+                        }
+                    }
+                    UNEQUIPPING_STATE => {
+                        inv.equip_state_name = EquipStateName::from(UNEQUIPPED_STATE);
+                    }
+                    _ => {}
                 }
-                _ => {}
+                inv.equip_state_dur = inv.equip_state_dur.saturating_sub(duration);
             }
-            inv.equip_state_dur = inv.equip_state_dur.saturating_sub(Duration::from_millis(2000));
+        } else {
+            // Nothing is held so there is nothing to put away, go straight to picking what to equip
+            inv.equip_state_name = EquipStateName::from(UNEQUIPPED_STATE);
+            inv.equip_state_dur = Duration::ZERO;
         }
 
-        if inv.equip_state_name != UNEQUIPPED_STATE { return; }
+        if inv.equip_state_name != UNEQUIPPED_STATE { continue; }
 
         // We have unequipped the last slot, so we need to starting equipping the new slot
         if has_valid_wanted {
@@ -183,41 +743,55 @@ pub fn modify_equip_state_sys(
         } else {
             inv.equipped_slot = inv.find_replacement(&mut item_query);
         }
-        inv.equip_state_name = EquipStateName::from(EQUIPPING_STATE);
+        if inv.equipped_slot.is_some() {
+            inv.equip_state_name = EquipStateName::from(EQUIPPING_STATE);
+        }
     }
 }
 
 pub fn modify_item_sys(
     time: Res<Time>,
-    mut item_query: Query<&mut Item>,
+    item_props: ItemPropsLookup,
+    mut item_events: ItemEvents,
+    mut item_query: Query<(Entity, &mut Item, Option<&mut Gun>)>,
     player_query: Query<(&PlayerInput, &Inventory)>,
 ) {
-    for mut item in item_query.iter_mut() {
-        let (input, inv): (&PlayerInput, &Inventory) = player_query.get(item.inv_ent).unwrap();
+    for (item_ent, mut item, mut gun) in item_query.iter_mut() {
+        // The owner can be despawned before its items are, e.g. when a client leaves
+        let Ok((input, inv)) = player_query.get(item.inv_ent) else { continue; };
         let is_equipped = inv.equipped_slot == Some(item.inv_slot);
         if is_equipped {
-            item.modify(inv, input, &time);
-            while item.state_dur > Duration::from_millis(2000) {
-                match item.state_name.as_str() {
-                    IDLE_STATE | RELOAD_STATE | FIRE_STATE => {
-                        item.state_name = ItemStateName::from(IDLE_STATE);
-                    }
-                    _ => unimplemented!()
-                }
-                item.state_dur = item.state_dur.saturating_sub(Duration::from_millis(2000));
-            }
+            let config = item_props.config(&item.name).ok();
+            let mag_size = match config {
+                Some(ItemConfig::Gun(props)) => Some(props.mag_size),
+                _ => None,
+            };
+            let mut held = HeldItem { props: config.map(ItemConfig::item_props), gun: gun.as_deref_mut().zip(mag_size) };
+            // Finished states roll over in here, so they always go through start_state and send their events
+            item.modify(item_ent, inv, input, &mut held, &time, &mut item_events);
         }
     }
 }
 
 pub fn item_pickup_sys(
     phys_ctx: Res<RapierContext>,
-    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut item_giver: ItemGiver,
     mut inv_query: Query<&mut Inventory>,
-    mut item_query: Query<&mut Item>,
     mut pickup_query: Query<&mut ItemPickup>,
 ) {
-    for (ent1, ent2, _inter) in phys_ctx.intersection_pairs() {
+    if !config.get(&config_state.handle).is_some_and(|config| config.auto_pickup) { return; }
+
+    // Placed pickups are sensors, dropped pickups are solid so they can land on terrain
+    let touching = phys_ctx.intersection_pairs()
+        .filter(|(_, _, intersecting)| *intersecting)
+        .map(|(ent1, ent2, _)| (ent1, ent2))
+        .chain(phys_ctx.contact_pairs()
+            .filter(|pair| pair.has_any_active_contacts())
+            .map(|pair| (pair.collider1(), pair.collider2())))
+        .collect::<Vec<_>>();
+    for (ent1, ent2) in touching {
         let mut pickup_ent: Option<Entity> = None;
         let mut player_ent: Option<Entity> = None;
         if pickup_query.get(ent1).is_ok() && inv_query.get(ent2).is_ok() {
@@ -229,82 +803,224 @@ pub fn item_pickup_sys(
         }
         if let Some(pickup_ent) = pickup_ent {
             if let Some(player_ent) = player_ent {
-                let pickup = pickup_query.get_mut(pickup_ent).unwrap();
+                let mut pickup = pickup_query.get_mut(pickup_ent).unwrap();
                 let mut inv = inv_query.get_mut(player_ent).unwrap();
-                inv.push_item(player_ent, &mut commands, &mut item_query, &pickup.item_name);
-                commands.entity(pickup_ent).despawn_recursive();
+                item_giver.take_pickup(player_ent, &mut inv, pickup_ent, &mut pickup);
             }
         }
     }
 }
 
+pub fn item_interact_sys(
+    phys_ctx: Res<RapierContext>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut item_giver: ItemGiver,
+    mut player_query: Query<(Entity, &PlayerInput, &PlayerController, &Transform, &mut Inventory, &mut InteractTarget)>,
+    mut pickup_query: Query<&mut ItemPickup>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for (player_ent, input, controller, transform, mut inv, mut target) in player_query.iter_mut() {
+        // Cast against everything so pickups behind terrain are not reachable
+        let eye = transform.translation + Vec3::Y * controller.eye_height();
+        let fwd = look_quat(input.pitch, input.yaw) * -Vec3::Z;
+        let filter = gameplay_query_filter().exclude_collider(player_ent);
+        target.0 = phys_ctx.cast_ray(eye, fwd, config.interact_range, true, filter)
+            .map(|(hit_ent, _toi)| hit_ent)
+            .filter(|&hit_ent| pickup_query.contains(hit_ent));
+
+        if !input.flags.contains(PlayerInputFlags::Interact) { continue; }
+        let Some(pickup_ent) = target.0 else { continue; };
+        let mut pickup = pickup_query.get_mut(pickup_ent).unwrap();
+        if item_giver.take_pickup(player_ent, &mut inv, pickup_ent, &mut pickup) {
+            target.0 = None;
+        }
+    }
+}
+
+pub fn drop_item_sys(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut inv_query: Query<(&PlayerInput, &PlayerController, &Transform, &mut Inventory)>,
+    item_query: Query<&Item>,
+) {
+    for (input, controller, transform, mut inv) in inv_query.iter_mut() {
+        if !input.flags.contains(PlayerInputFlags::Drop) { continue; }
+        let Some(slot) = inv.equipped_slot else { continue; };
+        let Some(item_ent) = inv.item_ents.get(slot) else { continue; };
+        let Ok(item) = item_query.get(item_ent) else { continue; };
+
+        // Toss forward from in front of the eyes so we do not immediately touch it again
+        let fwd = look_quat(input.pitch, input.yaw) * -Vec3::Z;
+        let origin = transform.translation + Vec3::Y * controller.eye_height() + fwd;
+        let pickup_ent = spawn_item_pickup(&mut commands, &asset_server, item.name.clone(), item.amount, Transform::from_translation(origin));
+        commands.entity(pickup_ent).insert((
+            RigidBody::Dynamic,
+            Collider::ball(0.25),
+            LockedAxes::ROTATION_LOCKED,
+            Velocity::linear(fwd * DROP_SPEED + Vec3::Y * 2.0),
+        ));
+        commands.entity(item_ent).despawn_recursive();
+
+        inv.item_ents.0[slot as usize] = None;
+        if inv.prev_equipped_slot == Some(slot) {
+            inv.prev_equipped_slot = None;
+        }
+        // Equip state machine picks a replacement once it sees we are unequipped
+        inv.equip_state_name = EquipStateName::from(UNEQUIPPED_STATE);
+        inv.equip_state_dur = Duration::ZERO;
+    }
+}
+
+pub fn spawn_item_pickup(
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    item_name: ItemName,
+    amount: u16,
+    transform: Transform,
+) -> Entity {
+    let scene = asset_server.load(format!("models/{}.glb#Scene0", item_name));
+    commands.spawn((
+        transform,
+        GlobalTransform::default(),
+        VisibilityBundle::default(),
+        ItemPickup { item_name, amount },
+    )).with_children(|parent| {
+        parent.spawn((
+            SceneBundle { scene, ..default() },
+            ItemPickupVisual,
+        ));
+    }).id()
+}
+
 impl Default for Inventory {
     fn default() -> Self {
-        Self {
-            equipped_slot: None,
-            prev_equipped_slot: None,
-            equip_state_name: EquipStateName::from(UNEQUIPPED_STATE),
-            equip_state_dur: Duration::ZERO,
-            item_ents: Items([None; 10]),
-        }
+        Self::with_slots(DEFAULT_INVENTORY_SLOTS)
+    }
+}
+
+/// Config and ammo of the item being run through its states, looked up once per tick
+struct HeldItem<'a> {
+    props: Option<&'a ItemProps>,
+    /// With the magazine size from the gun's config
+    gun: Option<(&'a mut Gun, u16)>,
+}
+
+/// None for persistent states, which last until something else moves the item on
+fn state_duration(states: Option<&HashMap<ItemStateName, ItemStateProps>>, state: &str) -> Option<Duration> {
+    match states.and_then(|states| states.get(state)) {
+        Some(props) if props.is_persistent => None,
+        Some(props) => Some(props.duration),
+        None => Some(UNTIMED_STATE_DURATION),
     }
 }
 
 impl Item {
-    fn start_state(&mut self, _inv: &Inventory, state: ItemStateName, dur: Duration) {
+    pub fn is_reloading(&self) -> bool {
+        self.state_name == RELOAD_STATE
+    }
+
+    /// Firing spends a round here, so every shot the fire event goes out for is paid for.
+    /// An empty gun still goes through the fire state so clicks come at the fire rate, but nothing is shot
+    fn start_state(
+        &mut self, item_ent: Entity, held: &mut HeldItem, state: ItemStateName, dur: Duration,
+        events: &mut ItemEvents,
+    ) {
         self.state_name = state;
         self.state_dur = dur;
-        match self.state_name.as_str() {
-            FIRE_STATE => {
-                println!("Boom!");
+        if self.state_name == FIRE_STATE {
+            match &mut held.gun {
+                Some((gun, _)) if gun.ammo == 0 => events.dry_fire.send(DryFireEvent { item_ent, inv_ent: self.inv_ent }),
+                Some((gun, _)) => {
+                    gun.ammo -= 1;
+                    events.fire.send(FireEvent { item_ent, inv_ent: self.inv_ent });
+                }
+                None => events.fire.send(FireEvent { item_ent, inv_ent: self.inv_ent }),
             }
-            _ => {}
         }
+        events.state.send(ItemStateEvent { item_ent, inv_ent: self.inv_ent, state: self.state_name.clone() });
     }
 
-    fn can_fire(&mut self, inv: &Inventory, at_state_end: bool) -> bool {
-        match (inv.equip_state_name.as_str(), self.state_name.as_str(), at_state_end) {
-            (EQUIPPED_STATE, FIRE_STATE, true) | (EQUIPPED_STATE, IDLE_STATE, _) => true,
-            _ => false,
-        }
+    /// Only fully equipped and idle, so nothing fires mid swap or mid reload
+    pub fn can_fire(&self, inv: &Inventory) -> bool {
+        inv.is_equipped() && self.state_name == IDLE_STATE
     }
 
-    fn modify_status(&mut self, inv: &Inventory, input: &PlayerInput, time: &Res<Time>) {
-        while self.state_dur > Duration::from_millis(2000) {
+    fn modify_status(
+        &mut self, item_ent: Entity, inv: &Inventory, input: &PlayerInput, held: &mut HeldItem,
+        time: &Res<Time>, events: &mut ItemEvents,
+    ) {
+        let states = held.props.map(|props| &props.states);
+        while let Some(duration) = state_duration(states, &self.state_name).filter(|&duration| self.state_dur > duration) {
             // We have just finished a state
-            self.end_status(inv, input, time);
+            self.end_status(held);
             let next_state = self.next_state(inv, input);
-            self.start_state(inv, next_state, self.state_dur - Duration::from_millis(2000));
+            self.start_state(item_ent, held, next_state, self.state_dur - duration, events);
         }
         self.state_dur = self.state_dur.saturating_add(time.delta());
     }
 
-    fn next_state(&mut self, inv: &Inventory, input: &PlayerInput) -> ItemStateName {
-        let do_fire = input.flags.contains(PlayerInputFlags::Fire) && self.can_fire(inv, true);
-        match (self.state_name.as_str(), do_fire) {
-            (FIRE_STATE, true) => ItemStateName::from(FIRE_STATE),
-            _ => ItemStateName::from(IDLE_STATE)
+    fn next_state(&self, inv: &Inventory, input: &PlayerInput) -> ItemStateName {
+        // Holding fire chains straight into the next shot as the last one ends, same rules as firing from idle
+        let refire = self.state_name == FIRE_STATE && inv.is_equipped() && input.flags.contains(PlayerInputFlags::Fire);
+        ItemStateName::from(if refire { FIRE_STATE } else { IDLE_STATE })
+    }
+
+    /// Rounds only move once the reload has played out in full
+    fn end_status(&mut self, held: &mut HeldItem) {
+        if self.state_name == RELOAD_STATE {
+            if let Some((gun, mag_size)) = &mut held.gun {
+                gun.reload(*mag_size);
+            }
         }
     }
 
-    fn end_status(&mut self, _inv: &Inventory, _input: &PlayerInput, _time: &Res<Time>) {}
+    /// Only from idle, so holding the key does not restart a reload that is already going
+    fn can_reload(&self, inv: &Inventory, held: &HeldItem) -> bool {
+        inv.is_equipped() && self.state_name == IDLE_STATE
+            && held.gun.as_ref().is_some_and(|(gun, mag_size)| gun.can_reload(*mag_size))
+    }
 
-    fn modify(&mut self, inv: &Inventory, input: &PlayerInput, time: &Res<Time>) {
-        if input.flags.contains(PlayerInputFlags::Fire) && self.can_fire(inv, false) {
-            self.start_state(inv, ItemStateName::from(FIRE_STATE), Duration::ZERO);
-        } else if input.flags.contains(PlayerInputFlags::Reload) {
-            self.start_state(inv, ItemStateName::from(RELOAD_STATE), Duration::ZERO);
+    fn modify(
+        &mut self, item_ent: Entity, inv: &Inventory, input: &PlayerInput, held: &mut HeldItem,
+        time: &Res<Time>, events: &mut ItemEvents,
+    ) {
+        if input.flags.contains(PlayerInputFlags::Fire) && self.can_fire(inv) {
+            self.start_state(item_ent, held, ItemStateName::from(FIRE_STATE), Duration::ZERO, events);
+        } else if input.flags.contains(PlayerInputFlags::Reload) && self.can_reload(inv, held) {
+            self.start_state(item_ent, held, ItemStateName::from(RELOAD_STATE), Duration::ZERO, events);
         }
-        self.modify_status(inv, input, time);
+        self.modify_status(item_ent, inv, input, held, time, events);
     }
 }
 
 impl Inventory {
+    pub fn is_equipped(&self) -> bool {
+        self.equip_state_name == EQUIPPED_STATE
+    }
+
+    /// Never drops items, if some would not fit the extra slots they are in are kept
+    pub fn resize(&mut self, slot_count: u8) {
+        let slots_used = self.item_ents.0.iter().rposition(Option::is_some).map_or(0, |slot| slot + 1);
+        self.item_ents.0.resize(usize::max(slot_count as usize, slots_used), None);
+    }
+
+    pub fn with_slots(slot_count: u8) -> Self {
+        Self {
+            equipped_slot: None,
+            prev_equipped_slot: None,
+            queued_slot: None,
+            equip_state_name: EquipStateName::from(UNEQUIPPED_STATE),
+            equip_state_dur: Duration::ZERO,
+            item_ents: Items(vec![None; slot_count as usize]),
+        }
+    }
+
     fn find_replacement(&self, item_query: &mut Query<&mut Item>) -> Option<u8> {
-        if self.prev_equipped_slot.is_none() {
-            self.find_slot(item_query, |item| item.is_some())
-        } else {
-            self.prev_equipped_slot
+        // Previous slot may have been emptied since, e.g. by dropping
+        match self.prev_equipped_slot.filter(|&slot| self.item_ents.get(slot).is_some()) {
+            Some(slot) => Some(slot),
+            None => self.find_slot(item_query, |item| item.is_some()),
         }
     }
 
@@ -324,37 +1040,60 @@ impl Inventory {
         None
     }
 
+    /// Tops up stacks of the same item first and only spills what is left into open slots.
+    /// Without a config the item is kept one per slot. Returns how many did not fit
     pub fn push_item(
         &mut self,
         inv_ent: Entity,
         commands: &mut Commands,
         item_query: &mut Query<&mut Item>,
         item_name: &ItemName,
-    ) {
-        let open_slot = self.find_slot(item_query, |item| item.is_none());
-        if let Some(open_slot) = open_slot {
-            self.set_item(inv_ent, commands, item_name, open_slot);
+        mut amount: u16,
+        config: Option<&ItemConfig>,
+    ) -> u16 {
+        let max_stack = config.map_or(1, |config| config.item_props().stack_limit());
+        for &item_ent in self.item_ents.0.iter().flatten() {
+            if amount == 0 { break; }
+            let Ok(mut item) = item_query.get_mut(item_ent) else { continue; };
+            if item.name != *item_name || item.amount >= max_stack { continue; }
+            let added = amount.min(max_stack - item.amount);
+            item.amount += added;
+            amount -= added;
+        }
+        // Checked directly since items spawned this tick are not in the query yet
+        while amount > 0 {
+            let Some(open_slot) = self.item_ents.0.iter().position(Option::is_none) else { break; };
+            let added = amount.min(max_stack);
+            self.set_item(inv_ent, commands, item_name, added, open_slot as u8, config.and_then(ItemConfig::starting_gun));
+            amount -= added;
         }
+        amount
     }
 
+    /// Replaces whatever is in the slot, guns are spawned with the ammo they are given
     pub fn set_item(
         &mut self,
         inv_ent: Entity,
         commands: &mut Commands,
-        item_name: &ItemName, slot: u8,
+        item_name: &ItemName, amount: u16, slot: u8,
+        gun: Option<Gun>,
     ) -> &mut Self {
         let existing_item_ent = self.item_ents.0[slot as usize];
         if let Some(existing_item_ent) = existing_item_ent {
             commands.entity(existing_item_ent).despawn()
         }
-        let item_ent = commands.spawn(Item {
+        let mut item_commands = commands.spawn(Item {
             name: item_name.clone(),
-            amount: 1,
+            amount,
             state_name: ItemStateName::from(IDLE_STATE),
             state_dur: Duration::ZERO,
             inv_ent,
             inv_slot: slot,
-        }).id();
+        });
+        if let Some(gun) = gun {
+            item_commands.insert(gun);
+        }
+        let item_ent = item_commands.id();
         if self.equipped_slot.is_none() {
             self.equipped_slot = Some(slot);
             self.equip_state_dur = Duration::ZERO;
@@ -372,37 +1111,136 @@ impl Inventory {
 // ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
 // ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
 
+type ItemModelQuery<'a> = (&'a Item, Option<&'a mut Transform>, Option<&'a mut Visibility>, Has<Handle<Scene>>);
+
+type ItemModelPlayerQuery<'a> = (&'a Inventory, Option<&'a AimDownSights>, Option<&'a mut ViewModelMotion>);
+
+type ViewModelPlayerQuery<'a> = (
+    &'a PlayerInput, &'a PlayerController, &'a Velocity, &'a Footsteps, Option<&'a AimDownSights>, &'a mut ViewModelMotion,
+);
+
+pub fn view_model_motion_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut player_query: Query<ViewModelPlayerQuery, With<LocalPlayer>>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let props = &config.view_model;
+    let dt = time.delta_seconds();
+    for (input, controller, velocity, footsteps, ads, mut motion) in player_query.iter_mut() {
+        let is_grounded = matches!(controller.move_mode, MoveMode::Ground) && controller.grounded;
+        let speed_ratio = if is_grounded { (velocity.linvel.xz().length() / config.max_speed).min(1.0) } else { 0.0 };
+        motion.bob_weight += (speed_ratio - motion.bob_weight) * (1.0 - f32::exp(-BOB_EASE_RATE * dt));
+        // Driven by distance walked instead of time, so the item dips on every footstep and swings once per left and right
+        let phase = footsteps.cycle(config.footstep_stride) * TAU;
+        let bob = Vec2::new(phase.sin(), (phase * 2.0).sin()) * props.bob_amplitude * motion.bob_weight;
+
+        let look = Vec2::new(input.yaw, input.pitch);
+        let look_rate = if dt > 0.0 { (look - motion.last_look) / dt } else { Vec2::ZERO };
+        motion.last_look = look;
+        // Turning left leaves the item behind to the right, looking up leaves it below
+        let sway_target = (Vec2::new(look_rate.x, -look_rate.y) * props.sway_factor).clamp_length_max(props.max_sway);
+        motion.sway = motion.sway.lerp(sway_target, 1.0 - f32::exp(-props.sway_smoothing * dt));
+
+        let breath = props.breath_amplitude * f32::sin(time.elapsed_seconds() * props.breath_frequency * 2.0 * PI);
+
+        let ads_progress = ads.map_or(0.0, |ads| smoothstep(ads.progress));
+        let scale = 1.0 + (props.ads_factor - 1.0) * ads_progress;
+        motion.offset = Vec3::new(bob.x + motion.sway.x, bob.y + motion.sway.y + breath, 0.0) * scale;
+    }
+}
+
 pub fn render_inventory_sys(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    item_query: Query<&mut Item>,
-    player_query: Query<&Inventory>,
+    item_props: ItemPropsLookup,
+    mut item_query: Query<ItemModelQuery, Without<Projection>>,
+    mut player_query: Query<ItemModelPlayerQuery, With<LocalPlayer>>,
     camera_query: Query<&Transform, With<Projection>>,
 ) {
-    for inv in player_query.iter() {
+    for (inv, ads, mut motion) in player_query.iter_mut() {
+        let ads_progress = ads.map_or(0.0, |ads| smoothstep(ads.progress));
+        let offset = HIP_OFFSET.lerp(SIGHT_OFFSET, ads_progress) + motion.as_ref().map_or(Vec3::ZERO, |motion| motion.offset);
         for item in inv.item_ents.0.iter() {
             if let Some(item_ent) = item {
-                if let Ok(item) = item_query.get(*item_ent) {
+                if let Ok((item, item_transform, item_visibility, has_scene)) = item_query.get_mut(*item_ent) {
                     let is_equipped = inv.equipped_slot == Some(item.inv_slot);
                     let mut transform = Transform::default();
-                    let scene_handle = asset_server.load(format!("models/{}.glb#Scene0", item.name));
+                    let mut visibility = Visibility::Hidden;
                     if is_equipped {
-                        transform = camera_query.single().mul_transform(Transform::from_xyz(0.4, -0.3, -1.0));
+                        let props = item_props.config(&item.name).ok().map(|config| config.item_props());
+                        let progress = props.map_or(1.0, |props| props.equip_progress(&inv.equip_state_name, inv.equip_state_dur));
+                        let last_lowered = motion.as_ref().map_or(0.0, |motion| motion.lowered);
+                        let lowered = match inv.equip_state_name.as_str() {
+                            EQUIPPING_STATE => 1.0 - progress,
+                            // Keep going down from wherever we were, switching mid raise would otherwise pop it back up first
+                            UNEQUIPPING_STATE => f32::max(progress, last_lowered),
+                            UNEQUIPPED_STATE => 1.0,
+                            _ => 0.0,
+                        };
+                        if let Some(motion) = motion.as_mut() {
+                            motion.lowered = lowered;
+                        }
+                        let (lowered_offset, lowered_angles) = props.map_or((Vec3::ZERO, Vec3::ZERO), |props| {
+                            (props.equip_animation.lowered_offset, props.equip_animation.lowered_angles)
+                        });
+                        let eased = smoothstep(lowered);
+                        let angles = lowered_angles * eased;
+                        let held = Transform::from_translation(offset + lowered_offset * eased)
+                            .with_rotation(Quat::from_euler(EulerRot::XYZ, angles.x, angles.y, angles.z));
+                        transform = camera_query.single().mul_transform(held);
+                        // Only disappear once all the way down, so it never vanishes mid motion
+                        if lowered < 1.0 {
+                            visibility = Visibility::Visible;
+                        }
                     }
-                    commands.entity(*item_ent).insert(
-                        SceneBundle {
-                            scene: scene_handle,
-                            transform,
-                            visibility: Visibility::Visible,
-                            ..default()
+                    // Inserting the scene again would respawn the whole model, losing any changes to its nodes
+                    match item_transform {
+                        Some(mut item_transform) if has_scene => {
+                            *item_transform = transform;
+                            if let Some(mut item_visibility) = item_visibility {
+                                *item_visibility = visibility;
+                            }
+                        }
+                        _ => {
+                            let scene_handle = asset_server.load(format!("models/{}.glb#Scene0", item.name));
+                            commands.entity(*item_ent).insert(
+                                SceneBundle {
+                                    scene: scene_handle,
+                                    transform,
+                                    visibility,
+                                    ..default()
+                                }
+                            );
                         }
-                    );
+                    }
                 }
             }
         }
     }
 }
 
+/// Runs after logic every frame so the model matches the magazine on the same frame a shot or reload changes it
+pub fn render_ammo_display_sys(
+    item_props: ItemPropsLookup,
+    gun_query: Query<(Entity, &Item, &Gun)>,
+    children_query: Query<&Children>,
+    mut node_query: Query<(&Name, &mut Visibility)>,
+) {
+    for (item_ent, item, gun) in gun_query.iter() {
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        if props.ammo_display.is_empty() { continue; }
+
+        for node_ent in children_query.iter_descendants(item_ent) {
+            let Ok((name, mut visibility)) = node_query.get_mut(node_ent) else { continue; };
+            let Some(ammo_node) = props.ammo_display.iter().find(|ammo_node| ammo_node.node == name.as_str()) else { continue; };
+            let shown = if gun.ammo >= ammo_node.min_ammo { Visibility::Inherited } else { Visibility::Hidden };
+            visibility.set_if_neq(shown);
+        }
+    }
+}
+
 pub fn item_pickup_animate_sys(
     time: Res<Time>,
     mut pickup_query: Query<&mut Transform, With<ItemPickupVisual>>,
@@ -414,3 +1252,299 @@ pub fn item_pickup_animate_sys(
         transform.translation = Vec3::new(0.0, height, 0.0);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use bevy::ecs::system::RunSystemOnce;
+    use flagset::FlagSet;
+
+    use super::*;
+
+    const RIFLE: &str = include_str!("../../assets/items/rifle.item.ron");
+    const AMMO: &str = r#"Item((
+        name: "ammo",
+        move_factor: 1.0,
+        states: {
+            "idle": (duration: (secs: 0, nanos: 0), is_persistent: true),
+        },
+        equip_states: {
+            "equipping": (duration: (secs: 0, nanos: 250000000), is_persistent: false),
+            "equipped": (duration: (secs: 0, nanos: 0), is_persistent: true),
+            "unequipping": (duration: (secs: 0, nanos: 250000000), is_persistent: false),
+            "unequipped": (duration: (secs: 0, nanos: 0), is_persistent: true),
+        },
+        max_stack: Some(50),
+    ))"#;
+
+    const KNIFE: &str = r#"Weapon((
+        damage: 55,
+        headshot_factor: 1.0,
+        item_props: (
+            name: "knife",
+            move_factor: 1.1,
+            states: {
+                "idle": (duration: (secs: 0, nanos: 0), is_persistent: true),
+                "fire": (duration: (secs: 0, nanos: 400000000), is_persistent: false),
+            },
+            equip_states: {
+                "equipping": (duration: (secs: 0, nanos: 200000000), is_persistent: false),
+                "equipped": (duration: (secs: 0, nanos: 0), is_persistent: true),
+                "unequipping": (duration: (secs: 0, nanos: 200000000), is_persistent: false),
+                "unequipped": (duration: (secs: 0, nanos: 0), is_persistent: true),
+            },
+        ),
+    ))"#;
+
+    /// Items and events without the asset server, with a rifle and stacking ammo registered and the default config
+    fn item_app() -> App {
+        let mut configs = Assets::<ItemConfig>::default();
+        let mut registry = ItemRegistry::default();
+        for source in [RIFLE, AMMO] {
+            let config: ItemConfig = ron::from_str(source).unwrap();
+            registry.register(config.item_props().name.clone(), configs.add(config));
+        }
+        let mut game_configs = Assets::<Config>::default();
+        let config_state = ConfigState { handle: game_configs.add(Config::default()) };
+        let mut app = App::new();
+        app.insert_resource(configs)
+            .insert_resource(registry)
+            .insert_resource(game_configs)
+            .insert_resource(config_state)
+            .init_resource::<Time>()
+            .add_event::<FireEvent>()
+            .add_event::<DryFireEvent>()
+            .add_event::<ItemStateEvent>()
+            .add_event::<EquippedEvent>();
+        app
+    }
+
+    fn rifle(inv_ent: Entity, state: &str) -> Item {
+        Item {
+            name: ItemName::from("rifle"),
+            amount: 1,
+            state_name: ItemStateName::from(state),
+            state_dur: Duration::ZERO,
+            inv_ent,
+            inv_slot: 0,
+        }
+    }
+
+    /// Player holding a fully equipped rifle in slot zero, returns the player and the rifle
+    fn spawn_armed_player(app: &mut App, gun: Gun, flags: impl Into<FlagSet<PlayerInputFlags>>) -> (Entity, Entity) {
+        let player_ent = app.world.spawn_empty().id();
+        let item_ent = app.world.spawn((rifle(player_ent, IDLE_STATE), gun)).id();
+        let mut inv = Inventory::with_slots(2);
+        inv.item_ents.0[0] = Some(item_ent);
+        inv.equipped_slot = Some(0);
+        inv.equip_state_name = EquipStateName::from(EQUIPPED_STATE);
+        app.world.entity_mut(player_ent).insert((inv, PlayerInput { flags: flags.into(), ..default() }));
+        (player_ent, item_ent)
+    }
+
+    fn event_count<E: Event>(app: &App) -> usize {
+        app.world.resource::<Events<E>>().len()
+    }
+
+    /// Amount in each slot
+    fn stacks(app: &App, inv_ent: Entity) -> Vec<Option<u16>> {
+        let inv = app.world.get::<Inventory>(inv_ent).unwrap();
+        inv.item_ents.0.iter().map(|item_ent| item_ent.map(|item_ent| app.world.get::<Item>(item_ent).unwrap().amount)).collect()
+    }
+
+    /// Picks up one pickup per tick like touching them one after another, returns whether all of it was taken
+    fn pick_up(app: &mut App, inv_ent: Entity, item_name: &str, amount: u16) -> bool {
+        let pickup_ent = app.world.spawn(ItemPickup { item_name: ItemName::from(item_name), amount }).id();
+        app.world.run_system_once(move |mut item_giver: ItemGiver, mut inv_query: Query<&mut Inventory>, mut pickup_query: Query<&mut ItemPickup>| {
+            let mut inv = inv_query.get_mut(inv_ent).unwrap();
+            let mut pickup = pickup_query.get_mut(pickup_ent).unwrap();
+            item_giver.take_pickup(inv_ent, &mut inv, pickup_ent, &mut pickup)
+        })
+    }
+
+    fn set_flags(app: &mut App, player_ent: Entity, flags: impl Into<FlagSet<PlayerInputFlags>>) {
+        app.world.get_mut::<PlayerInput>(player_ent).unwrap().flags = flags.into();
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_toml() {
+        for (source, variant) in [(RIFLE, "Gun"), (KNIFE, "Weapon"), (AMMO, "Item")] {
+            let config: ItemConfig = ron::from_str(source).unwrap();
+            config.validate().unwrap();
+            let toml = toml::to_string(&config).unwrap();
+            assert!(toml.starts_with(&format!("[{}", variant)), "{} is tagged as {}", toml, variant);
+            let parsed: ItemConfig = parse_by_extension(Path::new("item.item.toml"), toml.as_bytes()).unwrap();
+            assert_eq!(parsed, config);
+        }
+    }
+
+    #[test]
+    fn variant_tag_picks_the_props() {
+        let knife: ItemConfig = ron::from_str(KNIFE).unwrap();
+        assert!(matches!(&knife, ItemConfig::Weapon(props) if props.damage == 55));
+        assert_eq!(knife.item_props().name, "knife");
+        assert!(knife.starting_gun().is_none());
+        let rifle: ItemConfig = ron::from_str(RIFLE).unwrap();
+        assert_eq!(rifle.starting_gun(), Some(Gun { ammo: 30, ammo_in_reserve: 90 }));
+        // Gun fields under the wrong tag are rejected instead of silently dropped
+        assert!(ron::from_str::<ItemConfig>(&RIFLE.replacen("Gun(", "Weapon(", 1)).is_err());
+    }
+
+    #[test]
+    fn small_stacks_merge_and_only_overflow_spills() {
+        let mut app = item_app();
+        let inv_ent = app.world.spawn(Inventory::with_slots(3)).id();
+        assert!(pick_up(&mut app, inv_ent, "ammo", 20));
+        assert!(pick_up(&mut app, inv_ent, "ammo", 20));
+        assert_eq!(stacks(&app, inv_ent), [Some(40), None, None]);
+        assert!(pick_up(&mut app, inv_ent, "ammo", 20));
+        assert_eq!(stacks(&app, inv_ent), [Some(50), Some(10), None]);
+        assert!(app.world.query::<&ItemPickup>().iter(&app.world).next().is_none());
+    }
+
+    #[test]
+    fn pickup_stays_with_what_did_not_fit() {
+        let mut app = item_app();
+        let inv_ent = app.world.spawn(Inventory::with_slots(1)).id();
+        assert!(!pick_up(&mut app, inv_ent, "ammo", 70));
+        assert_eq!(stacks(&app, inv_ent), [Some(50)]);
+        let left_over = app.world.query::<&ItemPickup>().single(&app.world).amount;
+        assert_eq!(left_over, 20);
+    }
+
+    fn slot_gun(app: &App, inv_ent: Entity, slot: u8) -> Option<Gun> {
+        let item_ent = app.world.get::<Inventory>(inv_ent).unwrap().item_ents.get(slot).unwrap();
+        app.world.get::<Gun>(item_ent).copied()
+    }
+
+    #[test]
+    fn picked_up_guns_start_full() {
+        let mut app = item_app();
+        let inv_ent = app.world.spawn(Inventory::with_slots(2)).id();
+        assert!(pick_up(&mut app, inv_ent, "rifle", 1));
+        assert!(pick_up(&mut app, inv_ent, "ammo", 1));
+        assert_eq!(slot_gun(&app, inv_ent, 0), Some(Gun { ammo: 30, ammo_in_reserve: 90 }));
+        assert_eq!(slot_gun(&app, inv_ent, 1), None);
+    }
+
+    #[test]
+    fn set_item_keeps_the_ammo_it_is_given() {
+        let mut app = item_app();
+        let inv_ent = app.world.spawn(Inventory::with_slots(1)).id();
+        app.world.run_system_once(move |mut commands: Commands, mut inv_query: Query<&mut Inventory>| {
+            let gun = Gun { ammo: 3, ammo_in_reserve: 7 };
+            inv_query.get_mut(inv_ent).unwrap().set_item(inv_ent, &mut commands, &ItemName::from("rifle"), 1, 0, Some(gun));
+        });
+        assert_eq!(slot_gun(&app, inv_ent, 0), Some(Gun { ammo: 3, ammo_in_reserve: 7 }));
+    }
+
+    #[test]
+    fn first_pickup_into_an_empty_inventory_gets_equipped() {
+        let mut app = item_app();
+        app.add_systems(Update, modify_equip_state_sys);
+        let inv_ent = app.world.spawn((Inventory::with_slots(2), PlayerInput::default())).id();
+        app.update();
+        assert!(pick_up(&mut app, inv_ent, "rifle", 1));
+        assert_eq!(app.world.get::<Inventory>(inv_ent).unwrap().equip_state_name, EQUIPPING_STATE);
+
+        // The rifle takes half a second to equip
+        app.world.resource_mut::<Time>().advance_by(Duration::from_millis(600));
+        app.update();
+        let inv = app.world.get::<Inventory>(inv_ent).unwrap();
+        assert_eq!(inv.equipped_slot, Some(0));
+        assert!(inv.is_equipped());
+        assert_eq!(event_count::<EquippedEvent>(&app), 1);
+    }
+
+    #[test]
+    fn wanted_slot_is_equipped_when_nothing_is() {
+        let mut app = item_app();
+        app.add_systems(Update, modify_equip_state_sys);
+        let inv_ent = app.world.spawn(PlayerInput { wanted_item_slot: Some(1), ..default() }).id();
+        let item_ent = app.world.spawn(Item { inv_slot: 1, ..rifle(inv_ent, IDLE_STATE) }).id();
+        let mut inv = Inventory::with_slots(2);
+        inv.item_ents.0[1] = Some(item_ent);
+        app.world.entity_mut(inv_ent).insert(inv);
+
+        app.update();
+        assert_eq!(app.world.get::<Inventory>(inv_ent).unwrap().equipped_slot, Some(1));
+        app.world.resource_mut::<Time>().advance_by(Duration::from_millis(600));
+        app.update();
+        assert!(app.world.get::<Inventory>(inv_ent).unwrap().is_equipped());
+    }
+
+    #[test]
+    fn can_fire_only_once_equipped_and_idle() {
+        let mut inv = Inventory::with_slots(1);
+        let idle = rifle(Entity::PLACEHOLDER, IDLE_STATE);
+        let reloading = rifle(Entity::PLACEHOLDER, RELOAD_STATE);
+        let firing = rifle(Entity::PLACEHOLDER, FIRE_STATE);
+        for (equip_state, can_fire) in [(EQUIPPING_STATE, false), (EQUIPPED_STATE, true), (UNEQUIPPING_STATE, false), (UNEQUIPPED_STATE, false)] {
+            inv.equip_state_name = EquipStateName::from(equip_state);
+            assert_eq!(idle.can_fire(&inv), can_fire, "idle while {}", equip_state);
+            assert!(!reloading.can_fire(&inv), "reloading while {}", equip_state);
+            assert!(!firing.can_fire(&inv), "firing while {}", equip_state);
+        }
+    }
+
+    #[test]
+    fn reload_tops_up_a_partial_mag() {
+        let mut gun = Gun { ammo: 12, ammo_in_reserve: 40 };
+        assert!(gun.can_reload(30));
+        assert_eq!(gun.reload(30), 18);
+        assert_eq!(gun, Gun { ammo: 30, ammo_in_reserve: 22 });
+        assert!(!gun.can_reload(30));
+    }
+
+    #[test]
+    fn reload_takes_what_is_left_in_reserve() {
+        let mut gun = Gun { ammo: 12, ammo_in_reserve: 5 };
+        assert_eq!(gun.reload(30), 5);
+        assert_eq!(gun, Gun { ammo: 17, ammo_in_reserve: 0 });
+        assert!(!gun.can_reload(30));
+    }
+
+    #[test]
+    fn rounds_move_once_the_reload_state_ends() {
+        let mut app = item_app();
+        app.add_systems(Update, modify_item_sys);
+        let (player_ent, item_ent) = spawn_armed_player(&mut app, Gun { ammo: 12, ammo_in_reserve: 40 }, PlayerInputFlags::Reload);
+        app.update();
+        assert!(app.world.get::<Item>(item_ent).unwrap().is_reloading());
+        assert_eq!(app.world.get::<Gun>(item_ent).unwrap().ammo, 12);
+
+        // The rifle takes two seconds to reload
+        set_flags(&mut app, player_ent, FlagSet::default());
+        app.world.resource_mut::<Time>().advance_by(Duration::from_millis(2100));
+        app.update();
+        app.update();
+        assert_eq!(app.world.get::<Item>(item_ent).unwrap().state_name, IDLE_STATE);
+        assert_eq!(*app.world.get::<Gun>(item_ent).unwrap(), Gun { ammo: 30, ammo_in_reserve: 22 });
+    }
+
+    #[test]
+    fn firing_spends_a_round() {
+        let mut app = item_app();
+        app.add_systems(Update, modify_item_sys);
+        let (_, item_ent) = spawn_armed_player(&mut app, Gun { ammo: 2, ammo_in_reserve: 0 }, PlayerInputFlags::Fire);
+        app.update();
+
+        assert_eq!(app.world.get::<Gun>(item_ent).unwrap().ammo, 1);
+        assert_eq!(app.world.get::<Item>(item_ent).unwrap().state_name, FIRE_STATE);
+        assert_eq!(event_count::<FireEvent>(&app), 1);
+        assert_eq!(event_count::<DryFireEvent>(&app), 0);
+    }
+
+    #[test]
+    fn empty_gun_dry_fires() {
+        let mut app = item_app();
+        app.add_systems(Update, modify_item_sys);
+        let (_, item_ent) = spawn_armed_player(&mut app, Gun { ammo: 0, ammo_in_reserve: 30 }, PlayerInputFlags::Fire);
+        app.update();
+
+        assert_eq!(*app.world.get::<Gun>(item_ent).unwrap(), Gun { ammo: 0, ammo_in_reserve: 30 });
+        assert_eq!(event_count::<FireEvent>(&app), 0);
+        assert_eq!(event_count::<DryFireEvent>(&app), 1);
+    }
+}
@@ -1,4 +1,4 @@
-pub(crate) const EDGE_TABLE: &'static [u32; 256] = &[
+pub const EDGE_TABLE: &'static [u32; 256] = &[
     0x000, 0x109, 0x203, 0x30a, 0x406, 0x50f, 0x605, 0x70c,
     0x80c, 0x905, 0xa0f, 0xb06, 0xc0a, 0xd03, 0xe09, 0xf00,
     0x190, 0x099, 0x393, 0x29a, 0x596, 0x49f, 0x795, 0x69c,
@@ -33,7 +33,7 @@ pub(crate) const EDGE_TABLE: &'static [u32; 256] = &[
     0x70c, 0x605, 0x50f, 0x406, 0x30a, 0x203, 0x109, 0x000
 ];
 
-pub(crate) const TRI_TABLE: &'static [[i32; 16]; 256] = &[
+pub const TRI_TABLE: &'static [[i32; 16]; 256] = &[
     [-1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
     [0, 8, 3, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
     [0, 1, 9, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1],
@@ -0,0 +1,368 @@
+use bevy::{
+    app::AppExit,
+    prelude::*,
+};
+use bevy_rapier3d::prelude::*;
+
+use crate::{CHUNK_SZ, Config, ConfigState, MAX_FOV_DEGREES, MIN_FOV_DEGREES, USER_CONFIG_PATH, write_user_config};
+
+const BUTTON_COLOR: Color = Color::rgba(0.15, 0.15, 0.15, 0.9);
+const BUTTON_HOVERED_COLOR: Color = Color::rgba(0.3, 0.3, 0.3, 0.9);
+
+#[derive(States, Copy, Clone, Debug, Default, Hash, PartialEq, Eq)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    Playing,
+    /// Gameplay and physics are frozen where they were, the world is still drawn behind the menu
+    Paused,
+    /// Opened from the pause menu, still paused underneath
+    Settings,
+}
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_state::<GameState>()
+            .add_systems(OnEnter(GameState::MainMenu), (spawn_main_menu_sys, pause_physics_sys))
+            .add_systems(OnExit(GameState::MainMenu), despawn_menu_sys)
+            .add_systems(OnEnter(GameState::Paused), spawn_pause_menu_sys)
+            .add_systems(OnExit(GameState::Paused), despawn_menu_sys)
+            .add_systems(OnEnter(GameState::Settings), spawn_settings_menu_sys)
+            .add_systems(OnExit(GameState::Settings), despawn_menu_sys)
+            .add_systems(OnEnter(GameState::Playing), resume_physics_sys)
+            .add_systems(OnExit(GameState::Playing), pause_physics_sys)
+            .add_systems(Update, (
+                toggle_pause_sys,
+                button_color_sys,
+                menu_button_sys,
+                (setting_step_sys, setting_value_sys).chain().run_if(in_state(GameState::Settings)),
+            ));
+    }
+}
+
+/// Root of whichever menu is open, despawning it takes the whole menu with it
+#[derive(Component)]
+pub struct Menu;
+
+#[derive(Component, Copy, Clone, Debug)]
+pub enum MenuButton {
+    Play,
+    Resume,
+    Settings,
+    /// Saves the settings to the user config, they already took effect as they were changed
+    Apply,
+    Back,
+    Quit,
+}
+
+impl MenuButton {
+    fn label(self) -> &'static str {
+        match self {
+            MenuButton::Play => "Play",
+            MenuButton::Resume => "Resume",
+            MenuButton::Settings => "Settings",
+            MenuButton::Apply => "Apply",
+            MenuButton::Back => "Back",
+            MenuButton::Quit => "Quit",
+        }
+    }
+}
+
+/// Config fields players can change from the settings menu, edited in place on the live config
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Setting {
+    Sensitivity,
+    Fov,
+    ViewDistance,
+    Volume,
+}
+
+impl Setting {
+    const ALL: [Setting; 4] = [Setting::Sensitivity, Setting::Fov, Setting::ViewDistance, Setting::Volume];
+
+    fn label(self) -> &'static str {
+        match self {
+            Setting::Sensitivity => "Sensitivity",
+            Setting::Fov => "Field of View",
+            Setting::ViewDistance => "View Distance",
+            Setting::Volume => "Volume",
+        }
+    }
+
+    /// Name in the config file
+    fn field(self) -> &'static str {
+        match self {
+            Setting::Sensitivity => "sensitivity",
+            Setting::Fov => "fov_degrees",
+            Setting::ViewDistance => "chunk_render_distance",
+            Setting::Volume => "master_volume",
+        }
+    }
+
+    fn step(self) -> f32 {
+        match self {
+            Setting::Sensitivity => 0.05,
+            Setting::Fov => 5.0,
+            Setting::ViewDistance => CHUNK_SZ as f32,
+            Setting::Volume => 0.05,
+        }
+    }
+
+    fn range(self) -> (f32, f32) {
+        match self {
+            Setting::Sensitivity => (0.05, 5.0),
+            Setting::Fov => (MIN_FOV_DEGREES, MAX_FOV_DEGREES),
+            // Zero would mean no limit at all, which is not something a step away from the smallest should do
+            Setting::ViewDistance => (CHUNK_SZ as f32, 2048.0),
+            Setting::Volume => (0.0, 1.0),
+        }
+    }
+
+    fn get(self, config: &Config) -> f32 {
+        match self {
+            Setting::Sensitivity => config.sensitivity,
+            Setting::Fov => config.fov_degrees,
+            Setting::ViewDistance => config.chunk_render_distance,
+            Setting::Volume => config.master_volume,
+        }
+    }
+
+    fn set(self, config: &mut Config, value: f32) {
+        let field = match self {
+            Setting::Sensitivity => &mut config.sensitivity,
+            Setting::Fov => &mut config.fov_degrees,
+            Setting::ViewDistance => &mut config.chunk_render_distance,
+            Setting::Volume => &mut config.master_volume,
+        };
+        *field = value;
+    }
+
+    fn format(self, value: f32) -> String {
+        match self {
+            Setting::Sensitivity => format!("{:.2}", value),
+            Setting::Fov => format!("{:.0}", value),
+            Setting::ViewDistance => format!("{:.0} m", value),
+            Setting::Volume => format!("{:.0}%", value * 100.0),
+        }
+    }
+
+    /// Snaps to the step grid first so a hand edited value lines up again after one press
+    fn stepped(self, value: f32, steps: i8) -> f32 {
+        let (min, max) = self.range();
+        (((value / self.step()).round() + steps as f32) * self.step()).clamp(min, max)
+    }
+}
+
+/// Moves a setting by this many steps when pressed
+#[derive(Component, Copy, Clone, Debug)]
+pub struct SettingStep {
+    setting: Setting,
+    steps: i8,
+}
+
+#[derive(Component, Copy, Clone, Debug)]
+pub struct SettingValue(Setting);
+
+fn spawn_main_menu_sys(mut commands: Commands) {
+    spawn_menu(&mut commands, "qgame", |_| {}, &[MenuButton::Play, MenuButton::Quit]);
+}
+
+fn spawn_pause_menu_sys(mut commands: Commands) {
+    spawn_menu(&mut commands, "Paused", |_| {}, &[MenuButton::Resume, MenuButton::Settings, MenuButton::Quit]);
+}
+
+fn spawn_settings_menu_sys(mut commands: Commands) {
+    spawn_menu(&mut commands, "Settings", |parent| {
+        for setting in Setting::ALL {
+            parent.spawn(NodeBundle {
+                style: Style {
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(8.0),
+                    ..default()
+                },
+                ..default()
+            }).with_children(|parent| {
+                let label_style = Style { width: Val::Px(200.0), ..default() };
+                parent.spawn(TextBundle::from_section(setting.label(), TextStyle { font_size: 24.0, color: Color::WHITE, ..default() })
+                    .with_style(label_style));
+                spawn_step_button(parent, SettingStep { setting, steps: -1 }, "-");
+                // Filled in once the config is there to read
+                let value_style = Style { width: Val::Px(100.0), justify_content: JustifyContent::Center, ..default() };
+                parent.spawn(NodeBundle { style: value_style, ..default() }).with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section("", TextStyle { font_size: 24.0, color: Color::WHITE, ..default() }),
+                        SettingValue(setting),
+                    ));
+                });
+                spawn_step_button(parent, SettingStep { setting, steps: 1 }, "+");
+            });
+        }
+    }, &[MenuButton::Apply, MenuButton::Back]);
+}
+
+fn spawn_step_button(parent: &mut ChildBuilder, step: SettingStep, label: &str) {
+    parent.spawn((
+        ButtonBundle {
+            style: Style {
+                width: Val::Px(36.0),
+                height: Val::Px(36.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            background_color: BUTTON_COLOR.into(),
+            ..default()
+        },
+        step,
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(label, TextStyle { font_size: 24.0, color: Color::WHITE, ..default() }));
+    });
+}
+
+/// Content goes between the title and the buttons
+fn spawn_menu(commands: &mut Commands, title: &str, content: impl FnOnce(&mut ChildBuilder), buttons: &[MenuButton]) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                row_gap: Val::Px(12.0),
+                ..default()
+            },
+            background_color: Color::rgba(0.0, 0.0, 0.0, 0.5).into(),
+            // Over the HUD
+            z_index: ZIndex::Global(1),
+            ..default()
+        },
+        Menu,
+    )).with_children(|parent| {
+        parent.spawn(TextBundle::from_section(title, TextStyle { font_size: 48.0, color: Color::WHITE, ..default() }));
+        content(parent);
+        for &button in buttons {
+            parent.spawn((
+                ButtonBundle {
+                    style: Style {
+                        width: Val::Px(200.0),
+                        height: Val::Px(48.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    background_color: BUTTON_COLOR.into(),
+                    ..default()
+                },
+                button,
+            )).with_children(|parent| {
+                parent.spawn(TextBundle::from_section(button.label(), TextStyle { font_size: 24.0, color: Color::WHITE, ..default() }));
+            });
+        }
+    });
+}
+
+fn despawn_menu_sys(mut commands: Commands, menu_query: Query<Entity, With<Menu>>) {
+    for menu_ent in menu_query.iter() {
+        commands.entity(menu_ent).despawn_recursive();
+    }
+}
+
+fn toggle_pause_sys(
+    key: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !key.just_pressed(KeyCode::Escape) { return; }
+    match state.get() {
+        GameState::Playing => next_state.set(GameState::Paused),
+        GameState::Paused => next_state.set(GameState::Playing),
+        GameState::Settings => next_state.set(GameState::Paused),
+        GameState::MainMenu => {}
+    }
+}
+
+type ButtonChangedFilter = (Changed<Interaction>, With<Button>);
+
+/// Every button in any menu highlights the same way
+fn button_color_sys(mut button_query: Query<(&Interaction, &mut BackgroundColor), ButtonChangedFilter>) {
+    for (interaction, mut color) in button_query.iter_mut() {
+        *color = match interaction {
+            Interaction::Hovered | Interaction::Pressed => BUTTON_HOVERED_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }.into();
+    }
+}
+
+fn menu_button_sys(
+    button_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    config: Res<Assets<Config>>,
+    config_state: Option<Res<ConfigState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut exit_events: EventWriter<AppExit>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed { continue; }
+        match button {
+            MenuButton::Play | MenuButton::Resume => next_state.set(GameState::Playing),
+            MenuButton::Settings => next_state.set(GameState::Settings),
+            MenuButton::Apply => {
+                let Some(config) = config_state.as_ref().and_then(|config_state| config.get(&config_state.handle)) else { continue; };
+                match write_user_config(settings_table(config)) {
+                    Ok(()) => info!("Saved settings to {}", USER_CONFIG_PATH),
+                    Err(err) => error!("Could not save settings to {}: {}", USER_CONFIG_PATH, err),
+                }
+            }
+            MenuButton::Back => next_state.set(GameState::Paused),
+            MenuButton::Quit => exit_events.send(AppExit),
+        }
+    }
+}
+
+/// Rounded so the file reads as written instead of with the float noise of widening to f64
+fn settings_table(config: &Config) -> toml::Table {
+    Setting::ALL.iter()
+        .map(|setting| {
+            let value = (setting.get(config) as f64 * 1000.0).round() / 1000.0;
+            (setting.field().to_string(), toml::Value::Float(value))
+        })
+        .collect()
+}
+
+/// Takes effect right away, getting the config mutably sends the same modified event a file edit would
+fn setting_step_sys(
+    button_query: Query<(&Interaction, &SettingStep), Changed<Interaction>>,
+    mut config: ResMut<Assets<Config>>,
+    config_state: Option<Res<ConfigState>>,
+) {
+    let Some(config_state) = config_state else { return; };
+    for (interaction, step) in button_query.iter() {
+        if *interaction != Interaction::Pressed { continue; }
+        let Some(config) = config.get_mut(&config_state.handle) else { return; };
+        let value = step.setting.stepped(step.setting.get(config), step.steps);
+        step.setting.set(config, value);
+    }
+}
+
+fn setting_value_sys(
+    config: Res<Assets<Config>>,
+    config_state: Option<Res<ConfigState>>,
+    mut text_query: Query<(&SettingValue, &mut Text)>,
+) {
+    let Some(config) = config_state.and_then(|config_state| config.get(&config_state.handle)) else { return; };
+    for (&SettingValue(setting), mut text) in text_query.iter_mut() {
+        text.sections[0].value = setting.format(setting.get(config));
+    }
+}
+
+/// Gameplay systems are gated on the state, but rapier steps on its own so it is stopped here
+fn pause_physics_sys(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+}
+
+fn resume_physics_sys(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+}
@@ -0,0 +1,145 @@
+use std::f32::consts::SQRT_2;
+
+use bevy::{
+    prelude::*,
+    utils::HashSet,
+};
+
+use crate::{CHUNK_SZ, Config, config_loaded, ConfigState, LocalPlayer, look_quat, Map, PlayerInput};
+
+const MINIMAP_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.5);
+const MINIMAP_CHUNK_COLOR: Color = Color::rgba(0.4, 0.6, 0.4, 0.6);
+const MINIMAP_PLAYER_COLOR: Color = Color::WHITE;
+/// Pixels between chunk cells so the grid reads as a grid
+const MINIMAP_CELL_GAP: f32 = 1.0;
+const MINIMAP_DOT_SIZE: f32 = 6.0;
+const MINIMAP_HEADING_LENGTH: f32 = 12.0;
+const MINIMAP_HEADING_THICKNESS: f32 = 2.0;
+
+/// Top down view of the loaded chunks around the local player, north is always up
+#[derive(Component)]
+pub struct Minimap;
+
+/// Column of loaded chunks at this x and z, drawn as one cell whatever their height
+#[derive(Component)]
+pub struct MinimapCell(IVec2);
+
+#[derive(Component)]
+pub struct MinimapPlayerDot;
+
+#[derive(Component)]
+pub struct MinimapHeading;
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Startup, spawn_minimap_sys)
+            .add_systems(Update, update_minimap_sys.run_if(config_loaded));
+    }
+}
+
+fn spawn_minimap_sys(mut commands: Commands) {
+    let style = Style { position_type: PositionType::Absolute, ..default() };
+    commands.spawn((
+        NodeBundle {
+            style: Style { position_type: PositionType::Absolute, overflow: Overflow::clip(), ..default() },
+            background_color: MINIMAP_BACKGROUND.into(),
+            ..default()
+        },
+        Minimap,
+    )).with_children(|parent| {
+        // Spawned after the cells would be and drawn on top of them
+        parent.spawn((
+            NodeBundle { style: style.clone(), background_color: MINIMAP_PLAYER_COLOR.into(), z_index: ZIndex::Local(1), ..default() },
+            MinimapHeading,
+        ));
+        parent.spawn((
+            NodeBundle { style, background_color: MINIMAP_PLAYER_COLOR.into(), z_index: ZIndex::Local(1), ..default() },
+            MinimapPlayerDot,
+        ));
+    });
+}
+
+/// Positions are in pixels from the top left of the map, with y going down
+fn place_minimap_node(style: &mut Style, center: Vec2, size: Vec2) {
+    style.left = Val::Px(center.x - size.x * 0.5);
+    style.top = Val::Px(center.y - size.y * 0.5);
+    style.width = Val::Px(size.x);
+    style.height = Val::Px(size.y);
+}
+
+/// The map itself, its chunk cells, the player dot and the heading line
+type MinimapNodeQueries<'w, 's> = ParamSet<'w, 's, (
+    Query<'static, 'static, (Entity, &'static mut Style), With<Minimap>>,
+    Query<'static, 'static, (Entity, &'static MinimapCell, &'static mut Style)>,
+    Query<'static, 'static, &'static mut Style, With<MinimapPlayerDot>>,
+    Query<'static, 'static, (&'static mut Style, &'static mut Transform), With<MinimapHeading>>,
+)>;
+
+/// Cells are spawned and despawned as chunks come in and out of range, the rest is moved every frame
+pub fn update_minimap_sys(
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    map_query: Query<&Map>,
+    player_query: Query<(&Transform, &PlayerInput), With<LocalPlayer>>,
+    mut node_query: MinimapNodeQueries,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let minimap = &config.minimap;
+    let player = player_query.get_single().ok();
+    let mut minimap_query = node_query.p0();
+    let Ok((minimap_ent, mut minimap_style)) = minimap_query.get_single_mut() else { return; };
+    minimap_style.display = if minimap.enabled && player.is_some() { Display::Flex } else { Display::None };
+    minimap_style.top = Val::Px(minimap.position.y);
+    minimap_style.right = Val::Px(minimap.position.x);
+    minimap_style.width = Val::Px(minimap.size);
+    minimap_style.height = Val::Px(minimap.size);
+    let Some((player_transform, input)) = player.filter(|_| minimap.enabled) else { return; };
+
+    let center = Vec2::splat(minimap.size * 0.5);
+    let px_per_meter = center.x / minimap.range;
+    let player_xz = Vec2::new(player_transform.translation.x, player_transform.translation.z);
+    let to_map = |world_xz: Vec2| center + (world_xz - player_xz) * px_per_meter;
+
+    // Any chunk in a column is enough to show it, height does not matter from above
+    let chunk_sz = CHUNK_SZ as f32;
+    let columns: HashSet<IVec2> = map_query.iter()
+        .flat_map(|map| map.chunks.keys())
+        .map(|position| IVec2::new(position.x, position.z))
+        .filter(|column| {
+            let nearest = player_xz.clamp(column.as_vec2() * chunk_sz, (column.as_vec2() + 1.0) * chunk_sz);
+            nearest.distance(player_xz) <= minimap.range * SQRT_2
+        })
+        .collect();
+    let cell_size = Vec2::splat((chunk_sz * px_per_meter - MINIMAP_CELL_GAP).max(1.0));
+    let mut shown = HashSet::default();
+    for (cell_ent, cell, mut style) in node_query.p1().iter_mut() {
+        if !columns.contains(&cell.0) {
+            commands.entity(cell_ent).despawn_recursive();
+            continue;
+        }
+        place_minimap_node(&mut style, to_map((cell.0.as_vec2() + 0.5) * chunk_sz), cell_size);
+        shown.insert(cell.0);
+    }
+    commands.entity(minimap_ent).with_children(|parent| {
+        for &column in columns.difference(&shown) {
+            let mut style = Style { position_type: PositionType::Absolute, ..default() };
+            place_minimap_node(&mut style, to_map((column.as_vec2() + 0.5) * chunk_sz), cell_size);
+            parent.spawn((NodeBundle { style, background_color: MINIMAP_CHUNK_COLOR.into(), ..default() }, MinimapCell(column)));
+        }
+    });
+
+    for mut style in node_query.p2().iter_mut() {
+        place_minimap_node(&mut style, center, Vec2::splat(MINIMAP_DOT_SIZE));
+    }
+    // Screen y goes down like world z does, so forward along negative z points up
+    let forward = look_quat(0.0, input.yaw) * -Vec3::Z;
+    let heading = Vec2::new(forward.x, forward.z).normalize_or_zero();
+    for (mut style, mut transform) in node_query.p3().iter_mut() {
+        place_minimap_node(&mut style, center + heading * MINIMAP_HEADING_LENGTH * 0.5, Vec2::new(MINIMAP_HEADING_LENGTH, MINIMAP_HEADING_THICKNESS));
+        transform.rotation = Quat::from_rotation_z(heading.y.atan2(heading.x));
+    }
+}
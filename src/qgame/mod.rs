@@ -1,6 +1,10 @@
 use std::{
+    ffi::OsStr,
     mem::size_of,
-    slice::Iter,
+    ops::{Index, IndexMut, Range},
+    slice::{Iter, IterMut},
+    path::Path,
+    sync::{Arc, OnceLock},
 };
 
 use bevy::{
@@ -10,28 +14,129 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
     },
 };
+use serde::de::DeserializeOwned;
 use thiserror::Error;
 
+pub use audio::*;
+pub use bot::*;
+pub use console::*;
 pub use controller::*;
+pub use headless::*;
+pub use health::*;
+pub use impact::*;
 pub use input::*;
 pub use inventory::*;
-pub(crate) use lookup::*;
+pub use menu::*;
+pub use minimap::*;
+pub use nameplate::*;
+pub use net::*;
+pub use lookup::*;
+pub use profiler::*;
+pub use rng::*;
+pub use screenshot::*;
+pub use spectate::*;
 pub use voxel::*;
+pub use weapon::*;
 
+mod audio;
+mod bot;
+mod console;
 mod controller;
+mod headless;
+mod health;
+mod impact;
 mod input;
 mod inventory;
 mod lookup;
+mod menu;
+mod minimap;
+mod nameplate;
+mod net;
+mod profiler;
+mod rng;
+mod screenshot;
+mod spectate;
 mod voxel;
+mod weapon;
 
 #[derive(Debug, Error)]
 pub enum RonLoaderError {
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
-    RonSpannedError(#[from] ron::error::SpannedError),
+    Parse(#[from] ConfigParseError),
     #[error(transparent)]
     LoadDirectError(#[from] bevy::asset::LoadDirectError),
+    #[error(transparent)]
+    InvalidField(#[from] InvalidField),
+}
+
+/// File did not parse in the format its extension picked
+#[derive(Debug, Error)]
+pub enum ConfigParseError {
+    #[error(transparent)]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error(transparent)]
+    JsonError(#[from] serde_json::Error),
+    #[error(transparent)]
+    TomlError(#[from] toml::de::Error),
+    #[error(transparent)]
+    Utf8Error(#[from] std::str::Utf8Error),
+}
+
+/// Parsed fine but breaks an invariant, the field is a path from the top of the file like `weapon_props.damage`
+#[derive(Debug, Error)]
+#[error("{field} {problem}")]
+pub struct InvalidField {
+    pub field: std::string::String,
+    pub problem: &'static str,
+}
+
+/// Checks invariants serde can not express, loaders run this right after parsing so bad files fail to load
+pub trait Validate {
+    fn validate(&self) -> Result<(), InvalidField>;
+}
+
+impl InvalidField {
+    /// Prefixes the field with the one it is nested in
+    pub fn within(self, parent: &str) -> Self {
+        Self { field: format!("{}.{}", parent, self.field), ..self }
+    }
+}
+
+pub fn ensure_field(ok: bool, field: &str, problem: &'static str) -> Result<(), InvalidField> {
+    if ok {
+        Ok(())
+    } else {
+        Err(InvalidField { field: field.into(), problem })
+    }
+}
+
+/// The last extension of the path picks the format, anything but JSON or TOML is read as RON
+pub fn parse_by_extension<T: DeserializeOwned>(path: &Path, bytes: &[u8]) -> Result<T, ConfigParseError> {
+    Ok(match path.extension().and_then(OsStr::to_str) {
+        Some("json") => serde_json::from_slice(bytes)?,
+        Some("toml") => toml::from_str(std::str::from_utf8(bytes)?)?,
+        _ => ron::de::from_bytes(bytes)?,
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum BufVecError {
+    #[error("Source has changes the GPU does not have yet, write it before copying from it")]
+    SourceNotUploaded,
+    #[error("{requested} elements do not fit in a buffer, the device allows at most {max_bytes} bytes")]
+    TooLarge { requested: usize, max_bytes: u64 },
+    #[error("A read is still pending, it has to be taken with try_read before requesting another")]
+    ReadPending,
+    #[error("Could not map the staging buffer for reading: {0}")]
+    Map(#[from] wgpu::BufferAsyncError),
+}
+
+/// Read requested but not copied out yet, the map callback fills in the result once the GPU is done
+struct PendingRead {
+    len: usize,
+    mapped: Arc<OnceLock<Result<(), wgpu::BufferAsyncError>>>,
 }
 
 pub struct BufVec<T: Pod> {
@@ -40,48 +145,154 @@ pub struct BufVec<T: Pod> {
     values: Vec<T>,
     staging_buffer: Buffer,
     buffer: Buffer,
+    pending_read: Option<PendingRead>,
+    /// Indices of values changed since the last upload, the smallest range covering all of them
+    dirty: Option<Range<usize>>,
+    /// Names the buffers in GPU captures and validation errors
+    label: Option<&'static str>,
 }
 
-pub fn create_staging_buffer(read_only: bool, size: usize, device: &RenderDevice) -> Buffer {
+/// Read only values are mapped to come back, everything else goes up through the staging buffer.
+/// Mapped buffers are only ever the other end of a copy, so every device supports these without MAPPABLE_PRIMARY_BUFFERS
+fn staging_usage(read_only: bool) -> BufferUsages {
+    BufferUsages::COPY_DST | if read_only {
+        BufferUsages::MAP_READ
+    } else {
+        BufferUsages::COPY_SRC
+    }
+}
+
+/// Shaders bind it, and writes, reads and copies all go through copies
+const BUFFER_USAGE: BufferUsages = BufferUsages::STORAGE.union(BufferUsages::COPY_SRC).union(BufferUsages::COPY_DST);
+
+pub fn create_staging_buffer(label: Option<&str>, read_only: bool, size: usize, device: &RenderDevice) -> Buffer {
     device.create_buffer(&BufferDescriptor {
-        label: None,
-        size: size as BufferAddress,
-        usage: BufferUsages::COPY_DST | if read_only {
-            BufferUsages::MAP_READ
-        } else {
-            BufferUsages::COPY_SRC
-        },
+        label: label.map(|label| format!("{} staging buffer", label)).as_deref(),
+        size: copy_aligned(size) as BufferAddress,
+        usage: staging_usage(read_only),
         mapped_at_creation: false,
     })
 }
 
-pub fn create_buffer(_read_only: bool, size: usize, device: &RenderDevice) -> Buffer {
+pub fn create_buffer(label: Option<&str>, _read_only: bool, size: usize, device: &RenderDevice) -> Buffer {
     // let mut usage = BufferUsages::STORAGE | if read_only {
     //     BufferUsages::COPY_SRC
     // } else {
     //     BufferUsages::COPY_DST
     // };
-    let usage = BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST;
     device.create_buffer(&BufferDescriptor {
-        label: None,
-        size: size as BufferAddress,
-        usage,
+        label: label.map(|label| format!("{} buffer", label)).as_deref(),
+        size: copy_aligned(size) as BufferAddress,
+        usage: BUFFER_USAGE,
         mapped_at_creation: false,
     })
 }
 
+/// Copies and writes have to start and end on the copy alignment, buffers are padded to it like `create_buffer_with_data` does
+fn copy_aligned(size: usize) -> usize {
+    size.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT as usize)
+}
+
+/// Bytes to upload for the dirty values, widened out to the copy alignment.
+/// Buffers are padded to it as well, so clamping to their size keeps the end aligned
+fn aligned_byte_range(dirty: Range<usize>, value_size: usize, buffer_bytes: usize) -> Range<usize> {
+    let align = wgpu::COPY_BUFFER_ALIGNMENT as usize;
+    let start = dirty.start * value_size / align * align;
+    start..copy_aligned(dirty.end * value_size).min(buffer_bytes)
+}
+
+/// Smallest range covering both the values already changed and the newly changed ones
+fn merge_dirty(dirty: Option<Range<usize>>, range: Range<usize>) -> Range<usize> {
+    match dirty {
+        Some(dirty) => dirty.start.min(range.start)..dirty.end.max(range.end),
+        None => range,
+    }
+}
+
+/// What is left to upload once values from `len` on are gone
+fn truncate_dirty(dirty: Option<Range<usize>>, len: usize) -> Option<Range<usize>> {
+    dirty.map(|dirty| dirty.start..dirty.end.min(len)).filter(|dirty| !dirty.is_empty())
+}
+
+/// Capacity to grow to for holding `requested` values, doubling like a Vec so growing one value at a time stays cheap
+fn grown_capacity(capacity: usize, requested: usize, max_capacity: usize) -> usize {
+    if requested <= capacity {
+        return capacity;
+    }
+    // Past the device limit only what was asked for, so it errors on the request instead of the doubling
+    requested.max(capacity.saturating_mul(2).min(max_capacity))
+}
+
+/// Only shrunk once more than this many times bigger than needed
+const SHRINK_FACTOR: usize = 2;
+
+/// Whether a buffer holding `capacity` values wastes enough on `len` of them to be worth recreating
+fn is_wasteful(capacity: usize, len: usize) -> bool {
+    // Even empty it keeps room for one, so that is all it can shrink to
+    capacity > len.max(1) * SHRINK_FACTOR
+}
+
+/// Every buffer is bound as storage, so the binding limit caps it as well
+fn max_buffer_bytes(device: &RenderDevice) -> u64 {
+    let limits = device.limits();
+    limits.max_buffer_size.min(limits.max_storage_buffer_binding_size as u64)
+}
+
 impl<T: Pod> BufVec<T> {
     pub fn with_capacity(read_only: bool, capacity: usize, device: &RenderDevice) -> Self {
+        Self::with_capacity_labeled(None, read_only, capacity, device)
+    }
+
+    pub fn with_capacity_labeled(label: Option<&'static str>, read_only: bool, capacity: usize, device: &RenderDevice) -> Self {
         let size = capacity * size_of::<T>();
-        let mut buffer = BufVec {
+        BufVec {
             read_only,
             buffer_capacity: capacity,
             values: Vec::with_capacity(capacity),
-            staging_buffer: create_staging_buffer(read_only, size, device),
-            buffer: create_buffer(read_only, size, device),
-        };
-        buffer.ensure_buf_cap(device);
-        buffer
+            staging_buffer: create_staging_buffer(label, read_only, size, device),
+            buffer: create_buffer(label, read_only, size, device),
+            pending_read: None,
+            dirty: None,
+            label,
+        }
+    }
+
+    /// Already holding the values, uploaded so the buffer can be bound without writing it first.
+    /// Allocates right away like `with_capacity`, a BufVec always owns its GPU buffers
+    pub fn from_vec(read_only: bool, values: Vec<T>, device: &RenderDevice) -> Self {
+        Self::from_vec_labeled(None, read_only, values, device)
+    }
+
+    pub fn from_vec_labeled(label: Option<&'static str>, read_only: bool, values: Vec<T>, device: &RenderDevice) -> Self {
+        let bytes: &[u8] = cast_slice(&values);
+        let buffer = device.create_buffer_with_data(&BufferInitDescriptor {
+            label: label.map(|label| format!("{} buffer", label)).as_deref(),
+            contents: bytes,
+            usage: BUFFER_USAGE,
+        });
+        BufVec {
+            read_only,
+            buffer_capacity: values.len(),
+            staging_buffer: create_staging_buffer(label, read_only, bytes.len(), device),
+            buffer,
+            values,
+            pending_read: None,
+            dirty: None,
+            label,
+        }
+    }
+
+    pub fn from_slice(read_only: bool, values: &[T], device: &RenderDevice) -> Self {
+        Self::from_vec(read_only, values.to_vec(), device)
+    }
+
+    pub fn from_slice_labeled(label: Option<&'static str>, read_only: bool, values: &[T], device: &RenderDevice) -> Self {
+        Self::from_vec_labeled(label, read_only, values.to_vec(), device)
+    }
+
+    #[inline]
+    pub fn label(&self) -> Option<&'static str> {
+        self.label
     }
 
     #[inline]
@@ -107,28 +318,154 @@ impl<T: Pod> BufVec<T> {
     pub fn push(&mut self, value: T) -> usize {
         let index = self.values.len();
         self.values.push(value);
+        self.mark_dirty(index..index + 1);
         index
     }
 
-    fn ensure_buf_cap(&mut self, device: &RenderDevice) {
-        if self.values.len() > self.buffer_capacity {
-            let size = self.values.len() * size_of::<T>();
-            self.staging_buffer = create_staging_buffer(self.read_only, size, device);
-            self.buffer = create_buffer(self.read_only, size, device);
-            self.buffer_capacity = size;
+    /// Indices the new values ended up at
+    pub fn extend_from_slice(&mut self, values: &[T]) -> Range<usize> {
+        let start = self.values.len();
+        self.values.extend_from_slice(values);
+        self.mark_appended(start)
+    }
+
+    pub fn extend<I: IntoIterator<Item=T>>(&mut self, values: I) -> Range<usize> {
+        let start = self.values.len();
+        self.values.extend(values);
+        self.mark_appended(start)
+    }
+
+    fn mark_appended(&mut self, start: usize) -> Range<usize> {
+        let appended = start..self.values.len();
+        if !appended.is_empty() {
+            self.mark_dirty(appended.clone());
         }
+        appended
     }
 
-    pub fn encode_write(&mut self, queue: &RenderQueue, command_encoder: &mut CommandEncoder) {
-        if self.values.is_empty() {
-            return;
+    /// Drops values past `len`, the GPU buffers stay the same size
+    pub fn truncate(&mut self, len: usize) {
+        self.values.truncate(len);
+        self.dirty = truncate_dirty(self.dirty.take(), len);
+    }
+
+    /// What the next `encode_write` uploads, None when the GPU already has every value
+    pub fn dirty_range(&self) -> Option<Range<usize>> {
+        self.dirty.clone()
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(merge_dirty(self.dirty.take(), range));
+    }
+
+    /// Uploads everything next time, for when the GPU side was changed or replaced behind our back
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty = (!self.values.is_empty()).then_some(0..self.values.len());
+    }
+
+    /// Recreates the GPU buffers at exactly the number of values, to give back memory left over from a peak.
+    /// The live values are marked dirty, so the next `encode_write` puts them in the new buffers.
+    /// Bind groups made with the old buffers must be remade when this returns true
+    pub fn shrink_to_fit(&mut self, device: &RenderDevice) -> bool {
+        // Storage bindings can not be empty
+        let capacity = self.values.len().max(1);
+        if self.buffer_capacity <= capacity {
+            return false;
         }
+        self.values.shrink_to_fit();
+        self.reallocate(capacity, device);
+        true
+    }
+
+    /// Same as `shrink_to_fit`, but only once the buffers are wasting enough that it is worth the churn,
+    /// so it can be called every time values are dropped
+    pub fn shrink_if_wasteful(&mut self, device: &RenderDevice) -> bool {
+        is_wasteful(self.buffer_capacity, self.values.len()) && self.shrink_to_fit(device)
+    }
+
+    /// Grows the GPU buffers to hold at least `capacity` values, doubling like a Vec so growing one value at a time
+    /// stays cheap. Errors without allocating if the device can not fit it. Bind groups made with the old buffers must be remade after growing
+    pub fn try_reserve(&mut self, capacity: usize, device: &RenderDevice) -> Result<(), BufVecError> {
+        let max_capacity = (max_buffer_bytes(device) / size_of::<T>().max(1) as u64) as usize;
+        self.try_reserve_exact(grown_capacity(self.buffer_capacity, capacity, max_capacity), device)
+    }
+
+    /// Grows to exactly `capacity` values, for when the final size is known up front.
+    /// Errors without allocating if the device can not fit it
+    pub fn try_reserve_exact(&mut self, capacity: usize, device: &RenderDevice) -> Result<(), BufVecError> {
+        if capacity <= self.buffer_capacity {
+            return Ok(());
+        }
+        let max_bytes = max_buffer_bytes(device);
+        let fits = capacity.checked_mul(size_of::<T>()).is_some_and(|size| size as u64 <= max_bytes);
+        if !fits {
+            return Err(BufVecError::TooLarge { requested: capacity, max_bytes });
+        }
+        self.reallocate(capacity, device);
+        Ok(())
+    }
+
+    pub fn reserve(&mut self, capacity: usize, device: &RenderDevice) {
+        self.try_reserve(capacity, device).unwrap();
+    }
 
-        let size_bytes = size_of::<T>() * self.values.len();
-        let range = 0..size_bytes;
+    pub fn reserve_exact(&mut self, capacity: usize, device: &RenderDevice) {
+        self.try_reserve_exact(capacity, device).unwrap();
+    }
+
+    fn reallocate(&mut self, capacity: usize, device: &RenderDevice) {
+        let size = capacity * size_of::<T>();
+        // Whatever was being read was in the old staging buffer
+        self.pending_read = None;
+        self.staging_buffer = create_staging_buffer(self.label, self.read_only, size, device);
+        self.buffer = create_buffer(self.label, self.read_only, size, device);
+        self.buffer_capacity = capacity;
+        self.mark_all_dirty();
+    }
+
+    /// Only uploads the values changed since the last write
+    pub fn encode_write(&mut self, queue: &RenderQueue, command_encoder: &mut CommandEncoder) {
+        let Some(dirty) = self.dirty.take() else { return; };
+
+        let buffer_bytes = self.buffer.size().min(self.staging_buffer.size()) as usize;
+        let range = aligned_byte_range(dirty, size_of::<T>(), buffer_bytes);
+        let offset = range.start as BufferAddress;
+        let size_bytes = range.len() as BufferAddress;
         let bytes: &[u8] = cast_slice(&self.values);
-        queue.write_buffer(&self.staging_buffer, 0, &bytes[range]);
-        command_encoder.copy_buffer_to_buffer(&self.staging_buffer, 0, &self.buffer, 0, size_bytes as BufferAddress);
+        if range.end <= bytes.len() {
+            queue.write_buffer(&self.staging_buffer, offset, &bytes[range]);
+        } else {
+            // Widened past the last value, the padding is never read so zeros will do
+            let mut padded = bytes[range.start..].to_vec();
+            padded.resize(range.len(), 0);
+            queue.write_buffer(&self.staging_buffer, offset, &padded);
+        }
+        command_encoder.copy_buffer_to_buffer(&self.staging_buffer, offset, &self.buffer, offset, size_bytes);
+    }
+
+    /// Records zeroing the whole GPU buffer and empties the values to match, the capacity stays the same.
+    /// Unlike `clear`, shaders reading the buffer before the next write see zeros instead of stale values
+    pub fn encode_clear(&mut self, command_encoder: &mut CommandEncoder) {
+        self.values.clear();
+        self.dirty = None;
+        command_encoder.clear_buffer(&self.buffer, 0, None);
+    }
+
+    /// Records copying the source's values over ours on the GPU, growing our buffers first if they are too small.
+    /// The copy takes what is in the source's GPU buffer, so any changes to it have to be written first
+    pub fn copy_from(
+        &mut self, src: &BufVec<T>, device: &RenderDevice, command_encoder: &mut CommandEncoder,
+    ) -> Result<(), BufVecError> {
+        if src.dirty.is_some() {
+            return Err(BufVecError::SourceNotUploaded);
+        }
+        self.try_reserve(src.values.len(), device)?;
+        self.values.clone_from(&src.values);
+        let size = size_of::<T>() * src.values.len();
+        command_encoder.copy_buffer_to_buffer(&src.buffer, 0, &self.buffer, 0, size as BufferAddress);
+        // The GPU side matches the values once the copy runs
+        self.dirty = None;
+        Ok(())
     }
 
     pub fn encode_read(&mut self, len: usize, command_encoder: &mut CommandEncoder) {
@@ -136,12 +473,15 @@ impl<T: Pod> BufVec<T> {
         command_encoder.copy_buffer_to_buffer(&self.buffer, 0, &self.staging_buffer, 0, size as BufferAddress);
     }
 
+    /// Blocking half of a readback, the device has to be waited on between this and `read_and_unmap_buffer`.
+    /// Stalls until the GPU catches up, prefer `request_read` and `try_read` for anything read back every frame
     pub fn map_buffer(&mut self, len: usize) {
         self.values.resize(len, T::zeroed());
         let buffer_slice = self.staging_buffer.slice(..);
         buffer_slice.map_async(MapMode::Read, |_| {});
     }
 
+    /// Replaces the values with the first `len` read back, anything pushed since is overwritten
     pub fn read_and_unmap_buffer(&mut self, len: usize) {
         self.values.resize(len, T::zeroed());
 
@@ -149,6 +489,39 @@ impl<T: Pod> BufVec<T> {
         let range = 0..size_of::<T>() * len;
         self.values.copy_from_slice(cast_slice(&buffer_slice.get_mapped_range()[range]));
         self.staging_buffer.unmap();
+        self.dirty = None;
+    }
+
+    /// Starts mapping the staging buffer after `encode_read` was submitted and returns right away.
+    /// The staging buffer can only be mapped once at a time, so a read still pending is an error
+    pub fn request_read(&mut self, len: usize) -> Result<(), BufVecError> {
+        if self.pending_read.is_some() {
+            return Err(BufVecError::ReadPending);
+        }
+        let mapped = Arc::new(OnceLock::new());
+        let callback_mapped = mapped.clone();
+        self.staging_buffer.slice(..).map_async(MapMode::Read, move |result| {
+            let _ = callback_mapped.set(result);
+        });
+        self.pending_read = Some(PendingRead { len, mapped });
+        Ok(())
+    }
+
+    /// Copies out the values from `request_read` once mapped, None while the GPU is still busy or nothing was requested.
+    /// Map callbacks only run when the device is polled, which the renderer does every frame
+    pub fn try_read(&mut self) -> Option<Result<&[T], BufVecError>> {
+        let result = self.pending_read.as_ref()?.mapped.get()?.clone();
+        // Only given up once the map finished, until then the buffer is still owed its callback
+        let pending = self.pending_read.take()?;
+        if let Err(err) = result {
+            return Some(Err(err.into()));
+        }
+        self.read_and_unmap_buffer(pending.len);
+        Some(Ok(self.values.as_slice()))
+    }
+
+    pub fn is_read_pending(&self) -> bool {
+        self.pending_read.is_some()
     }
 
     pub fn as_slice(&self) -> &[T] {
@@ -159,7 +532,410 @@ impl<T: Pod> BufVec<T> {
         self.values.iter()
     }
 
+    /// Any value could be written, so all of them go up with the next `encode_write`
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        self.mark_all_dirty();
+        self.values.iter_mut()
+    }
+
     pub fn clear(&mut self) {
         self.values.clear();
+        self.dirty = None;
+    }
+}
+
+impl<T: Pod> Index<usize> for BufVec<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        &self.values[index]
+    }
+}
+
+/// Two of the same buffer taking turns, the GPU works on the current one while the previous one is read back,
+/// so reading never waits on the newest work. Each needs its own bind group
+pub struct DoubleBuffered<T: Pod> {
+    buffers: [BufVec<T>; 2],
+    current: usize,
+}
+
+impl<T: Pod> DoubleBuffered<T> {
+    pub fn new(first: BufVec<T>, second: BufVec<T>) -> Self {
+        Self { buffers: [first, second], current: 0 }
+    }
+
+    pub fn with_capacity_labeled(label: Option<&'static str>, read_only: bool, capacity: usize, device: &RenderDevice) -> Self {
+        Self::new(
+            BufVec::with_capacity_labeled(label, read_only, capacity, device),
+            BufVec::with_capacity_labeled(label, read_only, capacity, device),
+        )
+    }
+
+    /// The one to write and dispatch with next
+    pub fn current(&self) -> &BufVec<T> {
+        &self.buffers[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut BufVec<T> {
+        &mut self.buffers[self.current]
+    }
+
+    /// Written before the last swap, read it back with `request_read` and `try_read`
+    pub fn previous(&self) -> &BufVec<T> {
+        &self.buffers[1 - self.current]
+    }
+
+    pub fn previous_mut(&mut self) -> &mut BufVec<T> {
+        &mut self.buffers[1 - self.current]
+    }
+
+    /// After submitting work on the current buffer
+    pub fn swap(&mut self) {
+        self.current = 1 - self.current;
+    }
+}
+
+impl<'a, T: Pod> IntoIterator for &'a BufVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, T: Pod> IntoIterator for &'a mut BufVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+/// The GPU buffers are dropped with it, only the values are kept
+impl<T: Pod> IntoIterator for BufVec<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.into_iter()
+    }
+}
+
+/// Assumes the value is written, so it goes up with the next `encode_write`
+impl<T: Pod> IndexMut<usize> for BufVec<T> {
+    fn index_mut(&mut self, index: usize) -> &mut T {
+        // Out of bounds is left to panic like a Vec would
+        if index < self.values.len() {
+            self.mark_dirty(index..index + 1);
+        }
+        &mut self.values[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::once;
+
+    use bevy::{math::Vec4, tasks::block_on};
+    use wgpu::Maintain;
+
+    use super::*;
+
+    /// Tests that need one are ignored by default, run them with `--ignored` on a machine with a GPU
+    fn gpu() -> (RenderDevice, RenderQueue) {
+        let instance = wgpu::Instance::default();
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).expect("no GPU adapter");
+        let (device, queue) = block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).expect("no GPU device");
+        (RenderDevice::from(device), RenderQueue(Arc::new(queue)))
+    }
+
+    fn submit(device: &RenderDevice, queue: &RenderQueue, record: impl FnOnce(&mut CommandEncoder)) {
+        let mut command_encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        record(&mut command_encoder);
+        queue.submit(once(command_encoder.finish()));
+    }
+
+    /// Only read only buffers map, so the values go through one of those
+    fn read_back<T: Pod>(device: &RenderDevice, queue: &RenderQueue, src: &BufVec<T>) -> Vec<T> {
+        let mut readback = BufVec::with_capacity(true, src.len(), device);
+        submit(device, queue, |command_encoder| {
+            readback.copy_from(src, device, command_encoder).unwrap();
+            readback.encode_read(src.len(), command_encoder);
+        });
+        readback.request_read(src.len()).unwrap();
+        device.poll(Maintain::Wait);
+        let values = readback.try_read().unwrap().unwrap().to_vec();
+        values
+    }
+
+    /// Without MAPPABLE_PRIMARY_BUFFERS a mapped buffer can only be the other end of a copy, anything more fails validation
+    fn is_supported(usage: BufferUsages, features: wgpu::Features) -> bool {
+        let allowed = if usage.contains(BufferUsages::MAP_READ) {
+            BufferUsages::MAP_READ | BufferUsages::COPY_DST
+        } else if usage.contains(BufferUsages::MAP_WRITE) {
+            BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC
+        } else {
+            BufferUsages::all()
+        };
+        allowed.contains(usage) || features.contains(wgpu::Features::MAPPABLE_PRIMARY_BUFFERS)
+    }
+
+    #[test]
+    fn mapped_usages_are_only_copied_without_the_feature() {
+        let none = wgpu::Features::empty();
+        // Ours are fixed, so checking them here once covers every device
+        for usage in [staging_usage(true), staging_usage(false), BUFFER_USAGE, BufferUsages::MAP_WRITE | BufferUsages::COPY_SRC] {
+            assert!(is_supported(usage, none), "{:?}", usage);
+        }
+        for usage in [BufferUsages::MAP_READ | BufferUsages::STORAGE, BufferUsages::MAP_READ | BufferUsages::MAP_WRITE, BufferUsages::all()] {
+            assert!(!is_supported(usage, none), "{:?}", usage);
+        }
+        assert!(is_supported(BufferUsages::MAP_READ | BufferUsages::STORAGE, wgpu::Features::MAPPABLE_PRIMARY_BUFFERS));
+    }
+
+    #[test]
+    fn dirty_range_covers_every_change() {
+        let dirty = merge_dirty(None, 4..5);
+        let dirty = merge_dirty(Some(dirty), 1..2);
+        assert_eq!(dirty, 1..5);
+        assert_eq!(merge_dirty(Some(dirty.clone()), 2..3), 1..5);
+        assert_eq!(truncate_dirty(Some(dirty.clone()), 3), Some(1..3));
+        assert_eq!(truncate_dirty(Some(dirty), 1), None);
+        assert_eq!(truncate_dirty(None, 3), None);
+    }
+
+    #[test]
+    fn dirty_bytes_are_widened_to_the_copy_alignment() {
+        // Values a whole alignment unit wide are left as they are
+        assert_eq!(aligned_byte_range(1..3, 4, 64), 4..12);
+        // Single bytes out to the units around them
+        assert_eq!(aligned_byte_range(5..6, 1, 64), 4..8);
+        assert_eq!(aligned_byte_range(3..9, 2, 64), 4..20);
+        // Never past the end of the buffer, which is padded to the alignment itself
+        assert_eq!(aligned_byte_range(9..11, 1, copy_aligned(11)), 8..12);
+        assert_eq!(aligned_byte_range(2..3, 3, copy_aligned(9)), 4..12);
+    }
+
+    #[test]
+    fn pushing_one_at_a_time_only_grows_on_doubling() {
+        let mut capacity = 1;
+        let mut reallocations = 0;
+        for len in 1..=1000 {
+            let grown = grown_capacity(capacity, len, usize::MAX);
+            if grown != capacity {
+                reallocations += 1;
+                capacity = grown;
+            }
+        }
+        // Doubling from one reaches 1024 in ten steps, growing exactly would take 999
+        assert_eq!(reallocations, 10);
+        assert_eq!(capacity, 1024);
+        // Held back by the device, but never below what was asked for
+        assert_eq!(grown_capacity(1024, 1100, 1500), 1500);
+        assert_eq!(grown_capacity(1024, 1100, 1000), 1100);
+    }
+
+    #[test]
+    fn only_wasteful_buffers_shrink() {
+        assert!(!is_wasteful(16, 8));
+        assert!(is_wasteful(16, 7));
+        // Empty still needs one value to bind
+        assert!(!is_wasteful(2, 0));
+        assert!(is_wasteful(3, 0));
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn reserve_exact_grows_to_exactly_the_capacity() {
+        let (device, _queue) = gpu();
+        for read_only in [false, true] {
+            let mut values = BufVec::<u32>::with_capacity(read_only, 1, &device);
+            values.try_reserve_exact(8, &device).unwrap();
+            assert_eq!(values.capacity(), 8);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn from_slice_is_uploaded_already() {
+        let (device, queue) = gpu();
+        let values = BufVec::from_slice(false, &[3u32, 1, 4, 1, 5], &device);
+        assert_eq!(values.len(), 5);
+        assert_eq!(values.capacity(), 5);
+        assert_eq!(values.as_slice(), &[3, 1, 4, 1, 5]);
+        assert_eq!(values.dirty_range(), None);
+        assert_eq!(read_back(&device, &queue, &values), [3, 1, 4, 1, 5]);
+
+        let values = BufVec::from_vec_labeled(Some("test"), false, vec![9u32, 2, 6], &device);
+        assert_eq!(values.label(), Some("test"));
+        assert_eq!(values.as_slice(), &[9, 2, 6]);
+        assert_eq!(read_back(&device, &queue, &values), [9, 2, 6]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn copy_round_trips_once_the_source_is_written() {
+        let (device, queue) = gpu();
+        let mut source = BufVec::from_slice(false, &[1u32, 2, 3], &device);
+        source.push(4);
+        source.reserve(source.len(), &device);
+        let mut copy = BufVec::<u32>::with_capacity(false, 2, &device);
+        submit(&device, &queue, |command_encoder| {
+            assert!(matches!(copy.copy_from(&source, &device, command_encoder), Err(BufVecError::SourceNotUploaded)));
+        });
+        assert!(copy.is_empty());
+
+        submit(&device, &queue, |command_encoder| {
+            source.encode_write(&queue, command_encoder);
+            copy.copy_from(&source, &device, command_encoder).unwrap();
+        });
+        assert!(copy.capacity() >= 4);
+        assert_eq!(copy.as_slice(), &[1, 2, 3, 4]);
+        assert_eq!(copy.dirty_range(), None);
+        assert_eq!(read_back(&device, &queue, &copy), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn vectors_read_back_into_cleared_values() {
+        let (device, queue) = gpu();
+        let vectors = [Vec4::new(1.0, 2.0, 3.0, 4.0), Vec4::NEG_ONE, Vec4::splat(0.5)];
+        let source = BufVec::from_slice(false, &vectors, &device);
+        let mut readback = BufVec::<Vec4>::with_capacity(true, 3, &device);
+        submit(&device, &queue, |command_encoder| {
+            readback.copy_from(&source, &device, command_encoder).unwrap();
+            readback.encode_read(3, command_encoder);
+        });
+        // Values come back zero filled to fit, then overwritten by the read
+        readback.clear();
+        readback.map_buffer(3);
+        device.poll(Maintain::Wait);
+        readback.read_and_unmap_buffer(3);
+        assert_eq!(readback.as_slice(), &vectors);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn reserve_grows_geometrically() {
+        let (device, _queue) = gpu();
+        let mut values = BufVec::<u32>::with_capacity(false, 1, &device);
+        let mut reallocations = 0;
+        for value in 0..1000 {
+            let capacity = values.capacity();
+            values.push(value);
+            values.try_reserve(values.len(), &device).unwrap();
+            if values.capacity() != capacity {
+                reallocations += 1;
+            }
+        }
+        // Doubling from one reaches 1024 in ten steps, growing exactly would take 999
+        assert_eq!(reallocations, 10);
+        assert_eq!(values.capacity(), 1024);
+
+        values.try_reserve_exact(1100, &device).unwrap();
+        assert_eq!(values.capacity(), 1100);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn clear_zeroes_the_gpu_buffer() {
+        let (device, queue) = gpu();
+        let source = BufVec::from_slice(false, &[7u32, 8, 9, 10], &device);
+        let mut atomics = BufVec::<u32>::with_capacity(true, 4, &device);
+        submit(&device, &queue, |command_encoder| {
+            atomics.copy_from(&source, &device, command_encoder).unwrap();
+            atomics.encode_clear(command_encoder);
+            atomics.encode_read(4, command_encoder);
+        });
+        assert!(atomics.is_empty());
+        assert_eq!(atomics.capacity(), 4);
+        atomics.request_read(4).unwrap();
+        device.poll(Maintain::Wait);
+        assert_eq!(atomics.try_read().unwrap().unwrap(), &[0; 4]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn truncate_keeps_the_prefix() {
+        let (device, queue) = gpu();
+        let mut values = BufVec::from_slice(false, &[0u32, 1, 2, 3, 4, 5, 6, 7], &device);
+        values.push(8);
+        values.push(9);
+        values.truncate(9);
+        assert_eq!(values.as_slice(), &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(values.dirty_range(), Some(8..9));
+        // Nothing left to upload once the dirty values are all gone
+        values.truncate(4);
+        assert_eq!(values.dirty_range(), None);
+        assert_eq!(values.capacity(), 8);
+        assert_eq!(read_back(&device, &queue, &values), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn shrink_if_wasteful_only_recreates_past_the_threshold() {
+        let (device, queue) = gpu();
+        let mut values = BufVec::<u32>::with_capacity(false, 16, &device);
+        values.extend(0..8);
+        submit(&device, &queue, |command_encoder| values.encode_write(&queue, command_encoder));
+        let before = values.buffer().id();
+        assert!(!values.shrink_if_wasteful(&device));
+        assert_eq!(values.buffer().id(), before);
+        assert_eq!(values.capacity(), 16);
+
+        values.truncate(7);
+        assert!(values.shrink_if_wasteful(&device));
+        assert_ne!(values.buffer().id(), before);
+        assert_eq!(values.capacity(), 7);
+        assert_eq!(values.dirty_range(), Some(0..7));
+        submit(&device, &queue, |command_encoder| values.encode_write(&queue, command_encoder));
+        assert_eq!(read_back(&device, &queue, &values), [0, 1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn shrink_to_fit_recreates_at_exactly_the_length() {
+        let (device, queue) = gpu();
+        let mut values = BufVec::<u32>::with_capacity(false, 16, &device);
+        values.extend(0..10);
+        submit(&device, &queue, |command_encoder| values.encode_write(&queue, command_encoder));
+        assert!(values.shrink_to_fit(&device));
+        assert_eq!(values.capacity(), 10);
+        // Already exact, so there is nothing to recreate
+        let before = values.buffer().id();
+        assert!(!values.shrink_to_fit(&device));
+        assert_eq!(values.buffer().id(), before);
+        submit(&device, &queue, |command_encoder| values.encode_write(&queue, command_encoder));
+        assert_eq!(read_back(&device, &queue, &values), [0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    #[ignore = "needs a GPU"]
+    fn double_buffered_reads_back_while_the_other_is_written() {
+        let (device, queue) = gpu();
+        // Read only buffers are only written on the GPU, so they are filled by copying
+        let mut source = BufVec::<u32>::with_capacity(false, 4, &device);
+        let mut buffers = DoubleBuffered::<u32>::with_capacity_labeled(Some("test"), true, 4, &device);
+        for frame in 0..3 {
+            let current = buffers.current_mut();
+            assert!(current.try_read().is_none());
+            source.clear();
+            source.extend_from_slice(&[frame; 4]);
+            submit(&device, &queue, |command_encoder| {
+                source.encode_write(&queue, command_encoder);
+                current.copy_from(&source, &device, command_encoder).unwrap();
+                current.encode_read(4, command_encoder);
+            });
+            current.request_read(4).unwrap();
+            assert!(matches!(current.request_read(4), Err(BufVecError::ReadPending)));
+            buffers.swap();
+            assert!(!buffers.current().is_read_pending());
+
+            device.poll(Maintain::Wait);
+            assert_eq!(buffers.previous_mut().try_read().unwrap().unwrap(), &[frame; 4]);
+            assert!(!buffers.previous().is_read_pending());
+        }
     }
 }
@@ -0,0 +1,152 @@
+use bevy::prelude::*;
+
+use crate::{Config, config_loaded, ConfigState, Dead, Health, LocalPlayer, RenderPlayer};
+
+const NAMEPLATE_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.6);
+const NAMEPLATE_FILL: Color = Color::rgb(0.8, 0.15, 0.15);
+const NAMEPLATE_NAME_COLOR: Color = Color::WHITE;
+const NAMEPLATE_FONT_SIZE: f32 = 14.0;
+
+/// Health bar with an optional name over something that can be hurt, follows its target on screen
+#[derive(Component)]
+pub struct Nameplate {
+    target: Entity,
+}
+
+/// Width is the share of health left
+#[derive(Component)]
+pub struct NameplateFill;
+
+#[derive(Component)]
+pub struct NameplateBar;
+
+#[derive(Component)]
+pub struct NameplateName;
+
+pub struct NameplatePlugin;
+
+impl Plugin for NameplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (spawn_nameplates_sys, update_nameplates_sys).chain().run_if(config_loaded));
+    }
+}
+
+type NameplateSpawnFilter = (Added<Health>, Without<LocalPlayer>);
+
+/// Our own bar would sit right over the camera, so the local player never gets one
+fn spawn_nameplates_sys(
+    mut commands: Commands,
+    added_query: Query<(Entity, Option<&Name>), NameplateSpawnFilter>,
+    plate_query: Query<(Entity, &Nameplate)>,
+    health_query: Query<(), With<Health>>,
+) {
+    for (plate_ent, plate) in plate_query.iter() {
+        if !health_query.contains(plate.target) {
+            commands.entity(plate_ent).despawn_recursive();
+        }
+    }
+    for (target, name) in added_query.iter() {
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            Nameplate { target },
+        )).with_children(|parent| {
+            let name = name.map_or_else(String::new, |name| name.as_str().to_string());
+            parent.spawn((
+                TextBundle::from_section(name, TextStyle { font_size: NAMEPLATE_FONT_SIZE, color: NAMEPLATE_NAME_COLOR, ..default() }),
+                NameplateName,
+            ));
+            parent.spawn((
+                NodeBundle { background_color: NAMEPLATE_BACKGROUND.into(), ..default() },
+                NameplateBar,
+            )).with_children(|parent| {
+                parent.spawn((
+                    NodeBundle {
+                        style: Style { height: Val::Percent(100.0), ..default() },
+                        background_color: NAMEPLATE_FILL.into(),
+                        ..default()
+                    },
+                    NameplateFill,
+                ));
+            });
+        });
+    }
+}
+
+type NameplateTargetQuery<'a> = (&'a GlobalTransform, &'a Health, Has<Dead>, Has<LocalPlayer>);
+
+/// Parts of the plate, each with the style and color that change as health and distance do
+type NameplatePartQueries<'w, 's> = ParamSet<'w, 's, (
+    Query<'static, 'static, (&'static mut Style, &'static mut BackgroundColor), With<NameplateBar>>,
+    Query<'static, 'static, (&'static mut Style, &'static mut BackgroundColor), With<NameplateFill>>,
+    Query<'static, 'static, (&'static mut Text, &'static mut Style), With<NameplateName>>,
+)>;
+
+/// Projected every frame like damage numbers, fading with distance and hidden at full health unless configured otherwise
+fn update_nameplates_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<RenderPlayer>>,
+    target_query: Query<NameplateTargetQuery>,
+    mut plate_query: Query<(Entity, &Nameplate, &mut Style, &mut Visibility)>,
+    children_query: Query<&Children>,
+    mut part_queries: NameplatePartQueries,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let nameplates = &config.nameplates;
+    let camera = camera_query.iter().next();
+
+    for (plate_ent, plate, mut style, mut visibility) in plate_query.iter_mut() {
+        let Ok((transform, health, dead, local)) = target_query.get(plate.target) else { continue; };
+        let position = transform.translation() + Vec3::Y * nameplates.height;
+        let projected = camera.and_then(|(camera, camera_transform)| {
+            let screen = camera.world_to_viewport(camera_transform, position)?;
+            Some((screen, camera_transform.translation().distance(position)))
+        });
+        // Behind the camera has nothing to show
+        let Some((screen, distance)) = projected.filter(|_| nameplates.enabled && !dead && !local) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        let fraction = if health.max == 0 { 0.0 } else { health.current as f32 / health.max as f32 };
+        let distance_fade = 1.0 - ((distance - nameplates.fade_start) / nameplates.fade_distance).clamp(0.0, 1.0);
+        let opacity = distance_fade * if health.current >= health.max { nameplates.full_health_opacity } else { 1.0 };
+        if opacity <= 0.0 {
+            *visibility = Visibility::Hidden;
+            continue;
+        }
+        *visibility = Visibility::Inherited;
+
+        // Centered over the target with the bottom of the bar on the projected point
+        let name_height = if nameplates.show_names { NAMEPLATE_FONT_SIZE } else { 0.0 };
+        style.width = Val::Px(nameplates.size.x);
+        style.left = Val::Px(screen.x - nameplates.size.x * 0.5);
+        style.top = Val::Px(screen.y - nameplates.size.y - name_height);
+
+        for part in children_query.iter_descendants(plate_ent) {
+            if let Ok((mut bar_style, mut background)) = part_queries.p0().get_mut(part) {
+                bar_style.width = Val::Px(nameplates.size.x);
+                bar_style.height = Val::Px(nameplates.size.y);
+                background.0 = NAMEPLATE_BACKGROUND.with_a(NAMEPLATE_BACKGROUND.a() * opacity);
+            }
+            if let Ok((mut fill_style, mut fill)) = part_queries.p1().get_mut(part) {
+                fill_style.width = Val::Percent(fraction * 100.0);
+                fill.0 = NAMEPLATE_FILL.with_a(opacity);
+            }
+            if let Ok((mut text, mut name_style)) = part_queries.p2().get_mut(part) {
+                name_style.display = if nameplates.show_names { Display::Flex } else { Display::None };
+                for section in text.sections.iter_mut() {
+                    section.style.color = NAMEPLATE_NAME_COLOR.with_a(opacity);
+                }
+            }
+        }
+    }
+}
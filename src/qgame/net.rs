@@ -0,0 +1,723 @@
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy::prelude::shape::Capsule;
+use bevy_rapier3d::prelude::*;
+use flagset::FlagSet;
+use serde::{Deserialize, Serialize};
+use smartstring::alias::String;
+use thiserror::Error;
+
+use crate::{CAPSULE_BASE, CAPSULE_RADIUS, Config, ConfigState, Dead, Gun, Health, Inventory, Item, ItemName, LocalPlayer, LogicalPlayer, MoveEnvironment, player_collider, PlayerController, PlayerInput, render_player_camera, simulate_move, spawn_player, SpawnPicker, SpawnPointConfig, STAND_CAPSULE_TOP, TickInterpolation};
+
+pub const DEFAULT_PORT: u16 = 7777;
+/// Host included, every client is sent every player each tick so upload grows with the square of this
+pub const MAX_PLAYERS: u8 = 8;
+/// Clients that stay quiet this long are assumed gone and their player is removed
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_QUEUED_INPUTS: usize = 4;
+/// About two seconds at the default tick rate, longer than any round trip worth playing on
+const MAX_PREDICTED_TICKS: usize = 128;
+/// Meters or meters per second a prediction can be off and still count as right
+const PREDICTION_TOLERANCE: f32 = 0.01;
+/// Corrections bigger than this are teleports or respawns, snap those instead of gliding
+const MAX_SMOOTHED_CORRECTION: f32 = 1.0;
+/// How far behind the latest snapshot remote players are drawn
+const REMOTE_INTERPOLATION_DELAY: Duration = Duration::from_millis(100);
+const HELLO_INTERVAL: Duration = Duration::from_secs(1);
+/// Biggest datagram UDP allows, snapshots are nowhere near it
+const MAX_PACKET_SIZE: usize = 65507;
+
+#[derive(Debug, Error)]
+pub enum NetError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Encode(#[from] ron::Error),
+    #[error(transparent)]
+    Decode(#[from] ron::error::SpannedError),
+    #[error("bad address {0:?}")]
+    Address(std::string::String),
+    #[error("usage: qgame [--server [bind address]] [--client <server address>]")]
+    Usage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InputMessage {
+    pub tick: u32,
+    pub movement: Vec3,
+    pub flags: u32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub wanted_item_slot: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientMessage {
+    Hello,
+    Input(InputMessage),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ItemState {
+    pub name: ItemName,
+    pub state: String,
+    pub amount: u16,
+    /// Mag then reserve, only for guns
+    pub ammo: Option<(u16, u16)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerState {
+    pub id: u8,
+    pub translation: Vec3,
+    pub velocity: Vec3,
+    /// Everything movement carries between ticks, a client needs it to replay its inputs from here
+    pub controller: PlayerController,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub health: u16,
+    pub equipped_slot: Option<u8>,
+    pub equip_state: String,
+    pub slots: Vec<Option<ItemState>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ServerMessage {
+    Welcome { player_id: u8 },
+    Full,
+    /// Ack tick is the last input of the receiving client the server has simulated
+    Snapshot { tick: u32, ack_tick: Option<u32>, players: Vec<PlayerState> },
+}
+
+pub struct RemoteClient {
+    pub addr: SocketAddr,
+    pub player_id: u8,
+    pub player_ent: Option<Entity>,
+    pub last_heard: Instant,
+    /// Inputs received but not simulated yet, oldest first
+    pub inputs: VecDeque<InputMessage>,
+    /// Client tick of the input simulated most recently, sent back so they know what the snapshot includes
+    pub last_applied_tick: Option<u32>,
+}
+
+pub struct Server {
+    pub socket: UdpSocket,
+    pub clients: Vec<RemoteClient>,
+}
+
+pub struct Client {
+    pub socket: UdpSocket,
+    pub server_addr: SocketAddr,
+    /// Given by the server once it accepts us
+    pub player_id: Option<u8>,
+    pub last_hello: Option<Instant>,
+    pub last_snapshot_tick: Option<u32>,
+}
+
+/// A tick the client simulated ahead of the server, kept until the server confirms or corrects it
+pub struct PredictedTick {
+    pub tick: u32,
+    pub input: PlayerInput,
+    pub env: MoveEnvironment,
+    /// Where we were after the tick, the start of it until physics has moved us
+    pub translation: Vec3,
+    /// How far physics actually moved us over the tick, walls and ground included
+    pub displacement: Vec3,
+    pub controller: PlayerController,
+}
+
+/// On our own player as a client, moving right away instead of waiting a round trip for the server
+#[derive(Component, Default)]
+pub struct Prediction {
+    pub tick: u32,
+    pub history: VecDeque<PredictedTick>,
+    /// Snapshots where the server simulated no new input of ours have nothing to check against
+    pub last_ack_tick: Option<u32>,
+}
+
+impl Prediction {
+    /// Replays every input the server has not simulated yet on top of its state. Movement is replayed as far as
+    /// physics let it go the first time, integrating the velocity would walk straight through anything we pushed against.
+    /// Gives back the translation, velocity and controller to correct to, none when we had predicted it right
+    pub fn reconcile(&mut self, ack_tick: u32, server: &PlayerState, config: &Config, dt: f32) -> Option<(Vec3, Vec3, PlayerController)> {
+        if self.last_ack_tick == Some(ack_tick) {
+            return None;
+        }
+        self.last_ack_tick = Some(ack_tick);
+        while self.history.front().is_some_and(|predicted| (predicted.tick.wrapping_sub(ack_tick) as i32) < 0) {
+            self.history.pop_front();
+        }
+        if self.history.front().is_some_and(|predicted| predicted.tick == ack_tick) {
+            let acked = self.history.pop_front().unwrap();
+            let agrees = acked.translation.distance(server.translation) < PREDICTION_TOLERANCE
+                && acked.controller.velocity.distance(server.controller.velocity) < PREDICTION_TOLERANCE;
+            if agrees {
+                return None;
+            }
+        }
+
+        let mut translation = server.translation;
+        let mut linvel = server.velocity;
+        let mut controller = server.controller.clone();
+        for predicted in self.history.iter_mut() {
+            linvel = simulate_move(&mut controller, &predicted.input, &predicted.env, config, dt);
+            translation += predicted.displacement;
+            predicted.translation = translation;
+            predicted.controller = controller.clone();
+        }
+        Some((translation, linvel, controller))
+    }
+}
+
+/// On remote players as a client, positions from snapshots along with when they arrived
+#[derive(Component, Default)]
+pub struct SnapshotInterpolation {
+    pub snapshots: VecDeque<(Instant, Vec3)>,
+}
+
+/// Offline and server simulate everything, a client only sends its input and draws what the server sends back
+#[derive(Resource, Default)]
+pub enum Net {
+    #[default]
+    Offline,
+    Server(Server),
+    Client(Client),
+}
+
+fn parse_addr(addr: &str) -> Result<SocketAddr, NetError> {
+    addr.parse::<SocketAddr>()
+        .or_else(|_| format!("{}:{}", addr, DEFAULT_PORT).parse())
+        .map_err(|_| NetError::Address(addr.into()))
+}
+
+impl Net {
+    /// Reads `--server [bind address]` or `--client <server address>`, no arguments plays offline
+    pub fn from_args(mut args: impl Iterator<Item=std::string::String>) -> Result<Self, NetError> {
+        let net = match args.next().as_deref() {
+            None => Net::Offline,
+            Some("--server") => {
+                let bind_addr = match args.next() {
+                    Some(addr) => parse_addr(&addr)?,
+                    None => SocketAddr::from(([0, 0, 0, 0], DEFAULT_PORT)),
+                };
+                let socket = UdpSocket::bind(bind_addr)?;
+                socket.set_nonblocking(true)?;
+                info!("Hosting on {}", bind_addr);
+                Net::Server(Server { socket, clients: Vec::new() })
+            }
+            Some("--client") => {
+                let server_addr = parse_addr(&args.next().ok_or(NetError::Usage)?)?;
+                let socket = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0)))?;
+                socket.set_nonblocking(true)?;
+                info!("Connecting to {}", server_addr);
+                Net::Client(Client { socket, server_addr, player_id: None, last_hello: None, last_snapshot_tick: None })
+            }
+            Some(_) => return Err(NetError::Usage),
+        };
+        match args.next() {
+            Some(_) => Err(NetError::Usage),
+            None => Ok(net),
+        }
+    }
+}
+
+pub fn is_server(net: Res<Net>) -> bool {
+    matches!(*net, Net::Server(_))
+}
+
+pub fn is_client(net: Res<Net>) -> bool {
+    matches!(*net, Net::Client(_))
+}
+
+fn send<T: Serialize>(socket: &UdpSocket, addr: SocketAddr, message: &T) -> Result<(), NetError> {
+    socket.send_to(ron::to_string(message)?.as_bytes(), addr)?;
+    Ok(())
+}
+
+/// Everything waiting on the socket, stops at the first would block
+fn receive<T: for<'de> Deserialize<'de>>(socket: &UdpSocket) -> Vec<(SocketAddr, T)> {
+    let mut buf = vec![0; MAX_PACKET_SIZE];
+    let mut messages = Vec::new();
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, addr)) => match ron::de::from_bytes(&buf[..len]) {
+                Ok(message) => messages.push((addr, message)),
+                Err(err) => warn!("Bad message from {}: {}", addr, NetError::from(err)),
+            },
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // Windows reports an earlier send to a closed port here, nothing to do with this read
+            Err(err) if err.kind() == ErrorKind::ConnectionReset => continue,
+            Err(err) => {
+                error!("Could not receive: {}", err);
+                break;
+            }
+        }
+    }
+    messages
+}
+
+// ██╗      ██████╗  ██████╗ ██╗ ██████╗
+// ██║     ██╔═══██╗██╔════╝ ██║██╔════╝
+// ██║     ██║   ██║██║  ███╗██║██║
+// ██║     ██║   ██║██║   ██║██║██║
+// ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
+// ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
+
+/// Accepts new clients and queues up their inputs, the host's own input is read locally
+pub fn server_receive_sys(
+    mut commands: Commands,
+    mut net: ResMut<Net>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut spawn_picker: SpawnPicker,
+    inv_query: Query<&Inventory>,
+) {
+    let Net::Server(server) = &mut *net else { return; };
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    for (addr, message) in receive::<ClientMessage>(&server.socket) {
+        let client_index = server.clients.iter().position(|client| client.addr == addr);
+        match message {
+            ClientMessage::Hello => {
+                let player_id = match client_index {
+                    // Our welcome was probably lost, tell them again
+                    Some(index) => server.clients[index].player_id,
+                    None => {
+                        let Some(player_id) = (1..MAX_PLAYERS).find(|id| server.clients.iter().all(|client| client.player_id != *id)) else {
+                            if let Err(err) = send(&server.socket, addr, &ServerMessage::Full) {
+                                error!("Could not turn away {}: {}", addr, err);
+                            }
+                            continue;
+                        };
+                        let player_ent = spawn_player(&mut commands, config, spawn_picker.pick(), player_id);
+                        info!("Player {} joined from {}", player_id, addr);
+                        server.clients.push(RemoteClient {
+                            addr,
+                            player_id,
+                            player_ent: Some(player_ent),
+                            last_heard: Instant::now(),
+                            inputs: VecDeque::new(),
+                            last_applied_tick: None,
+                        });
+                        player_id
+                    }
+                };
+                if let Err(err) = send(&server.socket, addr, &ServerMessage::Welcome { player_id }) {
+                    error!("Could not welcome {}: {}", addr, err);
+                }
+            }
+            ClientMessage::Input(input) => {
+                let Some(client) = client_index.map(|index| &mut server.clients[index]) else { continue; };
+                client.last_heard = Instant::now();
+                // Duplicated or late datagrams are for ticks we have already simulated
+                let newest_tick = client.inputs.back().map(|queued| queued.tick).or(client.last_applied_tick);
+                if newest_tick.is_some_and(|newest| input.tick.wrapping_sub(newest) as i32 <= 0) {
+                    continue;
+                }
+                client.inputs.push_back(input);
+                // Falling this far behind would only add latency, drop the oldest instead
+                while client.inputs.len() > MAX_QUEUED_INPUTS {
+                    client.inputs.pop_front();
+                }
+            }
+        }
+    }
+
+    server.clients.retain(|client| {
+        let timed_out = client.last_heard.elapsed() > CLIENT_TIMEOUT;
+        if timed_out {
+            info!("Player {} timed out", client.player_id);
+            if let Some(player_ent) = client.player_ent {
+                despawn_player(&mut commands, player_ent, inv_query.get(player_ent).ok());
+            }
+        }
+        !timed_out
+    });
+}
+
+/// Items are entities of their own, so they would be left behind pointing at nobody
+fn despawn_player(commands: &mut Commands, player_ent: Entity, inv: Option<&Inventory>) {
+    for &item_ent in inv.iter().flat_map(|inv| inv.item_ents.0.iter().flatten()) {
+        commands.entity(item_ent).despawn_recursive();
+    }
+    commands.entity(player_ent).despawn_recursive();
+}
+
+/// Applies one queued input per tick, the same way the client predicted it, so the server ends up where they did
+pub fn server_apply_input_sys(
+    mut net: ResMut<Net>,
+    mut player_query: Query<(&mut PlayerInput, Has<Dead>), With<LogicalPlayer>>,
+) {
+    let Net::Server(server) = &mut *net else { return; };
+    for client in server.clients.iter_mut() {
+        // Nothing arrived in time, keep the last input going which is most likely what they are still doing
+        let Some(input) = client.inputs.pop_front() else { continue; };
+        client.last_applied_tick = Some(input.tick);
+        let Some(Ok((mut player_input, is_dead))) = client.player_ent.map(|ent| player_query.get_mut(ent)) else { continue; };
+        if is_dead {
+            continue;
+        }
+        *player_input = PlayerInput {
+            movement: input.movement.clamp_length_max(1.0),
+            flags: FlagSet::new_truncated(input.flags),
+            yaw: input.yaw,
+            pitch: input.pitch,
+            wanted_item_slot: input.wanted_item_slot,
+        };
+    }
+}
+
+type SnapshotPlayerQuery<'a> = (
+    &'a LogicalPlayer, &'a Transform, &'a Velocity, &'a PlayerController, &'a PlayerInput, &'a Health, &'a Inventory,
+);
+
+/// Sends every player to every client once per tick after physics has moved them
+pub fn server_send_sys(
+    mut tick: Local<u32>,
+    net: Res<Net>,
+    player_query: Query<SnapshotPlayerQuery>,
+    item_query: Query<(&Item, Option<&Gun>)>,
+) {
+    let Net::Server(server) = &*net else { return; };
+    *tick = tick.wrapping_add(1);
+    if server.clients.is_empty() {
+        return;
+    }
+
+    let players: Vec<_> = player_query.iter().map(|(player, transform, velocity, controller, input, health, inv)| PlayerState {
+        id: player.0,
+        translation: transform.translation,
+        velocity: velocity.linvel,
+        controller: controller.clone(),
+        yaw: input.yaw,
+        pitch: input.pitch,
+        health: health.current,
+        equipped_slot: inv.equipped_slot,
+        equip_state: inv.equip_state_name.clone(),
+        slots: inv.item_ents.0.iter().map(|item_ent| {
+            let (item, gun) = item_query.get((*item_ent)?).ok()?;
+            Some(ItemState {
+                name: item.name.clone(),
+                state: item.state_name.clone(),
+                amount: item.amount,
+                ammo: gun.map(|gun| (gun.ammo, gun.ammo_in_reserve)),
+            })
+        }).collect(),
+    }).collect();
+    for client in &server.clients {
+        // Each client gets told which of its own inputs made it in, the rest is the same for everyone
+        let snapshot = ServerMessage::Snapshot { tick: *tick, ack_tick: client.last_applied_tick, players: players.clone() };
+        if let Err(err) = send(&server.socket, client.addr, &snapshot) {
+            error!("Could not send snapshot to {}: {}", client.addr, err);
+        }
+    }
+}
+
+/// Says hello until the server answers, packets can be lost so keep retrying
+pub fn client_connect_sys(mut net: ResMut<Net>) {
+    let Net::Client(client) = &mut *net else { return; };
+    if client.player_id.is_some() || client.last_hello.is_some_and(|last| last.elapsed() < HELLO_INTERVAL) {
+        return;
+    }
+    client.last_hello = Some(Instant::now());
+    if let Err(err) = send(&client.socket, client.server_addr, &ClientMessage::Hello) {
+        error!("Could not reach {}: {}", client.server_addr, err);
+    }
+}
+
+type ReplicatedPlayerQuery<'a> = (
+    Entity,
+    &'a LogicalPlayer,
+    &'a mut Transform,
+    &'a mut Velocity,
+    &'a mut Collider,
+    &'a mut PlayerController,
+    &'a mut TickInterpolation,
+    &'a mut PlayerInput,
+    &'a mut Health,
+    &'a mut Inventory,
+    Option<&'a mut Prediction>,
+    Option<&'a mut SnapshotInterpolation>,
+);
+
+/// Corrects our own player against the server and queues up remote ones to be interpolated
+pub fn client_receive_sys(
+    mut commands: Commands,
+    mut net: ResMut<Net>,
+    fixed_time: Res<Time<Fixed>>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut player_query: Query<ReplicatedPlayerQuery>,
+    mut item_query: Query<&mut Item>,
+) {
+    let Net::Client(client) = &mut *net else { return; };
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let now = Instant::now();
+    let dt = fixed_time.timestep().as_secs_f32();
+
+    for (addr, message) in receive::<ServerMessage>(&client.socket) {
+        if addr != client.server_addr {
+            continue;
+        }
+        match message {
+            ServerMessage::Welcome { player_id } => {
+                if client.player_id.is_some() {
+                    continue;
+                }
+                info!("Joined as player {}", player_id);
+                client.player_id = Some(player_id);
+                let player_ent = spawn_player(&mut commands, config, SpawnPointConfig::default(), player_id);
+                commands.entity(player_ent).insert((LocalPlayer, Prediction::default()));
+                commands.spawn(render_player_camera(config, player_id));
+            }
+            ServerMessage::Full => {
+                if client.player_id.is_none() {
+                    warn!("Server at {} is full", client.server_addr);
+                }
+            }
+            ServerMessage::Snapshot { tick, ack_tick, players } => {
+                // Datagrams can arrive out of order, never go back in time
+                if client.last_snapshot_tick.is_some_and(|last| tick.wrapping_sub(last) as i32 <= 0) {
+                    continue;
+                }
+                client.last_snapshot_tick = Some(tick);
+
+                // Whoever the server stopped sending has left
+                for (player_ent, player, .., inv, _, snapshots) in player_query.iter() {
+                    if snapshots.is_some() && players.iter().all(|state| state.id != player.0) {
+                        despawn_player(&mut commands, player_ent, Some(inv));
+                    }
+                }
+
+                for state in players {
+                    let existing = player_query.iter_mut().find(|(_, player, ..)| player.0 == state.id);
+                    let Some((
+                        player_ent, _, mut transform, mut velocity, mut collider, mut controller,
+                        mut interpolation, mut input, mut health, mut inv, prediction, snapshots,
+                    )) = existing else {
+                        // Ours is spawned on welcome so the camera goes with it
+                        if client.player_id != Some(state.id) {
+                            let player_ent = spawn_player(&mut commands, config, SpawnPointConfig { translation: state.translation, yaw: state.yaw }, state.id);
+                            commands.entity(player_ent).insert((
+                                SnapshotInterpolation { snapshots: VecDeque::from([(now, state.translation)]) },
+                                // Moved to wherever the snapshots say, never by our physics
+                                RigidBody::KinematicPositionBased,
+                            ));
+                        }
+                        continue;
+                    };
+
+                    let stance = controller.stance;
+                    match (prediction, snapshots) {
+                        (Some(mut prediction), _) => {
+                            let corrected = match ack_tick {
+                                Some(ack_tick) => prediction.reconcile(ack_tick, &state, config, dt),
+                                // None of our inputs have made it yet, so there is nothing to predict from
+                                None => Some((state.translation, state.velocity, state.controller.clone())),
+                            };
+                            if let Some((translation, linvel, corrected_controller)) = corrected {
+                                let correction = transform.translation - translation;
+                                if correction.length() < MAX_SMOOTHED_CORRECTION {
+                                    // Keep drawing where we were and let the difference fade out
+                                    interpolation.previous -= correction;
+                                    interpolation.current -= correction;
+                                    interpolation.correction += correction;
+                                } else {
+                                    *interpolation = TickInterpolation { previous: translation, current: translation, ..default() };
+                                }
+                                transform.translation = translation;
+                                velocity.linvel = linvel;
+                                *controller = corrected_controller;
+                            }
+                        }
+                        (None, Some(mut snapshots)) => {
+                            snapshots.snapshots.push_back((now, state.translation));
+                            *controller = state.controller;
+                            input.yaw = state.yaw;
+                            input.pitch = state.pitch;
+                        }
+                        (None, None) => {}
+                    }
+                    if controller.stance != stance {
+                        *collider = player_collider(controller.stance);
+                    }
+
+                    health.current = state.health;
+                    inv.equipped_slot = state.equipped_slot;
+                    inv.equip_state_name = state.equip_state;
+                    for (slot, item_state) in state.slots.into_iter().enumerate().take(inv.item_ents.0.len()) {
+                        let slot = slot as u8;
+                        let existing_item = inv.item_ents.get(slot).and_then(|item_ent| item_query.get_mut(item_ent).ok().map(|item| (item_ent, item)));
+                        match (item_state, existing_item) {
+                            (Some(item_state), Some((item_ent, mut item))) if item.name == item_state.name => {
+                                item.state_name = item_state.state;
+                                item.amount = item_state.amount;
+                                if let Some((ammo, ammo_in_reserve)) = item_state.ammo {
+                                    commands.entity(item_ent).insert(Gun { ammo, ammo_in_reserve });
+                                }
+                            }
+                            (Some(item_state), _) => {
+                                let equipped_slot = inv.equipped_slot;
+                                let gun = item_state.ammo.map(|(ammo, ammo_in_reserve)| Gun { ammo, ammo_in_reserve });
+                                inv.set_item(player_ent, &mut commands, &item_state.name, item_state.amount, slot, gun);
+                                // Setting an item equips it when nothing is, the snapshot knows better
+                                inv.equipped_slot = equipped_slot;
+                            }
+                            (None, Some((item_ent, _))) => {
+                                commands.entity(item_ent).despawn_recursive();
+                                inv.item_ents.0[slot as usize] = None;
+                            }
+                            (None, None) => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Ships this tick's input to the server and remembers it for replaying, before latched presses are cleared
+pub fn client_send_sys(
+    net: Res<Net>,
+    mut player_query: Query<(&Transform, &PlayerInput, &mut Prediction), With<LocalPlayer>>,
+) {
+    let Net::Client(client) = &*net else { return; };
+    let Ok((transform, input, mut prediction)) = player_query.get_single_mut() else { return; };
+    prediction.tick = prediction.tick.wrapping_add(1);
+    let tick = prediction.tick;
+    prediction.history.push_back(PredictedTick {
+        tick,
+        input: input.clone(),
+        env: MoveEnvironment::default(),
+        translation: transform.translation,
+        displacement: Vec3::ZERO,
+        controller: PlayerController::default(),
+    });
+    while prediction.history.len() > MAX_PREDICTED_TICKS {
+        prediction.history.pop_front();
+    }
+
+    let message = ClientMessage::Input(InputMessage {
+        tick,
+        movement: input.movement,
+        flags: input.flags.bits(),
+        yaw: input.yaw,
+        pitch: input.pitch,
+        wanted_item_slot: input.wanted_item_slot,
+    });
+    if let Err(err) = send(&client.socket, client.server_addr, &message) {
+        error!("Could not send input: {}", err);
+    }
+}
+
+/// Fills in where this tick's prediction ended up once physics has moved us
+pub fn record_prediction_sys(
+    mut player_query: Query<(&Transform, &PlayerController, &MoveEnvironment, &mut Prediction)>,
+) {
+    for (transform, controller, env, mut prediction) in player_query.iter_mut() {
+        let tick = prediction.tick;
+        if let Some(predicted) = prediction.history.back_mut().filter(|predicted| predicted.tick == tick) {
+            predicted.env = *env;
+            predicted.displacement = transform.translation - predicted.translation;
+            predicted.translation = transform.translation;
+            predicted.controller = controller.clone();
+        }
+    }
+}
+
+// ██████╗ ███████╗███╗   ██╗██████╗ ███████╗██████╗
+// ██╔══██╗██╔════╝████╗  ██║██╔══██╗██╔════╝██╔══██╗
+// ██████╔╝█████╗  ██╔██╗ ██║██║  ██║█████╗  ██████╔╝
+// ██╔══██╗██╔══╝  ██║╚██╗██║██║  ██║██╔══╝  ██╔══██╗
+// ██║  ██║███████╗██║ ╚████║██████╔╝███████╗██║  ██║
+// ╚═╝  ╚═╝╚══════╝╚═╝  ╚═══╝╚═════╝ ╚══════╝╚═╝  ╚═╝
+
+/// Players we do not see through need a body to look at
+pub fn remote_player_model_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    player_query: Query<Entity, (Added<LogicalPlayer>, Without<LocalPlayer>)>,
+) {
+    for player_ent in player_query.iter() {
+        let model_ent = commands.spawn(PbrBundle {
+            mesh: meshes.add(Mesh::from(Capsule {
+                radius: CAPSULE_RADIUS,
+                depth: STAND_CAPSULE_TOP - CAPSULE_BASE,
+                ..default()
+            })),
+            material: materials.add(Color::rgb(0.2, 0.4, 0.8).into()),
+            transform: Transform::from_xyz(0.0, (CAPSULE_BASE + STAND_CAPSULE_TOP) / 2.0, 0.0),
+            ..default()
+        }).id();
+        commands.entity(player_ent).insert(VisibilityBundle::default()).add_child(model_ent);
+    }
+}
+
+/// Draws remote players a little in the past so there is nearly always a snapshot on either side to blend between
+pub fn interpolate_remote_players_sys(
+    mut player_query: Query<(&mut Transform, &mut SnapshotInterpolation)>,
+) {
+    let Some(render_time) = Instant::now().checked_sub(REMOTE_INTERPOLATION_DELAY) else { return; };
+    for (mut transform, mut interpolation) in player_query.iter_mut() {
+        let snapshots = &mut interpolation.snapshots;
+        while snapshots.len() > 2 && snapshots[1].0 <= render_time {
+            snapshots.pop_front();
+        }
+        transform.translation = match (snapshots.front(), snapshots.get(1)) {
+            (Some(&(from_time, from)), Some(&(to_time, to))) if to_time > from_time => {
+                let t = render_time.saturating_duration_since(from_time).as_secs_f32() / (to_time - from_time).as_secs_f32();
+                from.lerp(to, t.min(1.0))
+            }
+            (Some(&(_, from)), _) => from,
+            _ => continue,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    #[test]
+    fn replay_does_not_walk_through_a_wall() {
+        // Pressed up against a wall in front, walking into it goes nowhere
+        let against_wall = Vec3::new(0.0, 1.0, -5.0);
+        let input = PlayerInput { movement: Vec3::Z, ..default() };
+        let env = MoveEnvironment { grounded: true, ground_normal: Some(Vec3::Y), ..default() };
+        let history = (1..=4).map(|tick| PredictedTick {
+            tick,
+            input: input.clone(),
+            env,
+            translation: against_wall,
+            displacement: Vec3::ZERO,
+            controller: PlayerController::default(),
+        }).collect();
+        let mut prediction = Prediction { tick: 4, history, last_ack_tick: None };
+
+        // The server agrees on where we are but not on how fast we are trying to go
+        let server = PlayerState {
+            id: 0,
+            translation: against_wall,
+            velocity: Vec3::ZERO,
+            controller: PlayerController { velocity: Vec3::new(0.0, 0.0, -3.0), ..default() },
+            yaw: 0.0,
+            pitch: 0.0,
+            health: 100,
+            equipped_slot: None,
+            equip_state: String::new(),
+            slots: Vec::new(),
+        };
+        let (translation, ..) = prediction.reconcile(1, &server, &Config::default(), DT).expect("velocity was off");
+        assert_eq!(translation, against_wall);
+        assert!(prediction.history.iter().all(|predicted| predicted.translation == against_wall));
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fmt::{self, Write},
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::{BoundInput, Config, ConfigState};
+
+const PROFILE_DUMP_PATH: &str = "meshing_profile.txt";
+
+/// Buckets double in width, the first holds anything under a microsecond and the last anything over about 16ms
+const HISTOGRAM_BUCKETS: usize = 16;
+/// Darker characters for fuller buckets, kept to ASCII so the default font can draw them
+const HISTOGRAM_SHADES: &[u8] = b" .:-=+*#%@";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MeshingStage {
+    /// Noise and voxel compute passes, up to when their results are ready to map
+    Dispatch,
+    /// Copying vertex attributes and indices back from the GPU
+    Readback,
+    /// Writing readback results into the chunk mesh
+    MeshApply,
+    /// Building the trimesh collider in the background, recorded once it is applied
+    ColliderCook,
+}
+
+impl MeshingStage {
+    pub const ALL: [MeshingStage; 4] = [MeshingStage::Dispatch, MeshingStage::Readback, MeshingStage::MeshApply, MeshingStage::ColliderCook];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MeshingStage::Dispatch => "dispatch",
+            MeshingStage::Readback => "readback",
+            MeshingStage::MeshApply => "mesh apply",
+            MeshingStage::ColliderCook => "collider",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+pub struct StageTimings {
+    pub samples: u32,
+    pub total: Duration,
+    pub max: Duration,
+    pub buckets: [u32; HISTOGRAM_BUCKETS],
+}
+
+/// Per chunk timings of each meshing stage, only collected while profiling is turned on in the config.
+/// A chunk is always timed start to finish within one frame. Its readback lands while the next chunk is dispatched,
+/// so that stage only times copying out. Colliders cook over however many frames it takes and are timed on their own thread
+#[derive(Resource, Default, Debug)]
+pub struct MeshingProfiler {
+    pub stages: [StageTimings; MeshingStage::ALL.len()],
+    pub chunks: u32,
+    /// Chunks entirely air or solid, these never reach the GPU so have no stage timings
+    pub skipped_chunks: u32,
+}
+
+impl StageTimings {
+    pub fn record(&mut self, dur: Duration) {
+        self.samples += 1;
+        self.total += dur;
+        self.max = self.max.max(dur);
+        let micros = dur.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(HISTOGRAM_BUCKETS - 1)] += 1;
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.samples).unwrap_or_default()
+    }
+
+    fn write_histogram(&self, out: &mut impl Write) -> fmt::Result {
+        let fullest = self.buckets.iter().copied().max().unwrap_or_default().max(1);
+        for &count in &self.buckets {
+            // Any non-empty bucket gets at least the lightest shade so rare outliers stay visible
+            let shade = (count as usize * (HISTOGRAM_SHADES.len() - 1)).div_ceil(fullest as usize);
+            out.write_char(HISTOGRAM_SHADES[shade] as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl MeshingProfiler {
+    pub fn stage(&self, stage: MeshingStage) -> &StageTimings {
+        &self.stages[stage as usize]
+    }
+
+    pub fn record(&mut self, stage: MeshingStage, dur: Duration) {
+        self.stages[stage as usize].record(dur);
+    }
+
+    /// Times from the given start, returning now so the next stage can pick up where this one ended
+    pub fn lap(&mut self, stage: MeshingStage, start: Instant) -> Instant {
+        let now = Instant::now();
+        self.record(stage, now - start);
+        now
+    }
+
+    /// One line per stage with its histogram, short enough to sit in the debug overlay
+    pub fn write_summary(&self, out: &mut impl Write) -> fmt::Result {
+        write!(out, "{} chunks meshed, {} skipped", self.chunks, self.skipped_chunks)?;
+        for stage in MeshingStage::ALL {
+            let timings = self.stage(stage);
+            write!(out, "\n{:>10} {:>8.3} ms avg {:>8.3} ms max |", stage.name(), timings.mean().as_secs_f64() * 1e3, timings.max.as_secs_f64() * 1e3)?;
+            timings.write_histogram(out)?;
+            out.write_char('|')?;
+        }
+        Ok(())
+    }
+
+    /// Raw bucket counts so a dump can be compared across sessions
+    pub fn write_report(&self, out: &mut impl Write) -> fmt::Result {
+        self.write_summary(out)?;
+        writeln!(out, "\n\nbucket upper bounds in microseconds, last is unbounded")?;
+        write!(out, "{:>10}", "")?;
+        for bucket in 0..HISTOGRAM_BUCKETS {
+            write!(out, " {:>6}", 1u64 << bucket)?;
+        }
+        for stage in MeshingStage::ALL {
+            write!(out, "\n{:>10}", stage.name())?;
+            for count in self.stage(stage).buckets {
+                write!(out, " {:>6}", count)?;
+            }
+        }
+        writeln!(out)
+    }
+}
+
+pub fn dump_meshing_profile_sys(
+    input: BoundInput,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    profiler: Res<MeshingProfiler>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    if !config.profile_chunk_meshing || !input.just_pressed(input.bindings.dump_meshing_profile) { return; }
+
+    let mut report = String::new();
+    profiler.write_report(&mut report).unwrap();
+    match std::fs::write(PROFILE_DUMP_PATH, report) {
+        Ok(()) => info!("Wrote meshing profile to {}", PROFILE_DUMP_PATH),
+        Err(err) => error!("Could not write meshing profile to {}: {}", PROFILE_DUMP_PATH, err),
+    }
+}
@@ -0,0 +1,157 @@
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{Config, ConfigState};
+
+/// Each subsystem draws from its own stream, so adding a draw in one never shifts what another gets
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RngStream {
+    Spread,
+    Recoil,
+    Spawns,
+    Bots,
+}
+
+const RNG_STREAM_COUNT: usize = 4;
+
+/// All gameplay randomness, the same seed and inputs replay exactly as long as draws happen on the fixed tick.
+/// Anything only for show like casings and particles keeps using the thread rng
+#[derive(Resource)]
+pub struct GameRng {
+    seed: u64,
+    streams: [ChaCha8Rng; RNG_STREAM_COUNT],
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, streams: std::array::from_fn(|stream| Self::fork(seed, stream as u64)) }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn stream(&mut self, stream: RngStream) -> &mut ChaCha8Rng {
+        &mut self.streams[stream as usize]
+    }
+
+    /// Independent of every other stream with the same seed, ChaCha gives each stream its own sequence
+    pub fn fork(seed: u64, stream: u64) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        rng.set_stream(stream);
+        rng
+    }
+}
+
+/// Only reseeds when the seed itself changes, so editing anything else in the config mid game does not restart the streams
+pub fn seed_game_rng_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut rng: ResMut<GameRng>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    if rng.seed() != config.rng_seed {
+        *rng = GameRng::new(config.rng_seed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{scene::SceneSpawner, time::TimeUpdateStrategy};
+    use bevy_rapier3d::prelude::*;
+    use rand::RngCore;
+
+    use crate::{DamageEvent, DryFireEvent, FireEvent, Gun, hitscan_sys, ImpactEvent, Inventory, Item, ItemConfig, ItemRegistry, ItemStateEvent, modify_item_sys, PlayerController, PlayerInput, PlayerInputFlags, Spread, spread_sys};
+
+    use super::*;
+
+    const RIFLE: &str = include_str!("../../assets/items/rifle.item.ron");
+    /// Holds and releases of the trigger, one per frame, long frames so held ones keep refiring
+    const FIRE_SCRIPT: [bool; 12] = [true, true, true, false, true, false, false, true, true, true, true, false];
+
+    /// Shoots a rifle at a wall following the script in a game seeded from the config,
+    /// returns the direction of every shot as the hitscan system drew them
+    fn spread_directions(seed: u64) -> Vec<Vec3> {
+        let mut configs = Assets::<Config>::default();
+        let handle = configs.add(Config { rng_seed: seed, ..default() });
+        let mut item_configs = Assets::<ItemConfig>::default();
+        let mut registry = ItemRegistry::default();
+        let rifle: ItemConfig = ron::from_str(RIFLE).unwrap();
+        registry.register(rifle.item_props().name.clone(), item_configs.add(rifle));
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, RapierPhysicsPlugin::<NoUserData>::default()))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_millis(150)))
+            .insert_resource(configs)
+            .insert_resource(ConfigState { handle })
+            .insert_resource(item_configs)
+            .insert_resource(registry)
+            // Only used for colliders built from meshes and scenes
+            .init_resource::<Assets<Mesh>>()
+            .init_resource::<SceneSpawner>()
+            .init_resource::<GameRng>()
+            .add_event::<FireEvent>()
+            .add_event::<DryFireEvent>()
+            .add_event::<ItemStateEvent>()
+            .add_event::<DamageEvent>()
+            .add_event::<ImpactEvent>()
+            .add_systems(PreUpdate, seed_game_rng_sys)
+            .add_systems(Update, (modify_item_sys, spread_sys, hitscan_sys).chain());
+
+        let shooter = app.world.spawn((
+            TransformBundle::default(), Velocity::zero(), PlayerController::default(), PlayerInput::default(), Spread::default(),
+        )).id();
+        let item_ent = app.world.spawn((
+            Item { name: "rifle".into(), amount: 1, state_name: "idle".into(), state_dur: Duration::ZERO, inv_ent: shooter, inv_slot: 0 },
+            Gun { ammo: 30, ammo_in_reserve: 0 },
+        )).id();
+        let mut inv = Inventory::with_slots(1);
+        inv.item_ents.0[0] = Some(item_ent);
+        inv.equipped_slot = Some(0);
+        inv.equip_state_name = "equipped".into();
+        app.world.entity_mut(shooter).insert(inv);
+        app.world.spawn((Collider::cuboid(50.0, 50.0, 1.0), TransformBundle::from_transform(Transform::from_xyz(0.0, 0.0, -20.0))));
+        // Colliders only make it into the physics world at the end of a frame
+        app.update();
+
+        let eye = Vec3::Y * PlayerController::default().eye_height();
+        let mut fire_reader = app.world.resource::<Events<FireEvent>>().get_reader();
+        let mut impact_reader = app.world.resource::<Events<ImpactEvent>>().get_reader();
+        let mut shots = 0;
+        let mut directions = Vec::new();
+        for fire in FIRE_SCRIPT {
+            app.world.get_mut::<PlayerInput>(shooter).unwrap().flags = if fire { PlayerInputFlags::Fire.into() } else { default() };
+            app.update();
+            shots += fire_reader.read(app.world.resource::<Events<FireEvent>>()).count();
+            let impacts = app.world.resource::<Events<ImpactEvent>>();
+            directions.extend(impact_reader.read(impacts).map(|impact| (impact.position - eye).normalize()));
+        }
+        assert_eq!(directions.len(), shots, "every shot hits the wall");
+        directions
+    }
+
+    #[test]
+    fn same_seed_replays_the_same_spread() {
+        assert_eq!(spread_directions(42), spread_directions(42));
+        assert_ne!(spread_directions(42), spread_directions(43));
+    }
+
+    #[test]
+    fn streams_do_not_shift_each_other() {
+        let mut quiet = GameRng::new(7);
+        let mut busy = GameRng::new(7);
+        for _ in 0..8 {
+            busy.stream(RngStream::Recoil).next_u64();
+        }
+        assert_eq!(quiet.stream(RngStream::Spread).next_u64(), busy.stream(RngStream::Spread).next_u64());
+        assert_ne!(quiet.stream(RngStream::Spread).next_u64(), quiet.stream(RngStream::Bots).next_u64());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    prelude::*,
+    render::view::screenshot::ScreenshotManager,
+    window::PrimaryWindow,
+};
+
+use crate::BoundInput;
+
+const SCREENSHOT_DIRECTORY: &str = "screenshots";
+
+/// Set when the HUD was hidden for a capture, so it can be shown again once that frame has rendered
+#[derive(Resource, Default)]
+pub struct Screenshots {
+    hiding_hud: bool,
+}
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Screenshots>()
+            .add_systems(Update, screenshot_sys);
+    }
+}
+
+/// Named by when it was taken, counting up past any that already exist so nothing is written over
+fn next_screenshot_path() -> PathBuf {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis());
+    (0..)
+        .map(|count| PathBuf::from(format!("{}/screenshot_{}_{}.png", SCREENSHOT_DIRECTORY, millis, count)))
+        .find(|path| !path.exists())
+        .expect("Ran out of screenshot names")
+}
+
+/// Reading the frame back and writing it out both happen off the main thread, the saved path is logged once done
+pub fn screenshot_sys(
+    mut commands: Commands,
+    input: BoundInput,
+    mut screenshots: ResMut<Screenshots>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+    camera_query: Query<Entity, With<Camera>>,
+) {
+    // The hidden frame has been sent off to render by now
+    if screenshots.hiding_hud {
+        screenshots.hiding_hud = false;
+        for camera_ent in camera_query.iter() {
+            commands.entity(camera_ent).remove::<UiCameraConfig>();
+        }
+    }
+    if !input.just_pressed(input.bindings.screenshot) { return; }
+    let Ok(window_ent) = window_query.get_single() else { return; };
+
+    if let Err(err) = fs::create_dir_all(SCREENSHOT_DIRECTORY) {
+        error!("Could not create {}: {}", SCREENSHOT_DIRECTORY, err);
+        return;
+    }
+    let path = next_screenshot_path();
+    if let Err(err) = screenshot_manager.save_screenshot_to_disk(window_ent, &path) {
+        warn!("Could not take screenshot {}: {}", path.display(), err);
+        return;
+    }
+    if input.pressed(input.bindings.screenshot_hide_hud) {
+        screenshots.hiding_hud = true;
+        for camera_ent in camera_query.iter() {
+            commands.entity(camera_ent).insert(UiCameraConfig { show_ui: false });
+        }
+    }
+}
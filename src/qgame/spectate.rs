@@ -0,0 +1,139 @@
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+
+use crate::{BoundInput, Config, ConfigState, GameState, LocalPlayer, look_quat, PITCH_LIMIT, PlayerInput, render_player_camera_sys, RenderPlayer};
+
+const SPECTATE_SPEED: f32 = 16.0;
+const SPECTATE_MIN_SPEED: f32 = 1.0;
+const SPECTATE_MAX_SPEED: f32 = 512.0;
+/// Speed is multiplied by this for every notch scrolled up, and divided for every notch down
+const SPECTATE_SPEED_STEP: f32 = 1.25;
+const SPECTATE_SPRINT_FACTOR: f32 = 4.0;
+
+/// Detached camera for looking around from outside the player, whose body stays put and keeps simulating
+#[derive(Resource, Debug)]
+pub struct Spectate {
+    pub active: bool,
+    /// Meters per second, changed with the scroll wheel
+    pub speed: f32,
+    /// Kept apart from the camera since the player camera system moves it back every frame
+    pub translation: Vec3,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl Default for Spectate {
+    fn default() -> Self {
+        Self { active: false, speed: SPECTATE_SPEED, translation: Vec3::ZERO, pitch: 0.0, yaw: 0.0 }
+    }
+}
+
+pub struct SpectatePlugin;
+
+impl Plugin for SpectatePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<Spectate>()
+            .add_systems(Update, (toggle_spectate_sys, spectate_camera_sys).chain()
+                .after(render_player_camera_sys)
+                .run_if(in_state(GameState::Playing)))
+            // Leaving for a menu hands the view back, so resuming never finds the player stuck without control
+            .add_systems(OnExit(GameState::Playing), stop_spectating_sys);
+    }
+}
+
+pub fn not_spectating(spectate: Res<Spectate>) -> bool {
+    !spectate.active
+}
+
+/// Starts from wherever the player camera is looking, leaving drops straight back into the player view
+fn toggle_spectate_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    input: BoundInput,
+    mut spectate: ResMut<Spectate>,
+    mut player_query: Query<&mut PlayerInput, With<LocalPlayer>>,
+    camera_query: Query<&Transform, With<RenderPlayer>>,
+) {
+    let debug = config.get(&config_state.handle).is_some_and(|config| config.debug);
+    let toggled = input.just_pressed(input.bindings.spectate);
+    if spectate.active && (toggled || !debug) {
+        spectate.active = false;
+        return;
+    }
+    if !toggled || !debug { return; }
+    let Ok(mut player_input) = player_query.get_single_mut() else { return; };
+    let Ok(camera_transform) = camera_query.get_single() else { return; };
+    spectate.active = true;
+    spectate.translation = camera_transform.translation;
+    spectate.pitch = player_input.pitch;
+    spectate.yaw = player_input.yaw;
+    // Input stops being read while spectating, so let go of everything or the body would keep running
+    player_input.movement = Vec3::ZERO;
+    player_input.flags.clear();
+}
+
+fn stop_spectating_sys(mut spectate: ResMut<Spectate>) {
+    spectate.active = false;
+}
+
+/// Read separately from player input, which is not running while spectating
+#[derive(SystemParam)]
+pub struct SpectateInput<'w, 's> {
+    bound: BoundInput<'w>,
+    mouse_events: EventReader<'w, 's, MouseMotion>,
+    wheel_events: EventReader<'w, 's, MouseWheel>,
+}
+
+impl SpectateInput<'_, '_> {
+    fn mouse_delta(&mut self) -> Vec2 {
+        self.mouse_events.read().map(|event| event.delta).sum()
+    }
+
+    /// In notches, up is positive
+    fn scroll(&mut self) -> f32 {
+        self.wheel_events.read().map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // Touchpads report pixels, roughly one notch every twenty
+            MouseScrollUnit::Pixel => event.y / 20.0,
+        }).sum()
+    }
+}
+
+/// Runs after the player camera has been placed, so it wins while active and the player view is back the frame it stops
+fn spectate_camera_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut input: SpectateInput,
+    mut spectate: ResMut<Spectate>,
+    mut camera_query: Query<&mut Transform, With<RenderPlayer>>,
+) {
+    // Always drained so a toggle does not jump by whatever piled up before it
+    let mouse_delta = input.mouse_delta();
+    let scroll = input.scroll();
+    if !spectate.active { return; }
+    let Some(config) = config.get(&config_state.handle) else { return; };
+
+    spectate.speed = (spectate.speed * SPECTATE_SPEED_STEP.powf(scroll)).clamp(SPECTATE_MIN_SPEED, SPECTATE_MAX_SPEED);
+    let look = mouse_delta * config.sensitivity * Vec2::new(config.horizontal_sensitivity_factor, config.vertical_sensitivity_factor);
+    let look_y = if config.invert_y { -look.y } else { look.y };
+    spectate.pitch = (spectate.pitch - look_y).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+    spectate.yaw -= look.x;
+
+    let input = &input.bound;
+    let bindings = &input.bindings;
+    let rotation = look_quat(spectate.pitch, spectate.yaw);
+    // Flies where the camera points, up and down stay straight up and down
+    let wish = rotation * Vec3::new(input.get_axis(bindings.right, bindings.left), 0.0, -input.get_axis(bindings.forward, bindings.back))
+        + Vec3::Y * input.get_axis(bindings.up, bindings.down);
+    let speed = spectate.speed * if input.pressed(bindings.sprint) { SPECTATE_SPRINT_FACTOR } else { 1.0 };
+    spectate.translation += wish.normalize_or_zero() * speed * time.delta_seconds();
+    for mut transform in camera_query.iter_mut() {
+        transform.translation = spectate.translation;
+        transform.rotation = rotation;
+    }
+}
@@ -1,34 +1,117 @@
 use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
     iter::once,
-    mem::size_of,
+    path::Path,
+    time::{Duration, Instant},
 };
 
 use bevy::{
-    core::{cast_slice, Pod, Zeroable},
+    core::{Pod, Zeroable},
     prelude::*,
     render::{
         mesh::{Indices, VertexAttributeValues},
+        primitives::Aabb,
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
     },
+    tasks::{AsyncComputeTaskPool, block_on, Task},
     utils::HashMap,
 };
+use bevy_rapier3d::{prelude::Collider, rapier::prelude::SharedShape};
+use thiserror::Error;
 use wgpu::MaintainBase::Wait;
 
 use crate::*;
 
 // use flagset::{flags, FlagSet};
 
-const CHUNK_SZ: usize = 32;
+/// Voxels along each side of a chunk, which is also its width in meters
+pub const CHUNK_SZ: usize = 32;
 const CHUNK_SZ_2: usize = CHUNK_SZ * CHUNK_SZ;
 const CHUNK_SZ_3: usize = CHUNK_SZ * CHUNK_SZ * CHUNK_SZ;
 
+/// Vertices closer than this along every axis are treated as the same point when smoothing normals
+const WELD_PRECISION: f32 = 1024.0;
+
+/// Density the surface is placed at, must match the voxels shader
+const ISO_LEVEL: f32 = 0.5;
+
+/// Generated terrain is grass down to this many meters under the surface and rock below
+const TOPSOIL_DEPTH: f32 = 3.0;
+/// Meters over which caves fade out at the top and bottom of their depth range, instead of ending in a flat cut
+const CAVE_FADE_DEPTH: f32 = 8.0;
+/// Noise difference from the threshold over which cave walls go from empty to solid, keeps the surface between voxels smooth
+const CAVE_WALL_SOFTNESS: f32 = 0.1;
+/// Directions to the edges of a cube, picked from by hash for gradient noise
+const NOISE_GRADIENTS: [Vec3; 12] = [
+    Vec3::new(1.0, 1.0, 0.0), Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(-1.0, -1.0, 0.0),
+    Vec3::new(1.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, 1.0), Vec3::new(1.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, -1.0),
+    Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, 1.0), Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, -1.0),
+];
+/// Corners of a marching cubes cell in the order the tables expect, same as the voxels shader
+const CELL_CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 1), IVec3::new(1, 0, 1), IVec3::new(1, 0, 0), IVec3::new(0, 0, 0),
+    IVec3::new(0, 1, 1), IVec3::new(1, 1, 1), IVec3::new(1, 1, 0), IVec3::new(0, 1, 0),
+];
+/// Corners at either end of each edge in the triangle table
+const CELL_EDGES: [(usize, usize); 12] = [(0, 1), (1, 2), (2, 3), (3, 0), (4, 5), (5, 6), (6, 7), (7, 4), (0, 4), (1, 5), (2, 6), (3, 7)];
+
 #[derive(Component)]
 pub struct Chunk {
     pub position: IVec3,
     pub voxels: Vec<Voxel>,
+    /// Found whenever the voxels are written, so uniform chunks can skip meshing
+    pub fill: ChunkFill,
+}
+
+/// Whether every voxel is on the same side of the surface
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChunkFill {
+    /// Has a surface somewhere inside, needs meshing
+    #[default]
+    Mixed,
+    Air,
+    Solid,
+}
+
+/// Counts read back from the atomics buffer the last time the chunk was polygonized
+#[derive(Component, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct ChunkMeshStats {
+    pub vertices: usize,
+    pub indices: usize,
+    pub triangles: usize,
+}
+
+/// A chunk mesh outside of any asset, as built on the CPU
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+/// Terrain is generated as it is at time zero instead of scrolling, so separate runs mesh the same chunks
+#[derive(Resource, Default)]
+pub struct FrozenTerrain;
+
+/// Trimesh for the chunk's latest mesh being cooked in the background along with how long that took,
+/// replacing it drops the old task which cancels it
+#[derive(Component)]
+pub struct ChunkColliderTask(Task<(SharedShape, Duration)>);
+
+#[derive(Debug, Error)]
+pub enum ChunkMeshError {
+    #[error("index count {0} is not a multiple of three")]
+    PartialTriangle(usize),
+    #[error("index {index} at {at} is out of range for {vertices} vertices")]
+    IndexOutOfRange { index: u32, at: usize, vertices: usize },
 }
 
+/// Solid floor along the bottom of the map bounds, so nothing can fall out of the world through a cave
+#[derive(Component)]
+pub struct Bedrock;
+
 #[derive(Component)]
 pub struct Map {
     pub chunks: HashMap<IVec3, Entity>,
@@ -42,11 +125,131 @@ impl Default for Map {
     }
 }
 
+impl Map {
+    /// What a point on the terrain is made of, anything that is not terrain like a hand placed collider gets the default
+    pub fn surface_at(&self, world: Vec3, chunk_query: &Query<&Chunk>) -> SurfaceType {
+        // A point on the surface sits between solid and air voxels, so go by the most solid corner of its cell
+        let cell = world.floor().as_ivec3();
+        (0..8)
+            .filter_map(|corner| {
+                let corner = cell + IVec3::new(corner & 1, (corner >> 1) & 1, corner >> 2);
+                let chunk = chunk_query.get(*self.chunks.get(&Chunk::position_of(corner.as_vec3()))?).ok()?;
+                chunk.voxel(corner - chunk.position * CHUNK_SZ as i32)
+            })
+            .filter(|voxel| voxel.density >= ISO_LEVEL)
+            .max_by(|a, b| a.density.total_cmp(&b.density))
+            .map_or_else(SurfaceType::default, |voxel| SurfaceType::from_material(voxel.material))
+    }
+}
+
+impl MapBounds {
+    pub fn contains(&self, position: IVec3) -> bool {
+        (self.min_chunk_y..=self.max_chunk_y).contains(&position.y)
+    }
+
+    /// How a chunk outside the bounds is filled in, none for ones inside that are generated
+    pub fn fill_outside(&self, position: IVec3) -> Option<ChunkFill> {
+        if position.y > self.max_chunk_y {
+            Some(ChunkFill::Air)
+        } else if position.y < self.min_chunk_y {
+            Some(ChunkFill::Solid)
+        } else {
+            None
+        }
+    }
+
+    /// World height of the top of the bedrock
+    pub fn floor(&self) -> f32 {
+        (self.min_chunk_y * CHUNK_SZ as i32) as f32
+    }
+}
+
+/// What something is made of, for picking sounds and effects to match
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SurfaceType {
+    Grass,
+    #[default]
+    Rock,
+    Metal,
+}
+
+impl SurfaceType {
+    /// Voxels store this as their material index
+    pub fn material(self) -> u32 {
+        self as u32
+    }
+
+    /// Unknown materials fall back to the default rather than failing
+    pub fn from_material(material: u32) -> Self {
+        match material {
+            0 => SurfaceType::Grass,
+            1 => SurfaceType::Rock,
+            2 => SurfaceType::Metal,
+            _ => SurfaceType::default(),
+        }
+    }
+}
+
 impl Chunk {
     pub fn new(position: IVec3) -> Self {
         let mut voxels = Vec::with_capacity(CHUNK_SZ_3);
         voxels.resize(CHUNK_SZ_3, Voxel::default());
-        Self { position, voxels }
+        Self { position, voxels, fill: ChunkFill::Air }
+    }
+
+    /// Call after editing voxels, a min and max density pass is far cheaper than meshing
+    pub fn update_fill(&mut self) {
+        let (min, max) = self.voxels.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), voxel| {
+            (min.min(voxel.density), max.max(voxel.density))
+        });
+        self.fill = if max < ISO_LEVEL {
+            ChunkFill::Air
+        } else if min >= ISO_LEVEL {
+            ChunkFill::Solid
+        } else {
+            ChunkFill::Mixed
+        };
+    }
+
+    /// World position of the chunk's minimum corner
+    pub fn origin(&self) -> Vec3 {
+        self.position.as_vec3() * CHUNK_SZ as f32
+    }
+
+    /// Position is local to the chunk, none when it is outside
+    pub fn voxel(&self, local: IVec3) -> Option<&Voxel> {
+        if local.cmplt(IVec3::ZERO).any() || local.cmpge(IVec3::splat(CHUNK_SZ as i32)).any() {
+            return None;
+        }
+        let local = local.as_uvec3();
+        self.voxels.get(local.x as usize + local.y as usize * CHUNK_SZ + local.z as usize * CHUNK_SZ_2)
+    }
+
+    /// Position of the chunk a world point falls in
+    pub fn position_of(world: Vec3) -> IVec3 {
+        (world / CHUNK_SZ as f32).floor().as_ivec3()
+    }
+}
+
+impl MeshData {
+    pub fn triangles(&self) -> usize {
+        self.indices.len() / 3
+    }
+}
+
+impl ChunkMeshStats {
+    pub fn new(vertices: usize, indices: usize) -> Self {
+        Self { vertices, indices, triangles: indices / 3 }
+    }
+
+    pub fn validate(&self, indices: &[u32]) -> Result<(), ChunkMeshError> {
+        if self.indices % 3 != 0 {
+            return Err(ChunkMeshError::PartialTriangle(self.indices));
+        }
+        match indices.iter().position(|&index| index as usize >= self.vertices) {
+            Some(at) => Err(ChunkMeshError::IndexOutOfRange { index: indices[at], at, vertices: self.vertices }),
+            None => Ok(()),
+        }
     }
 }
 
@@ -62,6 +265,8 @@ impl Chunk {
 pub struct Voxel {
     flags: u32,
     density: f32,
+    /// Index of a [`SurfaceType`], not used for meshing
+    material: u32,
 }
 
 #[derive(Resource)]
@@ -74,18 +279,26 @@ pub struct VoxelsPipeline {
 pub struct VoxelBuffers {
     // Place edge table and triangle table in uniform buffer
     // They are too large to have inline in the shader
-    edge_table: Buffer,
-    tri_table: Buffer,
+    edge_table: BufVec<u32>,
+    tri_table: BufVec<[i32; 16]>,
     points: BufVec<Vec2>,
     heights: BufVec<f32>,
-    voxels: Buffer,
-    voxels_staging: Buffer,
-    vertices: BufVec<Vec4>,
-    normals: BufVec<Vec4>,
-    uvs: BufVec<Vec2>,
-    indices: BufVec<u32>,
+    voxels: BufVec<Voxel>,
+    // Outputs alternate between chunks, one is read back while the next chunk is dispatched into the other
+    vertices: DoubleBuffered<Vec4>,
+    normals: DoubleBuffered<Vec4>,
+    uvs: DoubleBuffered<Vec2>,
+    indices: DoubleBuffered<u32>,
     atomics: BufVec<u32>,
-    atomics_staging: Buffer,
+}
+
+/// Chunk dispatched into the previous outputs, its mesh is still being read back
+struct MeshReadback {
+    entity: Entity,
+    mesh: Handle<Mesh>,
+    position: IVec3,
+    stats: ChunkMeshStats,
+    start: Instant,
 }
 
 struct BindingGroups {
@@ -98,48 +311,26 @@ pub struct VoxelsPlugin;
 impl Plugin for VoxelsPlugin {
     fn build(&self, app: &mut App) {
         app
+            .init_resource::<MeshingProfiler>()
             .add_systems(PreUpdate, (
                 init_pipeline_system.run_if(not(resource_exists::<VoxelsPipeline>())),
                 voxel_polygonize_system.run_if(resource_exists::<VoxelsPipeline>()),
-            ));
+            ))
+            .add_systems(Update, (sync_added_chunks_system, dump_meshing_profile_sys, export_chunk_mesh_sys, chunk_visibility_sys, apply_chunk_colliders_sys));
     }
 }
 
 fn init_pipeline_system(mut commands: Commands, render_device: Res<RenderDevice>) {
-    let edge_table = render_device.create_buffer_with_data(&BufferInitDescriptor {
-        label: Some("edge table buffer"),
-        contents: cast_slice(EDGE_TABLE),
-        usage: BufferUsages::STORAGE,
-    });
-    let tri_table = render_device.create_buffer_with_data(&BufferInitDescriptor {
-        label: Some("tri table buffer"),
-        contents: cast_slice(TRI_TABLE),
-        usage: BufferUsages::STORAGE,
-    });
-    let points: BufVec<Vec2> = BufVec::with_capacity(false, CHUNK_SZ_2, render_device.as_ref());
-    let heights: BufVec<f32> = BufVec::with_capacity(true, CHUNK_SZ_2, render_device.as_ref());
-    let voxels = render_device.create_buffer(&BufferDescriptor {
-        label: Some("voxels buffer"),
-        size: (CHUNK_SZ_3 * size_of::<Voxel>()) as BufferAddress,
-        usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-        mapped_at_creation: false,
-    });
-    let voxels_staging = render_device.create_buffer(&BufferDescriptor {
-        label: Some("voxels staging buffer"),
-        size: (CHUNK_SZ_3 * size_of::<Voxel>()) as BufferAddress,
-        usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
-        mapped_at_creation: false,
-    });
-    let vertices: BufVec<Vec4> = BufVec::with_capacity(true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
-    let uvs: BufVec<Vec2> = BufVec::with_capacity(true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
-    let normals: BufVec<Vec4> = BufVec::with_capacity(true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
-    let indices: BufVec<u32> = BufVec::with_capacity(true, CHUNK_SZ_3 * 6 * 6, render_device.as_ref());
-    let atomics: BufVec<u32> = BufVec::with_capacity(true, 2, render_device.as_ref());
-    let atomics_staging = render_device.create_buffer_with_data(&BufferInitDescriptor {
-        label: Some("atomics staging buffer"),
-        contents: cast_slice(&[0u32, 0u32]),
-        usage: BufferUsages::COPY_SRC,
-    });
+    let edge_table = BufVec::from_slice_labeled(Some("edge table"), false, EDGE_TABLE, render_device.as_ref());
+    let tri_table = BufVec::from_slice_labeled(Some("tri table"), false, TRI_TABLE, render_device.as_ref());
+    let points: BufVec<Vec2> = BufVec::with_capacity_labeled(Some("points"), false, CHUNK_SZ_2, render_device.as_ref());
+    let heights: BufVec<f32> = BufVec::with_capacity_labeled(Some("heights"), true, CHUNK_SZ_2, render_device.as_ref());
+    let voxels: BufVec<Voxel> = BufVec::with_capacity_labeled(Some("voxels"), false, CHUNK_SZ_3, render_device.as_ref());
+    let vertices = DoubleBuffered::with_capacity_labeled(Some("vertices"), true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
+    let uvs = DoubleBuffered::with_capacity_labeled(Some("uvs"), true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
+    let normals = DoubleBuffered::with_capacity_labeled(Some("normals"), true, CHUNK_SZ_3 * 4 * 6, render_device.as_ref());
+    let indices = DoubleBuffered::with_capacity_labeled(Some("indices"), true, CHUNK_SZ_3 * 6 * 6, render_device.as_ref());
+    let atomics: BufVec<u32> = BufVec::with_capacity_labeled(Some("atomics"), true, 2, render_device.as_ref());
 
     // let simplex_shader = asset_server.load("shaders/simplex.wgsl");
     let shader_source = include_str!("../../assets/shaders/simplex.wgsl");
@@ -168,11 +359,11 @@ fn init_pipeline_system(mut commands: Commands, render_device: Res<RenderDevice>
         entry_point: "main",
     });
 
-    commands.insert_resource(VoxelBuffers { edge_table, tri_table, points, heights, voxels, voxels_staging, vertices, normals, uvs, indices, atomics, atomics_staging });
+    commands.insert_resource(VoxelBuffers { edge_table, tri_table, points, heights, voxels, vertices, normals, uvs, indices, atomics });
     commands.insert_resource(VoxelsPipeline { simplex_pipeline, voxels_pipeline });
 }
 
-pub fn _sync_added_chunks_system(
+pub fn sync_added_chunks_system(
     added_chunk_query: Query<(Entity, &Chunk), Added<Chunk>>,
     mut map_query: Query<&mut Map>,
 ) {
@@ -189,24 +380,34 @@ pub fn voxel_polygonize_system(
     mut meshes: ResMut<Assets<Mesh>>,
     mut buffers: ResMut<VoxelBuffers>,
     time: Res<Time>,
+    frozen: Option<Res<FrozenTerrain>>,
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     pipeline: Res<VoxelsPipeline>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut profiler: ResMut<MeshingProfiler>,
 ) {
-    // let now = std::time::Instant::now();
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let validate = config.validate_chunk_meshes;
+    let profile = config.profile_chunk_meshing;
+    let crease_cos = config.normal_crease_angle_degrees.to_radians().cos();
 
+    let mut readback: Option<MeshReadback> = None;
     for (entity, mesh, mut chunk) in query.iter_mut() {
-        buffers.atomics.clear();
-        buffers.atomics.push(0);
-        buffers.atomics.push(0);
+        let start = Instant::now();
+
+        // Nothing to generate outside the bounds, just open sky above and bedrock below
+        let outside_fill = config.map_bounds.fill_outside(chunk.position);
+        if let Some(fill) = outside_fill {
+            let density = if fill == ChunkFill::Solid { 1.0 } else { 0.0 };
+            chunk.voxels.fill(Voxel { flags: 0, density, material: SurfaceType::Rock.material() });
+            chunk.fill = fill;
+        }
 
-        let time = time.elapsed().as_secs_f32();
+        let time = if frozen.is_some() { 0.0 } else { time.elapsed().as_secs_f32() };
         buffers.points.clear();
-        for x in 0..CHUNK_SZ {
-            for y in 0..CHUNK_SZ {
-                buffers.points.push(0.05 * Vec2::new(x as f32 + time, y as f32 + time));
-            }
-        }
+        buffers.points.extend(height_sample_points(time));
 
         let binding_groups = BindingGroups {
             simplex: render_device.create_bind_group(
@@ -221,19 +422,19 @@ pub fn voxel_polygonize_system(
                 "voxels binding",
                 &pipeline.voxels_pipeline.get_bind_group_layout(0).into(),
                 &BindGroupEntries::sequential((
-                    buffers.edge_table.as_entire_binding(),
-                    buffers.tri_table.as_entire_binding(),
-                    buffers.voxels.as_entire_binding(),
+                    buffers.edge_table.buffer().as_entire_binding(),
+                    buffers.tri_table.buffer().as_entire_binding(),
+                    buffers.voxels.buffer().as_entire_binding(),
                     buffers.atomics.buffer().as_entire_binding(),
-                    buffers.vertices.buffer().as_entire_binding(),
-                    buffers.normals.buffer().as_entire_binding(),
-                    buffers.indices.buffer().as_entire_binding(),
-                    buffers.uvs.buffer().as_entire_binding(),
+                    buffers.vertices.current().buffer().as_entire_binding(),
+                    buffers.normals.current().buffer().as_entire_binding(),
+                    buffers.indices.current().buffer().as_entire_binding(),
+                    buffers.uvs.current().buffer().as_entire_binding(),
                 )),
             ),
         };
 
-        if !buffers.points.is_empty() {
+        if outside_fill.is_none() && !buffers.points.is_empty() {
             let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor { label: Some("simplex command encoder") });
             buffers.points.encode_write(render_queue.as_ref(), &mut command_encoder);
             {
@@ -248,35 +449,32 @@ pub fn voxel_polygonize_system(
             render_device.poll(Wait);
             buffers.heights.read_and_unmap_buffer(CHUNK_SZ_2);
 
-            for z in 0..CHUNK_SZ {
-                for y in 0..CHUNK_SZ {
-                    for x in 0..CHUNK_SZ {
-                        let noise01 = (buffers.heights.as_slice()[x + z * CHUNK_SZ] + 1.0) * 0.5;
-                        let height = noise01 * 4.0 + 8.0 - (y as f32);
-                        let mut density = 0.0;
-
-                        if height > 1.0 {
-                            density = 1.0;
-                        } else if height > 0.0 {
-                            density = height;
-                        }
-                        // voxels.0[x + y * CHUNK_SZ + z * CHUNK_SZ_2] = Voxel {
-                        //     flags: if z == (noise01 * 4.0) as usize { 1 } else { 0 },
-                        //     density: 0.0,
-                        // };
-                        chunk.voxels[x + y * CHUNK_SZ + z * CHUNK_SZ_2] = Voxel {
-                            flags: 0,
-                            density,
-                        };
-                    }
+            fill_chunk_voxels(&mut chunk, buffers.heights.as_slice(), &config.caves);
+        }
+
+        if chunk.fill != ChunkFill::Mixed {
+            // Air has nothing to show or collide with. Solid would only mesh the shell around the chunk's bounds,
+            // which is never seen from outside, so a box is enough to keep anything from falling in
+            clear_mesh(meshes.get_mut(mesh).unwrap());
+            commands.entity(entity).insert(ChunkMeshStats::default()).remove::<(Aabb, ChunkColliderTask)>();
+            match chunk.fill {
+                ChunkFill::Solid => {
+                    let half = CHUNK_SZ as f32 * 0.5;
+                    commands.entity(entity).insert(Collider::compound(vec![(Vec3::splat(half), Quat::IDENTITY, Collider::cuboid(half, half, half))]));
                 }
+                _ => { commands.entity(entity).remove::<Collider>(); }
             }
+            if profile { profiler.skipped_chunks += 1; }
+            continue;
         }
+        if profile { profiler.chunks += 1; }
 
         let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor { label: Some("voxel 1 command encoder") });
-        render_queue.write_buffer(&buffers.voxels_staging, 0, &cast_slice(&chunk.voxels)[..]);
-        command_encoder.copy_buffer_to_buffer(&buffers.voxels_staging, 0, &buffers.voxels, 0, (CHUNK_SZ_3 * size_of::<Voxel>()) as BufferAddress);
-        command_encoder.copy_buffer_to_buffer(&buffers.atomics_staging, 0, &buffers.atomics.buffer, 0, (2 * size_of::<u32>()) as BufferAddress);
+        buffers.voxels.clear();
+        buffers.voxels.extend_from_slice(&chunk.voxels);
+        buffers.voxels.encode_write(render_queue.as_ref(), &mut command_encoder);
+        // Counters start from zero for every dispatch
+        buffers.atomics.encode_clear(&mut command_encoder);
         {
             let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor::default());
             pass.set_pipeline(&pipeline.voxels_pipeline);
@@ -289,61 +487,501 @@ pub fn voxel_polygonize_system(
         buffers.atomics.map_buffer(2);
         render_device.poll(Wait);
         buffers.atomics.read_and_unmap_buffer(2);
+        // That wait also landed the previous chunk's readback, so it is finished while this chunk's outputs are fresh
+        if let Some(readback) = readback.take() {
+            buffers.finish_readback(readback, &mut meshes, &mut commands, crease_cos, validate, profile.then(|| profiler.as_mut()));
+        }
         let vertex_count = buffers.atomics.as_slice()[0] as usize;
         let index_count = buffers.atomics.as_slice()[1] as usize;
+        let stats = ChunkMeshStats::new(vertex_count, index_count);
+        commands.entity(entity).insert(stats);
+        if profile { profiler.lap(MeshingStage::Dispatch, start); }
 
         if vertex_count == 0 {
+            clear_mesh(meshes.get_mut(mesh).unwrap());
+            commands.entity(entity).remove::<(Collider, Aabb, ChunkColliderTask)>();
             continue;
         }
 
         let mut command_encoder = render_device.create_command_encoder(&CommandEncoderDescriptor { label: Some("voxel 2 command encoder") });
-        buffers.vertices.encode_read(vertex_count, &mut command_encoder);
-        buffers.normals.encode_read(vertex_count, &mut command_encoder);
-        buffers.uvs.encode_read(vertex_count, &mut command_encoder);
-        buffers.indices.encode_read(index_count, &mut command_encoder);
+        buffers.vertices.current_mut().encode_read(vertex_count, &mut command_encoder);
+        buffers.normals.current_mut().encode_read(vertex_count, &mut command_encoder);
+        buffers.uvs.current_mut().encode_read(vertex_count, &mut command_encoder);
+        buffers.indices.current_mut().encode_read(index_count, &mut command_encoder);
         render_queue.submit(once(command_encoder.finish()));
-        buffers.vertices.map_buffer(vertex_count);
-        buffers.normals.map_buffer(vertex_count);
-        buffers.uvs.map_buffer(vertex_count);
-        buffers.indices.map_buffer(index_count);
+        if let Err(err) = buffers.request_mesh_read(vertex_count, index_count) {
+            error!("Could not read back the mesh of chunk {}: {}", chunk.position, err);
+            continue;
+        }
+        readback = Some(MeshReadback { entity, mesh: mesh.clone(), position: chunk.position, stats, start });
+        buffers.swap_outputs();
+    }
+    // Nothing is dispatched after the last chunk, so its readback is waited on here
+    if let Some(readback) = readback {
         render_device.poll(Wait);
+        buffers.finish_readback(readback, &mut meshes, &mut commands, crease_cos, validate, profile.then(|| profiler.as_mut()));
+    }
+}
 
-        buffers.vertices.read_and_unmap_buffer(vertex_count);
-        buffers.normals.read_and_unmap_buffer(vertex_count);
-        buffers.uvs.read_and_unmap_buffer(vertex_count);
-        buffers.indices.read_and_unmap_buffer(index_count);
+impl VoxelBuffers {
+    /// Maps the current outputs without waiting, they are taken with `finish_readback` once swapped to previous
+    fn request_mesh_read(&mut self, vertex_count: usize, index_count: usize) -> Result<(), BufVecError> {
+        self.vertices.current_mut().request_read(vertex_count)?;
+        self.normals.current_mut().request_read(vertex_count)?;
+        self.uvs.current_mut().request_read(vertex_count)?;
+        self.indices.current_mut().request_read(index_count)
+    }
 
-        let mesh = meshes.get_mut(mesh).unwrap();
+    fn swap_outputs(&mut self) {
+        self.vertices.swap();
+        self.normals.swap();
+        self.uvs.swap();
+        self.indices.swap();
+    }
 
-        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
-            indices.resize(index_count, 0);
-            indices.copy_from_slice(buffers.indices.as_slice());
+    /// Copies a chunk's mesh out of the previous outputs and hands it off to be cooked into a collider.
+    /// The device has to have been waited on since the read was requested
+    fn finish_readback(
+        &mut self, readback: MeshReadback, meshes: &mut Assets<Mesh>, commands: &mut Commands, crease_cos: f32, validate: bool,
+        mut profiler: Option<&mut MeshingProfiler>,
+    ) {
+        let MeshReadback { entity, mesh, position, stats, start } = readback;
+        let mut lap = Instant::now();
+        // Every read is taken even after one fails so none are left mapped
+        let read = take_read(self.vertices.previous_mut())
+            & take_read(self.normals.previous_mut())
+            & take_read(self.uvs.previous_mut())
+            & take_read(self.indices.previous_mut());
+        if !read { return; }
+        let (vertices, normals, uvs, indices) = (self.vertices.previous(), self.normals.previous(), self.uvs.previous(), self.indices.previous());
+        if let Some(profiler) = profiler.as_deref_mut() { lap = profiler.lap(MeshingStage::Readback, lap); }
+
+        if validate {
+            if let Err(err) = stats.validate(indices.as_slice()) {
+                error!("Chunk {} produced a malformed mesh: {}", position, err);
+                return;
+            }
+        }
+
+        let Some(mesh) = meshes.get_mut(&mesh) else { return; };
+
+        if let Some(Indices::U32(mesh_indices)) = mesh.indices_mut() {
+            mesh_indices.clear();
+            mesh_indices.extend_from_slice(indices.as_slice());
+        }
+        if let Some(VertexAttributeValues::Float32x3(mesh_vertices)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
+            mesh_vertices.clear();
+            mesh_vertices.reserve(vertices.len());
+            for v in vertices.iter() {
+                mesh_vertices.push([v[0], v[1], v[2]]);
+            }
+        }
+        if let Some(VertexAttributeValues::Float32x3(mesh_normals)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
+            mesh_normals.clear();
+            mesh_normals.reserve(vertices.len());
+            smooth_normals(vertices.as_slice(), normals.as_slice(), crease_cos, mesh_normals);
         }
-        if let Some(VertexAttributeValues::Float32x3(vertices)) = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) {
-            vertices.clear();
-            vertices.reserve(vertex_count);
-            for v in buffers.vertices.iter() {
-                vertices.push([v[0], v[1], v[2]]);
+        if let Some(VertexAttributeValues::Float32x2(mesh_uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+            mesh_uvs.clear();
+            mesh_uvs.reserve(vertices.len());
+            for v in uvs.iter() {
+                mesh_uvs.push((*v).into());
             }
         }
-        if let Some(VertexAttributeValues::Float32x3(normals)) = mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) {
-            normals.clear();
-            normals.reserve(vertex_count);
-            for v in buffers.normals.iter() {
-                normals.push([v[0], v[1], v[2]]);
+
+        // Bevy only computes bounds for meshes without any, so replace them here or a chunk that grew gets culled too early
+        if let Some(aabb) = mesh.compute_aabb() {
+            commands.entity(entity).insert(aabb);
+        }
+
+        if let Some(profiler) = profiler.as_deref_mut() { profiler.lap(MeshingStage::MeshApply, lap); }
+
+        // Building the trimesh acceleration structure is the slow part, the old collider stays until this is applied
+        let collider_vertices = vertices.iter().map(|v| Vec3::new(v[0], v[1], v[2]).into()).collect();
+        let triangles = indices.as_slice().chunks_exact(3).map(|tri| [tri[0], tri[1], tri[2]]).collect();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            let cook_start = Instant::now();
+            (SharedShape::trimesh(collider_vertices, triangles), cook_start.elapsed())
+        });
+        commands.entity(entity).insert(ChunkColliderTask(task));
+
+        if profiler.is_some() {
+            debug!("Meshed chunk {} into {} triangles in {:.2?}", position, stats.triangles, start.elapsed());
+        }
+    }
+}
+
+/// Takes a requested read, logging why it could not be
+fn take_read<T: Pod>(buffer: &mut BufVec<T>) -> bool {
+    let label = buffer.label().unwrap_or("buffer");
+    match buffer.try_read() {
+        Some(Ok(_)) => true,
+        Some(Err(err)) => {
+            error!("Could not read back {}: {}", label, err);
+            false
+        }
+        None => {
+            error!("Read back of {} had not landed after waiting on the device", label);
+            false
+        }
+    }
+}
+
+fn height_sample_points(time: f32) -> impl Iterator<Item=Vec2> {
+    (0..CHUNK_SZ).flat_map(move |x| (0..CHUNK_SZ).map(move |y| 0.05 * Vec2::new(x as f32 + time, y as f32 + time)))
+}
+
+/// Heights are indexed by x and z within the chunk, as the simplex shader writes them
+fn fill_chunk_voxels(chunk: &mut Chunk, heights: &[f32], caves: &CaveConfig) {
+    let origin = chunk.position * CHUNK_SZ as i32;
+    for z in 0..CHUNK_SZ {
+        for y in 0..CHUNK_SZ {
+            for x in 0..CHUNK_SZ {
+                let world = origin + IVec3::new(x as i32, y as i32, z as i32);
+                let noise01 = (heights[x + z * CHUNK_SZ] + 1.0) * 0.5;
+                let height = noise01 * 4.0 + 8.0 - world.y as f32;
+                let mut density = 0.0;
+
+                if height > 1.0 {
+                    density = 1.0;
+                } else if height > 0.0 {
+                    density = height;
+                }
+                if caves.enabled && density > 0.0 {
+                    density = density.min(cave_density(caves, world.as_vec3()));
+                }
+                // voxels.0[x + y * CHUNK_SZ + z * CHUNK_SZ_2] = Voxel {
+                //     flags: if z == (noise01 * 4.0) as usize { 1 } else { 0 },
+                //     density: 0.0,
+                // };
+                let surface = if height > TOPSOIL_DEPTH { SurfaceType::Rock } else { SurfaceType::Grass };
+                chunk.voxels[x + y * CHUNK_SZ + z * CHUNK_SZ_2] = Voxel {
+                    flags: 0,
+                    density,
+                    material: surface.material(),
+                };
             }
         }
-        if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
-            uvs.clear();
-            uvs.reserve(vertex_count);
-            for v in buffers.uvs.iter() {
-                uvs.push((*v).into());
+    }
+    chunk.update_fill();
+}
+
+/// Same as the simplex shader, so chunks generated without a GPU come out the same
+pub fn simplex_noise_2d(v: Vec2) -> f32 {
+    const C: Vec4 = Vec4::new(0.211_324_87, 0.366_025_42, -0.577_350_26, 0.024_390_243);
+    let permute = |x: Vec3| ((x * 34.0 + 1.0) * x) % 289.0;
+    let i = (v + v.dot(Vec2::splat(C.y))).floor();
+    let x0 = v - i + i.dot(Vec2::splat(C.x));
+    let i1 = if x0.x > x0.y { Vec2::Y } else { Vec2::X };
+    let x12 = Vec4::new(x0.x + C.x - i1.x, x0.y + C.x - i1.y, x0.x + C.z, x0.y + C.z);
+    let i = i % 289.0;
+    let p = permute(permute(Vec3::splat(i.y) + Vec3::new(0.0, i1.y, 1.0)) + Vec3::splat(i.x) + Vec3::new(0.0, i1.x, 1.0));
+    let x12_xy = Vec2::new(x12.x, x12.y);
+    let x12_zw = Vec2::new(x12.z, x12.w);
+    let mut m = (Vec3::splat(0.5) - Vec3::new(x0.dot(x0), x12_xy.dot(x12_xy), x12_zw.dot(x12_zw))).max(Vec3::ZERO);
+    m = m * m;
+    m = m * m;
+    let x = 2.0 * (p * C.w).fract() - 1.0;
+    let h = x.abs() - 0.5;
+    let a0 = x - (x + 0.5).floor();
+    m *= Vec3::splat(1.792_842_9) - 0.853_734_7 * (a0 * a0 + h * h);
+    let g = Vec3::new(a0.x * x0.x + h.x * x0.y, a0.y * x12.x + h.y * x12.y, a0.z * x12.z + h.z * x12.w);
+    130.0 * m.dot(g)
+}
+
+/// Marching cubes over the chunk's smooth voxels, the same surface the voxels shader builds
+pub fn mesh_chunk_cpu(chunk: &Chunk, crease_cos: f32) -> MeshData {
+    let mut vertices = Vec::new();
+    let mut face_normals = Vec::new();
+    for z in 0..CHUNK_SZ as i32 {
+        for y in 0..CHUNK_SZ as i32 {
+            for x in 0..CHUNK_SZ as i32 {
+                let cell = IVec3::new(x, y, z);
+                // Past the edge of the chunk counts as air, like in the shader
+                let densities = CELL_CORNERS.map(|corner| chunk.voxel(cell + corner).map_or(0.0, |voxel| voxel.density));
+                let cube_idx = (0..8).filter(|&corner| densities[corner] < ISO_LEVEL).fold(0, |idx, corner| idx | 1 << corner);
+                if cube_idx == 0x00 || cube_idx == 0xff { continue; }
+
+                let edge_vertex = |edge: i32| {
+                    let (a, b) = CELL_EDGES[edge as usize];
+                    let (from, to) = ((cell + CELL_CORNERS[a]).as_vec3(), (cell + CELL_CORNERS[b]).as_vec3());
+                    from + (ISO_LEVEL - densities[a]) / (densities[b] - densities[a]) * (to - from)
+                };
+                for tri in TRI_TABLE[cube_idx].chunks_exact(3).take_while(|tri| tri[0] != -1) {
+                    let [v0, v1, v2] = [edge_vertex(tri[0]), edge_vertex(tri[1]), edge_vertex(tri[2])];
+                    let normal = (v0 - v1).cross(v0 - v2);
+                    vertices.extend([v0, v1, v2].map(|v| v.extend(0.0)));
+                    face_normals.extend([normal.extend(0.0); 3]);
+                }
             }
         }
+    }
+    let mut normals = Vec::with_capacity(vertices.len());
+    smooth_normals(&vertices, &face_normals, crease_cos, &mut normals);
+    MeshData {
+        positions: vertices.iter().map(|v| v.truncate().into()).collect(),
+        normals,
+        indices: (0..vertices.len() as u32).collect(),
+    }
+}
+
+/// Generates and meshes a chunk entirely on the CPU with terrain frozen at time zero,
+/// for benchmarks and tools that have neither a GPU nor a Bevy schedule
+pub fn generate_chunk_mesh(position: IVec3, config: &Config) -> MeshData {
+    // Outside the bounds is uniform, so there is never anything to mesh
+    if config.map_bounds.fill_outside(position).is_some() { return MeshData::default(); }
+    let mut chunk = Chunk::new(position);
+    let heights: Vec<f32> = height_sample_points(0.0).map(simplex_noise_2d).collect();
+    fill_chunk_voxels(&mut chunk, &heights, &config.caves);
+    if chunk.fill != ChunkFill::Mixed { return MeshData::default(); }
+    mesh_chunk_cpu(&chunk, config.normal_crease_angle_degrees.to_radians().cos())
+}
+
+/// Mixes the lattice point into well spread bits, the same point always gives the same hash
+fn hash_lattice(point: IVec3) -> u32 {
+    let mut hash = (point.x as u32).wrapping_mul(0x8da6_b343) ^ (point.y as u32).wrapping_mul(0xd816_3841) ^ (point.z as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    hash ^ (hash >> 15)
+}
+
+/// Perlin style gradient noise, roughly between -1 and 1. It only depends on the position,
+/// so neighboring chunks sample one continuous field and line up at their borders
+pub fn gradient_noise(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let local = point - cell;
+    let cell = cell.as_ivec3();
+    let fade = local * local * local * (local * (local * 6.0 - 15.0) + 10.0);
+    let corner = |x: i32, y: i32, z: i32| {
+        let offset = IVec3::new(x, y, z);
+        NOISE_GRADIENTS[hash_lattice(cell + offset) as usize % NOISE_GRADIENTS.len()].dot(local - offset.as_vec3())
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let y0 = lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), fade.x), lerp(corner(0, 1, 0), corner(1, 1, 0), fade.x), fade.y);
+    let y1 = lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), fade.x), lerp(corner(0, 1, 1), corner(1, 1, 1), fade.x), fade.y);
+    lerp(y0, y1, fade.z)
+}
+
+/// Most a point can be filled in once caves are carved out, solid outside the configured depths
+fn cave_density(caves: &CaveConfig, world: Vec3) -> f32 {
+    let depth = -world.y;
+    let fade = ((depth - caves.min_depth).min(caves.max_depth - depth) / CAVE_FADE_DEPTH).clamp(0.0, 1.0);
+    if fade <= 0.0 { return 1.0; }
+    // Raised toward the top of the noise range near the ends so less and less is carved there
+    let threshold = caves.threshold + (1.0 - caves.threshold) * (1.0 - fade);
+    let noise = gradient_noise(world * caves.frequency);
+    ((threshold - noise) / CAVE_WALL_SOFTNESS + ISO_LEVEL).clamp(0.0, 1.0)
+}
+
+/// Swaps in at most the configured number of finished colliders per frame, nearest our camera first,
+/// so a burst of edits or newly loaded chunks does not land all at once
+pub fn apply_chunk_colliders_sys(
+    mut commands: Commands,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    mut profiler: ResMut<MeshingProfiler>,
+    local_query: Query<&LogicalPlayer, With<LocalPlayer>>,
+    camera_query: Query<(&GlobalTransform, &RenderPlayer)>,
+    mut task_query: Query<(Entity, &Chunk, &mut ChunkColliderTask)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    // Spectating or the minimap can add cameras, the chunks that matter most are the ones around us
+    let local_id = local_query.iter().next().map(|player| player.0);
+    let eye = camera_query.iter()
+        .find(|(_, render_player)| Some(render_player.0) == local_id)
+        .map_or(Vec3::ZERO, |(transform, _)| transform.translation());
+    let mut pending: Vec<_> = task_query.iter_mut().collect();
+    pending.sort_unstable_by(|(_, a, _), (_, b, _)| {
+        let center = |chunk: &Chunk| (chunk.position * CHUNK_SZ as i32).as_vec3() + Vec3::splat(CHUNK_SZ as f32 * 0.5);
+        center(a).distance_squared(eye).total_cmp(&center(b).distance_squared(eye))
+    });
+    let mut applied = 0;
+    for (entity, _, mut task) in pending {
+        if applied == config.chunk_collider_budget { break; }
+        if !task.0.is_finished() { continue; }
+        let (shape, cook_time) = block_on(&mut task.0);
+        if config.profile_chunk_meshing { profiler.record(MeshingStage::ColliderCook, cook_time); }
+        commands.entity(entity).insert(Collider::from(shape)).remove::<ChunkColliderTask>();
+        applied += 1;
+    }
+}
+
+/// Hides chunks past the render distance and ones completely hidden behind solid chunks nearer the camera.
+/// Frustum culling then handles whatever is left using the mesh bounds
+pub fn chunk_visibility_sys(
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    camera_query: Query<&GlobalTransform, With<Camera3d>>,
+    mut chunk_query: Query<(&Chunk, &mut Visibility)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let Some(eye) = camera_query.iter().next().map(GlobalTransform::translation) else { return; };
+    let eye_chunk = Chunk::position_of(eye);
+    let fills: HashMap<IVec3, ChunkFill> = chunk_query.iter().map(|(chunk, _)| (chunk.position, chunk.fill)).collect();
+
+    // Walks towards the point in half chunk steps, small gaps at corners are fine since this only ever hides more conservatively
+    let is_blocked = |target_chunk: IVec3, point: Vec3| {
+        let steps = (eye.distance(point) / (CHUNK_SZ as f32 * 0.5)).ceil() as usize;
+        (1..steps).any(|step| {
+            let position = Chunk::position_of(eye.lerp(point, step as f32 / steps as f32));
+            position != eye_chunk && position != target_chunk && fills.get(&position) == Some(&ChunkFill::Solid)
+        })
+    };
+
+    for (chunk, mut visibility) in chunk_query.iter_mut() {
+        let min = (chunk.position * CHUNK_SZ as i32).as_vec3();
+        let center = min + Vec3::splat(CHUNK_SZ as f32 * 0.5);
+        let is_far = config.chunk_render_distance > 0.0 && eye.distance(center) > config.chunk_render_distance;
+        // Only hidden when the center and every corner are blocked, so partly visible chunks stay drawn
+        let is_occluded = chunk.position != eye_chunk && once(center)
+            .chain((0..8).map(|corner| min + IVec3::new(corner & 1, (corner >> 1) & 1, (corner >> 2) & 1).as_vec3() * CHUNK_SZ as f32))
+            .all(|point| is_blocked(chunk.position, point));
+        let wanted = if chunk.fill == ChunkFill::Air || is_far || is_occluded { Visibility::Hidden } else { Visibility::Inherited };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
+
+/// Writes the chunk we are standing in next to the game, named by its position
+fn export_chunk_mesh_sys(
+    input: BoundInput,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    meshes: Res<Assets<Mesh>>,
+    player_query: Query<&Transform, With<LocalPlayer>>,
+    chunk_query: Query<(&Chunk, &GlobalTransform, &Handle<Mesh>)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    if !config.debug || !input.just_pressed(input.bindings.export_chunk_mesh) { return; }
+    let Some(player_transform) = player_query.iter().next() else { return; };
+
+    let position = Chunk::position_of(player_transform.translation);
+    let Some((chunk, transform, mesh)) = chunk_query.iter().find(|(chunk, ..)| chunk.position == position) else {
+        warn!("No chunk at {} to export", position);
+        return;
+    };
+    let Some(mesh) = meshes.get(mesh) else { return; };
+    let path = format!("chunk_{}_{}_{}.obj", position.x, position.y, position.z);
+    match export_chunk_mesh(chunk, transform, mesh, Path::new(&path)) {
+        Ok(()) => info!("Wrote chunk {} to {}", position, path),
+        Err(err) => error!("Could not write chunk {} to {}: {}", position, path, err),
+    }
+}
+
+pub fn export_chunk_mesh(chunk: &Chunk, transform: &GlobalTransform, mesh: &Mesh, path: &Path) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "o chunk_{}_{}_{}", chunk.position.x, chunk.position.y, chunk.position.z)?;
+    write_chunk_obj(&mut out, transform, mesh, 1)?;
+    out.flush()
+}
 
-        // TODO:perf inefficient
-        commands.entity(entity).insert(Collider::from_bevy_mesh(mesh, &ComputedColliderShape::TriMesh).unwrap());
+/// Appends a chunk's mesh in world space to an OBJ, returning how many vertices it added so the next can follow.
+/// Winding is kept as rendered, which is counter-clockwise facing out like OBJ expects.
+/// Empty chunks add nothing, which is still a valid file
+pub fn write_chunk_obj(out: &mut impl Write, transform: &GlobalTransform, mesh: &Mesh, first_vertex: usize) -> io::Result<usize> {
+    let (
+        Some(VertexAttributeValues::Float32x3(positions)),
+        Some(VertexAttributeValues::Float32x3(normals)),
+        Some(VertexAttributeValues::Float32x2(uvs)),
+        Some(Indices::U32(indices)),
+    ) = (
+        mesh.attribute(Mesh::ATTRIBUTE_POSITION),
+        mesh.attribute(Mesh::ATTRIBUTE_NORMAL),
+        mesh.attribute(Mesh::ATTRIBUTE_UV_0),
+        mesh.indices(),
+    ) else { return Ok(0); };
+
+    let (_, rotation, _) = transform.to_scale_rotation_translation();
+    for &position in positions {
+        let position = transform.transform_point(position.into());
+        writeln!(out, "v {} {} {}", position.x, position.y, position.z)?;
     }
+    for &normal in normals {
+        let normal = rotation * Vec3::from(normal);
+        writeln!(out, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+    }
+    for uv in uvs {
+        writeln!(out, "vt {} {}", uv[0], uv[1])?;
+    }
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| index as usize + first_vertex);
+        writeln!(out, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+    }
+    Ok(positions.len())
+}
 
-    // println!("Elapsed: {:.2?}", now.elapsed());
-}
\ No newline at end of file
+/// Empty with every attribute meshing writes, chunks need their own since each is written in place
+pub fn new_chunk_mesh() -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_indices(Some(Indices::U32(Vec::with_capacity(4096))));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, VertexAttributeValues::Float32x3(Vec::with_capacity(4096)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, VertexAttributeValues::Float32x3(Vec::with_capacity(4096)));
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, VertexAttributeValues::Float32x2(Vec::with_capacity(4096)));
+    mesh
+}
+
+fn clear_mesh(mesh: &mut Mesh) {
+    if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+        indices.clear();
+    }
+    for attribute in [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_NORMAL] {
+        if let Some(VertexAttributeValues::Float32x3(values)) = mesh.attribute_mut(attribute) {
+            values.clear();
+        }
+    }
+    if let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0) {
+        uvs.clear();
+    }
+}
+
+/// Each vertex comes out of the shader with its own face normal. Average them with the other faces
+/// touching the same point, skipping any past the crease angle so sharp features like cliff edges
+/// keep a hard edge while gentle slopes shade smoothly.
+fn smooth_normals(vertices: &[Vec4], face_normals: &[Vec4], crease_cos: f32, normals: &mut Vec<[f32; 3]>) {
+    let keys: Vec<IVec3> = vertices.iter()
+        .map(|v| (v.truncate() * WELD_PRECISION).round().as_ivec3())
+        .collect();
+    let mut welded: HashMap<IVec3, Vec<usize>> = HashMap::default();
+    for (i, &key) in keys.iter().enumerate() {
+        welded.entry(key).or_default().push(i);
+    }
+
+    for (i, key) in keys.iter().enumerate() {
+        let face_normal = face_normals[i].truncate();
+        let face_dir = face_normal.normalize_or_zero();
+        // Face normals are not normalized, so larger faces weigh more
+        let normal = welded[key].iter()
+            .map(|&j| face_normals[j].truncate())
+            .filter(|other| other.normalize_or_zero().dot(face_dir) >= crease_cos)
+            .fold(Vec3::ZERO, |sum, other| sum + other);
+        let normal = if normal == Vec3::ZERO { face_normal } else { normal };
+        normals.push(normal.normalize_or_zero().into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn whole_triangles_in_range_are_valid() {
+        let stats = ChunkMeshStats::new(4, 6);
+        assert_eq!(stats.triangles, 2);
+        assert!(stats.validate(&[0, 1, 2, 2, 3, 0]).is_ok());
+        assert!(ChunkMeshStats::default().validate(&[]).is_ok());
+    }
+
+    #[test]
+    fn partial_triangle_is_rejected() {
+        let stats = ChunkMeshStats::new(4, 5);
+        assert!(matches!(stats.validate(&[0, 1, 2, 2, 3]), Err(ChunkMeshError::PartialTriangle(5))));
+    }
+
+    #[test]
+    fn first_index_out_of_range_is_reported() {
+        let stats = ChunkMeshStats::new(3, 6);
+        let err = stats.validate(&[0, 1, 2, 2, 3, 4]).unwrap_err();
+        assert!(matches!(err, ChunkMeshError::IndexOutOfRange { index: 3, at: 4, vertices: 3 }), "{}", err);
+    }
+}
@@ -0,0 +1,493 @@
+use std::{
+    f32::consts::TAU,
+    time::Duration,
+};
+
+use bevy::{
+    ecs::system::SystemParam,
+    math::Vec3Swizzles,
+    prelude::*,
+    prelude::shape::{Cylinder, UVSphere},
+};
+use bevy_rapier3d::prelude::*;
+use rand::{Rng, thread_rng};
+
+use crate::{Config, ConfigState, DamageEvent, GameRng, Health, ImpactEvent, Inventory, Item, ItemPropsLookup, look_quat, MoveMode, PITCH_LIMIT, PlayerController, PlayerInput, PlayerInputFlags, RngStream, SpreadProps};
+
+const HITSCAN_RANGE: f32 = 512.0;
+
+/// Frames a projectile can touch whoever fired it without exploding
+const SHOOTER_GRACE_FRAMES: u32 = 4;
+
+/// Oldest casings are removed past this so sustained fire can not pile up bodies
+const MAX_SHELL_CASINGS: usize = 64;
+/// Fraction at the end of a casing's lifetime spent fading out
+const SHELL_FADE_FRACTION: f32 = 0.25;
+
+/// Purely visual bodies like shell casings, left out of gameplay queries so they never block shots or count as ground
+pub const DEBRIS_GROUP: Group = Group::GROUP_2;
+
+/// Skips debris, use for anything gameplay related like shots or finding the ground
+pub fn gameplay_query_filter<'a>() -> QueryFilter<'a> {
+    QueryFilter::default().groups(CollisionGroups::new(Group::ALL, !DEBRIS_GROUP))
+}
+
+/// Sent each time an item enters its fire state
+#[derive(Event)]
+pub struct FireEvent {
+    pub item_ent: Entity,
+    pub inv_ent: Entity,
+}
+
+/// Sent instead of a fire event when the trigger is pulled on an empty magazine
+#[derive(Event)]
+pub struct DryFireEvent {
+    pub item_ent: Entity,
+    pub inv_ent: Entity,
+}
+
+#[derive(Component, Debug)]
+pub struct ShellCasing {
+    pub age: Duration,
+    pub lifetime: Duration,
+    pub radius: f32,
+    pub length: f32,
+    pub color: Color,
+}
+
+#[derive(Component, Default, Debug)]
+pub struct Recoil {
+    /// Kick in radians of (pitch, yaw) that has not been recovered or compensated for yet
+    pub offset: Vec2,
+    /// Shots since the trigger was last pulled, indexes into the recoil pattern
+    pub shot_index: usize,
+    /// Recovery rate of the gun that last kicked, in radians per second
+    pub recovery_rate: f32,
+    /// Aim after we last touched it, any difference since then is the player's own mouse movement
+    last_aim: Vec2,
+}
+
+/// How far into aiming down sights the player is, zero at the hip and one fully aimed
+#[derive(Component, Default, Debug)]
+pub struct AimDownSights {
+    pub progress: f32,
+}
+
+#[derive(Component, Default, Debug)]
+pub struct Spread {
+    /// Half-angle in radians of the cone the next shot lands in, also what the crosshair should show
+    pub current_spread: f32,
+    hip: SpreadCone,
+    ads: SpreadCone,
+    /// Aim down sights progress the current spread was blended with
+    ads_progress: f32,
+    /// Aiming with a scope, the crosshair should be hidden
+    pub is_scoped: bool,
+}
+
+/// Hipfire and aiming each keep their own cone so they bloom and recover independently
+#[derive(Default, Debug)]
+struct SpreadCone {
+    stance: f32,
+    /// Extra spread from recent shots on top of the stance
+    bloom: f32,
+}
+
+/// A fired body that explodes on contact or when its fuse runs out
+#[derive(Component, Debug)]
+pub struct Projectile {
+    pub shooter: Entity,
+    /// Damage at the center of the blast
+    pub damage: u16,
+    pub blast_radius: f32,
+    pub fuse: Duration,
+    pub explode_on_contact: bool,
+    pub age: Duration,
+    pub frames: u32,
+}
+
+/// What a shot hitting something sends out
+#[derive(SystemParam)]
+pub struct HitEvents<'w> {
+    damage: EventWriter<'w, DamageEvent>,
+    impacts: EventWriter<'w, ImpactEvent>,
+}
+
+// ██╗      ██████╗  ██████╗ ██╗ ██████╗
+// ██║     ██╔═══██╗██╔════╝ ██║██╔════╝
+// ██║     ██║   ██║██║  ███╗██║██║
+// ██║     ██║   ██║██║   ██║██║██║
+// ███████╗╚██████╔╝╚██████╔╝██║╚██████╗
+// ╚══════╝ ╚═════╝  ╚═════╝ ╚═╝ ╚═════╝
+
+pub fn recoil_sys(
+    time: Res<Time>,
+    item_props: ItemPropsLookup,
+    mut rng: ResMut<GameRng>,
+    mut fire_events: EventReader<FireEvent>,
+    item_query: Query<&Item>,
+    mut player_query: Query<(&mut PlayerInput, &mut Recoil)>,
+) {
+    // Aim the player pulled against the kick counts as recovered already,
+    // otherwise manual compensation and recovery add up and we overshoot
+    for (input, mut recoil) in player_query.iter_mut() {
+        let aim_delta = Vec2::new(input.pitch, input.yaw) - recoil.last_aim;
+        recoil.offset = Vec2::new(
+            compensate(recoil.offset.x, aim_delta.x),
+            compensate(recoil.offset.y, aim_delta.y),
+        );
+    }
+
+    let rng = rng.stream(RngStream::Recoil);
+    for fire in fire_events.read() {
+        let Ok((mut input, mut recoil)) = player_query.get_mut(fire.inv_ent) else { continue; };
+        let Ok(item) = item_query.get(fire.item_ent) else { continue; };
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        let recoil_props = &props.recoil;
+
+        // Past the end of the pattern keep repeating the last kick
+        let pattern = &recoil_props.pattern;
+        let Some(&kick) = pattern.get(recoil.shot_index).or(pattern.last()) else { continue; };
+        let jitter = if recoil_props.yaw_jitter > 0.0 {
+            rng.gen_range(-recoil_props.yaw_jitter..=recoil_props.yaw_jitter)
+        } else {
+            0.0
+        };
+        let kick = kick + Vec2::new(0.0, jitter);
+        add_aim(&mut input, kick);
+        recoil.offset += kick;
+        recoil.shot_index += 1;
+        recoil.recovery_rate = recoil_props.recovery_rate;
+    }
+
+    let dt = time.delta_seconds();
+    for (mut input, mut recoil) in player_query.iter_mut() {
+        if !input.flags.contains(PlayerInputFlags::Fire) {
+            recoil.shot_index = 0;
+            let offset_len = recoil.offset.length();
+            if offset_len > 1e-6 {
+                let step = recoil.offset * f32::min(recoil.recovery_rate * dt / offset_len, 1.0);
+                add_aim(&mut input, -step);
+                recoil.offset -= step;
+            }
+        }
+        recoil.last_aim = Vec2::new(input.pitch, input.yaw);
+    }
+}
+
+fn add_aim(input: &mut PlayerInput, delta: Vec2) {
+    input.pitch = (input.pitch + delta.x).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+    input.yaw += delta.y;
+}
+
+/// Shrink outstanding kick by aim movement that opposes it, never past zero
+fn compensate(offset: f32, aim_delta: f32) -> f32 {
+    if offset > 0.0 {
+        (offset + aim_delta.min(0.0)).max(0.0)
+    } else {
+        (offset + aim_delta.max(0.0)).min(0.0)
+    }
+}
+
+pub fn aim_down_sights_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    item_query: Query<&Item>,
+    mut player_query: Query<(&PlayerInput, &Inventory, &mut AimDownSights)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    for (input, inv, mut ads) in player_query.iter_mut() {
+        // Switching weapons or reloading takes us back to the hip
+        let can_aim = inv.is_equipped() && inv.equipped_slot
+            .and_then(|slot| inv.item_ents.get(slot))
+            .and_then(|item_ent| item_query.get(item_ent).ok())
+            .is_some_and(|item| !item.is_reloading());
+        let target = if can_aim && input.flags.contains(PlayerInputFlags::Aim) { 1.0 } else { 0.0 };
+
+        // Move at a fixed rate towards the target so letting go midway reverses smoothly
+        let step = if config.ads_duration > 0.0 { time.delta_seconds() / config.ads_duration } else { 1.0 };
+        ads.progress += (target - ads.progress).clamp(-step, step);
+    }
+}
+
+pub fn spread_sys(
+    time: Res<Time>,
+    config: Res<Assets<Config>>,
+    config_state: Res<ConfigState>,
+    item_props: ItemPropsLookup,
+    item_query: Query<&Item>,
+    mut player_query: Query<(&Inventory, &PlayerController, &Velocity, Option<&AimDownSights>, &mut Spread)>,
+) {
+    let Some(config) = config.get(&config_state.handle) else { return; };
+    let dt = time.delta_seconds();
+    for (inv, controller, vel, ads, mut spread) in player_query.iter_mut() {
+        let props = inv.equipped_slot
+            .and_then(|slot| inv.item_ents.get(slot))
+            .and_then(|item_ent| item_query.get(item_ent).ok())
+            .and_then(|item| item_props.gun_props(&item.name).ok());
+        let Some(props) = props else {
+            *spread = Spread::default();
+            continue;
+        };
+
+        let move_frac = (vel.linvel.xz().length() / config.max_speed).min(1.0);
+        let is_airborne = matches!(controller.move_mode, MoveMode::Ground) && !controller.grounded;
+        spread.hip.settle(&props.hipfire_spread, move_frac, is_airborne, dt);
+        spread.ads.settle(&props.ads_spread, move_frac, is_airborne, dt);
+        spread.ads_progress = ads.map_or(0.0, |ads| ads.progress);
+        spread.is_scoped = props.has_scope && spread.ads_progress > 0.0;
+        spread.blend();
+    }
+}
+
+pub fn hitscan_sys(
+    phys_ctx: Res<RapierContext>,
+    item_props: ItemPropsLookup,
+    mut rng: ResMut<GameRng>,
+    mut fire_events: EventReader<FireEvent>,
+    mut hit_events: HitEvents,
+    item_query: Query<&Item>,
+    mut player_query: Query<(&PlayerInput, &PlayerController, &Transform, &mut Spread)>,
+) {
+    for fire in fire_events.read() {
+        let Ok((input, controller, transform, mut spread)) = player_query.get_mut(fire.inv_ent) else { continue; };
+        let Ok(item) = item_query.get(fire.item_ent) else { continue; };
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        if props.projectile.is_some() { continue; }
+
+        let eye = transform.translation + Vec3::Y * controller.eye_height();
+        let fwd = look_quat(input.pitch, input.yaw) * -Vec3::Z;
+        let dir = spread_direction(fwd, spread.current_spread, rng.stream(RngStream::Spread));
+        let filter = gameplay_query_filter().exclude_collider(fire.inv_ent);
+        if let Some((hit_ent, hit)) = phys_ctx.cast_ray_and_get_normal(eye, dir, HITSCAN_RANGE, true, filter) {
+            hit_events.damage.send(DamageEvent { ent: hit_ent, attacker: Some(fire.inv_ent), amount: props.weapon_props.damage, position: hit.point, headshot_factor: Some(props.weapon_props.headshot_factor) });
+            hit_events.impacts.send(ImpactEvent { ent: hit_ent, position: hit.point, normal: hit.normal });
+        }
+
+        // Bloom only after the shot so the first one from a settled stance is accurate
+        spread.hip.bloom(&props.hipfire_spread);
+        spread.ads.bloom(&props.ads_spread);
+        spread.blend();
+    }
+}
+
+pub fn launch_projectile_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    item_props: ItemPropsLookup,
+    mut fire_events: EventReader<FireEvent>,
+    item_query: Query<&Item>,
+    player_query: Query<(&PlayerInput, &PlayerController, &Transform, &Velocity)>,
+) {
+    for fire in fire_events.read() {
+        let Ok((input, controller, transform, vel)) = player_query.get(fire.inv_ent) else { continue; };
+        let Ok(item) = item_query.get(fire.item_ent) else { continue; };
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        let Some(projectile_props) = &props.projectile else { continue; };
+
+        // Launch from in front of the eyes, carrying our own velocity along
+        let fwd = look_quat(input.pitch, input.yaw) * -Vec3::Z;
+        let origin = transform.translation + Vec3::Y * controller.eye_height() + fwd;
+        commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(Mesh::from(UVSphere { radius: projectile_props.radius, ..default() })),
+                material: materials.add(StandardMaterial {
+                    base_color: Color::DARK_GRAY,
+                    ..default()
+                }),
+                transform: Transform::from_translation(origin),
+                ..default()
+            },
+            RigidBody::Dynamic,
+            Collider::ball(projectile_props.radius),
+            GravityScale(projectile_props.gravity_scale),
+            Velocity::linear(fwd * projectile_props.speed + vel.linvel),
+            Ccd::enabled(),
+            Projectile {
+                shooter: fire.inv_ent,
+                damage: props.weapon_props.damage,
+                blast_radius: projectile_props.blast_radius,
+                fuse: Duration::from_secs_f32(projectile_props.fuse),
+                explode_on_contact: projectile_props.explode_on_contact,
+                age: Duration::ZERO,
+                frames: 0,
+            },
+        ));
+    }
+}
+
+pub fn eject_shell_sys(
+    mut commands: Commands,
+    item_props: ItemPropsLookup,
+    mut fire_events: EventReader<FireEvent>,
+    item_query: Query<(&Item, &GlobalTransform)>,
+    player_query: Query<&Velocity>,
+    children_query: Query<&Children>,
+    node_query: Query<(&Name, &GlobalTransform)>,
+) {
+    // Casings are only for show so they do not need to be deterministic
+    let mut rng = thread_rng();
+    for fire in fire_events.read() {
+        let Ok((item, item_transform)) = item_query.get(fire.item_ent) else { continue; };
+        let Ok(props) = item_props.gun_props(&item.name) else { continue; };
+        let Some(shell_props) = &props.shell_eject else { continue; };
+
+        let port_transform = children_query.iter_descendants(fire.item_ent)
+            .filter_map(|node_ent| node_query.get(node_ent).ok())
+            .find(|(name, _)| shell_props.port_node == name.as_str())
+            .map_or(item_transform, |(_, port_transform)| port_transform);
+        let (_, rotation, translation) = port_transform.to_scale_rotation_translation();
+        let shooter_vel = player_query.get(fire.inv_ent).map_or(Vec3::ZERO, |vel| vel.linvel);
+        let tumble = Vec3::new(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0)) * shell_props.tumble;
+        commands.spawn((
+            TransformBundle::from_transform(Transform::from_translation(translation).with_rotation(rotation)),
+            RigidBody::Dynamic,
+            Collider::cylinder(shell_props.length * 0.5, shell_props.radius),
+            CollisionGroups::new(DEBRIS_GROUP, Group::ALL),
+            Velocity {
+                linvel: shooter_vel + rotation * shell_props.direction.normalize_or_zero() * shell_props.speed,
+                angvel: tumble,
+            },
+            Ccd::enabled(),
+            ShellCasing {
+                age: Duration::ZERO,
+                lifetime: Duration::from_secs_f32(shell_props.lifetime),
+                radius: shell_props.radius,
+                length: shell_props.length,
+                color: shell_props.color,
+            },
+        ));
+    }
+}
+
+/// Each casing gets its own material so it can fade out on its own
+pub fn init_shell_sys(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    shell_query: Query<(Entity, &ShellCasing), Added<ShellCasing>>,
+) {
+    for (shell_ent, shell) in shell_query.iter() {
+        commands.entity(shell_ent).insert((
+            meshes.add(Mesh::from(Cylinder { radius: shell.radius, height: shell.length, ..default() })),
+            materials.add(StandardMaterial {
+                base_color: shell.color,
+                alpha_mode: AlphaMode::Blend,
+                ..default()
+            }),
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+pub fn shell_casing_sys(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut shell_query: Query<(Entity, &mut ShellCasing, Option<&Handle<StandardMaterial>>)>,
+) {
+    let mut alive = Vec::new();
+    for (shell_ent, mut shell, material) in shell_query.iter_mut() {
+        shell.age = shell.age.saturating_add(time.delta());
+        if shell.age >= shell.lifetime {
+            commands.entity(shell_ent).despawn_recursive();
+            continue;
+        }
+        alive.push((shell.age, shell_ent));
+
+        let fade_start = shell.lifetime.mul_f32(1.0 - SHELL_FADE_FRACTION);
+        if shell.age < fade_start { continue; }
+        let Some(material) = material.and_then(|material| materials.get_mut(material)) else { continue; };
+        let fade = (shell.age - fade_start).as_secs_f32() / (shell.lifetime - fade_start).as_secs_f32();
+        material.base_color.set_a(shell.color.a() * (1.0 - fade));
+    }
+
+    if alive.len() > MAX_SHELL_CASINGS {
+        alive.sort_unstable_by(|(age, _), (other_age, _)| other_age.cmp(age));
+        for &(_, shell_ent) in &alive[..alive.len() - MAX_SHELL_CASINGS] {
+            commands.entity(shell_ent).despawn_recursive();
+        }
+    }
+}
+
+pub fn projectile_sys(
+    time: Res<Time>,
+    phys_ctx: Res<RapierContext>,
+    mut commands: Commands,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut projectile_query: Query<(Entity, &Transform, &mut Projectile)>,
+    target_query: Query<(&Collider, &GlobalTransform), With<Health>>,
+) {
+    for (projectile_ent, transform, mut projectile) in projectile_query.iter_mut() {
+        projectile.age = projectile.age.saturating_add(time.delta());
+        projectile.frames = projectile.frames.saturating_add(1);
+
+        // Touching whoever fired it does not count until it has had time to get clear
+        let ignore_shooter = projectile.frames <= SHOOTER_GRACE_FRAMES;
+        let is_touching = projectile.explode_on_contact && phys_ctx.contacts_with(projectile_ent)
+            .filter(|pair| pair.has_any_active_contacts())
+            .map(|pair| if pair.collider1() == projectile_ent { pair.collider2() } else { pair.collider1() })
+            .any(|other_ent| !(ignore_shooter && other_ent == projectile.shooter));
+        let is_fuse_done = projectile.fuse > Duration::ZERO && projectile.age >= projectile.fuse;
+        if !is_touching && !is_fuse_done { continue; }
+
+        let center = transform.translation;
+        let blast = Collider::ball(projectile.blast_radius);
+        phys_ctx.intersections_with_shape(center, Quat::IDENTITY, &blast, QueryFilter::default(), |hit_ent| {
+            let Ok((collider, global_transform)) = target_query.get(hit_ent) else { return true; };
+            // Measure to the closest point so large targets are not favored by where their origin is
+            let (_, rotation, translation) = global_transform.to_scale_rotation_translation();
+            let closest = collider.project_point(translation, rotation, center, true).point;
+            let falloff = 1.0 - (closest.distance(center) / projectile.blast_radius).min(1.0);
+            let amount = (projectile.damage as f32 * falloff).round() as u16;
+            if amount > 0 {
+                damage_events.send(DamageEvent { ent: hit_ent, attacker: Some(projectile.shooter), amount, position: closest, headshot_factor: None });
+            }
+            true
+        });
+        commands.entity(projectile_ent).despawn_recursive();
+    }
+}
+
+impl Spread {
+    /// Going in or out of sights slides between the two cones instead of snapping
+    fn blend(&mut self) {
+        let hip = self.hip.spread();
+        self.current_spread = hip + (self.ads.spread() - hip) * self.ads_progress;
+    }
+}
+
+impl SpreadCone {
+    fn settle(&mut self, props: &SpreadProps, move_frac: f32, is_airborne: bool, dt: f32) {
+        let mut target = props.base + props.moving * move_frac;
+        if is_airborne {
+            target += props.airborne;
+        }
+        // Moving opens the cone right away but settling takes time
+        let settle = props.recovery_rate * dt;
+        self.stance = target.max(self.stance - settle);
+        self.bloom = (self.bloom - settle).max(0.0);
+    }
+
+    fn bloom(&mut self, props: &SpreadProps) {
+        self.bloom = (self.bloom + props.bloom_per_shot).min(props.max_bloom);
+    }
+
+    fn spread(&self) -> f32 {
+        self.stance + self.bloom
+    }
+}
+
+/// Random direction inside a cone around a unit direction, spread evenly over the cone's cap
+pub fn spread_direction(dir: Vec3, half_angle: f32, rng: &mut impl Rng) -> Vec3 {
+    if half_angle <= 0.0 {
+        return dir;
+    }
+    let cos_theta = rng.gen_range(half_angle.cos()..=1.0);
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = rng.gen_range(0.0..TAU);
+    let (u, v) = dir.any_orthonormal_pair();
+    (dir * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta).normalize()
+}